@@ -11,11 +11,24 @@ import com.google.protobuf.Message
 import com.google.protobuf.Parser
 
 private interface Callback {
-    fun run(data: ByteArray?, error: String?)
+    fun progress(data: ByteArray)
+    fun run(data: ByteArray?, error: ByteArray?)
+}
+
+// Failure of a logic call, also thrown by `rustCall` with the serialized response.
+class LogicException(val response: Service.ServiceErrorResponse) :
+    Exception("${response.kindName}|${response.message}") {
+    constructor(data: ByteArray) : this(Service.ServiceErrorResponse.parseFrom(data))
 }
 
 private external fun rustCall(data: ByteArray): ByteArray
-private external fun rustCallAsync(data: ByteArray, callback: Callback)
+private external fun rustCallAsync(data: ByteArray, callback: Callback): Long
+private external fun rustCancel(token: Long): Boolean
+
+// Same as above, but the requests and responses are UTF-8 JSON, while the errors are still the
+// serialized `ServiceErrorResponse`.
+private external fun rustCallJson(data: ByteArray): ByteArray
+private external fun rustCallAsyncJson(data: ByteArray, callback: Callback): Long
 
 fun loadLogic() {
     System.loadLibrary("logic")
@@ -64,19 +77,31 @@ fun getExampleTopics() {
 fun <Response : Message> logicCallAsync(
     request: Service.AsyncRequest,
     responseParser: Parser<Response>,
+    onProgress: ((progress: Service.AsyncProgress) -> Unit)? = null,
     onResponse: (result: Result<Response>) -> Unit
-) {
+): Long {
     val data = request.toByteArray()
     val callback = object : Callback {
-        override fun run(data: ByteArray?, error: String?) {
+        override fun progress(data: ByteArray) {
+            val progress = Service.AsyncProgress.parseFrom(data)
+            onProgress?.let {
+                Handler(Looper.getMainLooper()).post {
+                    it(progress)
+                }
+            }
+        }
+
+
+        override fun run(data: ByteArray?, error: ByteArray?) {
             val result = when {
                 data != null -> {
                     val response = responseParser.parseFrom(data)
                     Result.success(response)
                 }
                 else -> {
-                    Log.e("logic", "logicCallAsync: $error")
-                    Result.failure(Exception(error ?: "???"))
+                    val exception = LogicException(error!!)
+                    Log.e("logic", "logicCallAsync: ${exception.message}")
+                    Result.failure(exception)
                 }
             }
             Handler(Looper.getMainLooper()).post {
@@ -84,10 +109,16 @@ fun <Response : Message> logicCallAsync(
             }
         }
     }
-    rustCallAsync(data, callback)
+    return rustCallAsync(data, callback)
+}
+
+// Cancel the request with the token returned by `logicCallAsync`, whose `onResponse` will still
+// be called with a `CANCELLED` failure.
+fun logicCancel(token: Long): Boolean {
+    return rustCancel(token)
 }
 
-@Throws(Exception::class)
+@Throws(LogicException::class)
 fun <Response : Message> logicCall(
     request: Service.SyncRequest,
     responseParser: Parser<Response>
@@ -97,10 +128,48 @@ fun <Response : Message> logicCall(
     return responseParser.parseFrom(responseData)
 }
 
-@Throws(Exception::class)
+@Throws(LogicException::class)
 fun logicCall(
     request: Service.SyncRequest
 ) {
     val data = request.toByteArray()
     rustCall(data)
 }
+
+@Throws(LogicException::class)
+fun logicCallJson(request: String): String {
+    return String(rustCallJson(request.toByteArray()))
+}
+
+// Same as `logicCallAsync`, but the request, the progress and the response are JSON.
+fun logicCallAsyncJson(
+    request: String,
+    onProgress: ((progress: String) -> Unit)? = null,
+    onResponse: (result: Result<String>) -> Unit
+): Long {
+    val callback = object : Callback {
+        override fun progress(data: ByteArray) {
+            val progress = String(data)
+            onProgress?.let {
+                Handler(Looper.getMainLooper()).post {
+                    it(progress)
+                }
+            }
+        }
+
+        override fun run(data: ByteArray?, error: ByteArray?) {
+            val result = when {
+                data != null -> Result.success(String(data))
+                else -> {
+                    val exception = LogicException(error!!)
+                    Log.e("logic", "logicCallAsyncJson: ${exception.message}")
+                    Result.failure(exception)
+                }
+            }
+            Handler(Looper.getMainLooper()).post {
+                onResponse(result)
+            }
+        }
+    }
+    return rustCallAsyncJson(request.toByteArray(), callback)
+}
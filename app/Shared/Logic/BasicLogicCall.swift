@@ -26,13 +26,23 @@ func logicInitialConfigure() {
 
 struct LogicError: Error, LocalizedError, Equatable {
   let error: String
+  var response = ServiceErrorResponse()
+
+  init(error: String) {
+    self.error = error
+  }
+
+  init(response: ServiceErrorResponse) {
+    error = "\(response.kindName)|\(response.message)"
+    self.response = response
+  }
 
   var errorDescription: String? {
     error
   }
 
   var isXMLParseError: Bool {
-    error.starts(with: "XML Parse")
+    response.kind == .xmlParse
   }
 }
 
@@ -41,7 +51,12 @@ private func extractByteBuffer(_ bb: ByteBuffer) -> (Data?, LogicError?) {
   var resData: Data?
 
   if let err = bb.err {
-    resError = LogicError(error: String(cString: err)) // copied
+    let errData = Data(UnsafeRawBufferPointer(start: err, count: Int(bb.err_len))) // copied
+    if let response = try? ServiceErrorResponse(serializedBytes: errData) {
+      resError = LogicError(response: response)
+    } else {
+      resError = LogicError(error: "Protobuf: deserialize error failed")
+    }
   } else {
     resData = Data(UnsafeRawBufferPointer(start: bb.ptr, count: Int(bb.len))) // copied
   }
@@ -144,7 +159,7 @@ func basicLogicCallAsync<Response: SwiftProtobuf.Message>(
       errorCallback: onError,
     )
     let dataCallbackPtr = Unmanaged.passRetained(dataCallback).toOpaque()
-    let rustCallback = Callback(user_data: dataCallbackPtr, callback: byteBufferCallback)
+    let rustCallback = Callback(user_data: dataCallbackPtr, callback: byteBufferCallback, progress: nil)
 
     reqData.withUnsafeBytes { ptr in
       let ptr = ptr.bindMemory(to: UInt8.self).baseAddress
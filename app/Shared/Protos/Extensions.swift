@@ -303,6 +303,10 @@ extension CacheType {
       "Topic Cache"
     case .notification:
       "Notifications"
+    case .favorite:
+      "Favorites"
+    case .blockList:
+      "Block List"
     default:
       ""
     }
@@ -67,7 +67,7 @@ struct CacheView: View {
       }
 
       Section(header: Text("Data"), footer: Text("Tap an item to clear it.")) {
-        ForEach(CacheType.allCases, id: \.self) { type in
+        ForEach([CacheType.all, .topicHistory, .topicDetails, .notification], id: \.self) { type in
           let action = type == .all ? nil : {
             cacheStatus.removeValue(forKey: type)
             manipulateCache(for: type, operation: .clear)
@@ -106,6 +106,9 @@ struct CacheView: View {
     })) { (response: CacheResponse) in
       if type == .all {
         cacheStatus[type] = ByteCountFormatter().string(fromByteCount: Int64(response.totalSize))
+      } else if let size = response.typeSizes.first(where: { $0.type == type }) {
+        let items = String(format: "%llu items".localized, size.items)
+        cacheStatus[type] = "\(items), \(ByteCountFormatter().string(fromByteCount: Int64(size.size)))"
       } else {
         cacheStatus[type] = String(format: "%llu items".localized, response.items)
       }
@@ -0,0 +1,186 @@
+//! Async variants of the cache methods.
+//!
+//! The storage is accessed on the blocking thread pool of tokio, so that the runtime workers are
+//! never blocked by the cache, no matter whether the runtime is multi-threaded or not.
+
+use std::panic;
+
+use futures::{Stream, stream};
+
+use crate::{Cache, CacheResult};
+
+/// Messages buffered ahead of the consumer when scanning.
+const SCAN_BUFFER_SIZE: usize = 64;
+
+impl Cache {
+    /// Run `f` with the cache on the blocking thread pool. Panics in `f` are propagated.
+    pub async fn run_blocking<T, F>(&'static self, f: F) -> CacheResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&'static Cache) -> CacheResult<T> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(move || f(self)).await {
+            Ok(r) => r,
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn insert_msg_async<M: protos::Message>(
+        &'static self,
+        key: &str,
+        msg: &M,
+    ) -> CacheResult<Option<M>> {
+        let key = key.to_owned();
+        let value = self.encode_msg(msg)?;
+        self.run_blocking(move |cache| cache.insert_encoded(&key, value))
+            .await
+    }
+
+    pub async fn get_msg_async<M: protos::Message>(
+        &'static self,
+        key: &str,
+    ) -> CacheResult<Option<M>> {
+        let key = key.to_owned();
+        self.run_blocking(move |cache| cache.get_msg(&key)).await
+    }
+
+    pub async fn mutate_msg_async<M: protos::Message>(
+        &'static self,
+        key: &str,
+        mutate: impl FnOnce(&mut M) + Send + 'static,
+    ) -> CacheResult<Option<M>> {
+        let key = key.to_owned();
+        self.run_blocking(move |cache| cache.mutate_msg(&key, mutate))
+            .await
+    }
+
+    pub async fn mutate_msg_or_default_async<M: protos::Message>(
+        &'static self,
+        key: &str,
+        mutate: impl FnOnce(&mut M) + Send + 'static,
+    ) -> CacheResult<M> {
+        let key = key.to_owned();
+        self.run_blocking(move |cache| cache.mutate_msg_or_default(&key, mutate))
+            .await
+    }
+
+    pub async fn scan_mutate_msg_async<M: protos::Message>(
+        &'static self,
+        prefix: &str,
+        mutate: impl Fn(&mut M) + Send + 'static,
+    ) -> CacheResult<()> {
+        let prefix = prefix.to_owned();
+        self.run_blocking(move |cache| cache.scan_mutate_msg(&prefix, mutate))
+            .await
+    }
+
+    /// Stream of the messages under `prefix`. The scan is driven by a blocking task which stays
+    /// at most `SCAN_BUFFER_SIZE` messages ahead, and stops once the stream is dropped.
+    pub fn scan_msg_async<M: protos::Message>(
+        &'static self,
+        prefix: &str,
+    ) -> impl Stream<Item = M> + Send + 'static {
+        let (tx, rx) = tokio::sync::mpsc::channel(SCAN_BUFFER_SIZE);
+        let prefix = prefix.to_owned();
+        tokio::task::spawn_blocking(move || {
+            for msg in self.scan_msg::<M>(&prefix) {
+                if tx.blocking_send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        stream::unfold(rx, |mut rx| async move {
+            let msg = rx.recv().await?;
+            Some((msg, rx))
+        })
+    }
+
+    pub async fn remove_prefix_async(&'static self, prefix: &str) -> CacheResult<usize> {
+        let prefix = prefix.to_owned();
+        self.run_blocking(move |cache| cache.remove_prefix(&prefix))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+    use protos::DataModel::Subject;
+
+    use super::*;
+    use crate::storage::MemoryBackend;
+
+    fn subject(content: &str) -> Subject {
+        Subject {
+            content: content.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    // Runs on the current-thread runtime, where `block_in_place` would panic.
+    #[tokio::test]
+    async fn test_async_api() -> CacheResult<()> {
+        let cache: &'static Cache =
+            Box::leak(Box::new(Cache::new(Box::new(MemoryBackend::default()))?));
+
+        for i in 0..(SCAN_BUFFER_SIZE * 2) {
+            cache
+                .insert_msg_async(&format!("/async/{:03}", i), &subject("a"))
+                .await?;
+        }
+        let last = cache.insert_msg_async("/async/000", &subject("b")).await?;
+        assert_eq!(last, Some(subject("a")));
+        assert_eq!(
+            cache.get_msg_async::<Subject>("/async/000").await?,
+            Some(subject("b"))
+        );
+
+        let mutated = cache
+            .mutate_msg_async("/async/001", |s: &mut Subject| {
+                s.set_content("c".to_owned())
+            })
+            .await?;
+        assert_eq!(mutated, Some(subject("c")));
+        assert!(
+            cache
+                .mutate_msg_async("/async/none", |_: &mut Subject| {})
+                .await?
+                .is_none()
+        );
+        let created = cache
+            .mutate_msg_or_default_async("/other/1", |s: &mut Subject| {
+                s.set_content("d".to_owned())
+            })
+            .await?;
+        assert_eq!(created, subject("d"));
+
+        let msgs = cache
+            .scan_msg_async::<Subject>("/async/")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(msgs.len(), SCAN_BUFFER_SIZE * 2);
+        assert_eq!(msgs[0], subject("b"));
+        assert_eq!(msgs[1], subject("c"));
+        // Dropping the stream early stops the scan.
+        let first = cache
+            .scan_msg_async::<Subject>("/async/")
+            .take(1)
+            .count()
+            .await;
+        assert_eq!(first, 1);
+
+        cache
+            .scan_mutate_msg_async("/async/", |s: &mut Subject| s.set_content("e".to_owned()))
+            .await?;
+        assert_eq!(cache.get_msg::<Subject>("/async/002")?, Some(subject("e")));
+
+        assert_eq!(
+            cache.remove_prefix_async("/async/").await?,
+            SCAN_BUFFER_SIZE * 2
+        );
+        assert_eq!(cache.scan_msg_async::<Subject>("/").count().await, 1);
+        Ok(())
+    }
+}
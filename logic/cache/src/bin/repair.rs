@@ -1,31 +1,398 @@
-use std::env;
+//! Inspect and repair the cache db sent by users.
 
-fn open(path: &str, flush: bool) -> sled::Db {
-    sled::Config::new()
-        .path(path)
-        .flush_every_ms(flush.then_some(1000))
-        .cache_capacity(50 * 1024 * 1024)
-        .open()
-        .expect("cannot open or create cache db")
+use std::{collections::BTreeMap, env, fs, path::Path, process::exit};
+
+use cache::{
+    Batch, CacheResult, Tree, encryption, migration,
+    storage::{self, Backend, SledBackend},
+};
+use protos::{
+    DataModel::{BlockWord, Notification, TopicDownloadProgress, TopicSnapshot},
+    Message, ProtobufError,
+    Service::{ClockInResponse, PostVoteResponse, TopicDetailsResponse, TopicFavorResponse},
+};
+use serde_json::{Value, json};
+
+const USAGE: &str = "usage: repair <db> <command> [args...]
+
+The db is opened with SQLite if the path ends with `.sqlite3`, otherwise with sled. Encrypted
+values are not decoded.
+
+commands:
+    prefixes                    list the key prefixes with counts and sizes
+    show <prefix> [limit]       print the decoded values under the prefix
+    verify [--delete]           check that every known record parses, deleting the corrupt ones
+    dump <output> <prefix>...   dump the decoded values under the prefixes into a JSON file
+    copy <output>               copy a sled db with export and import";
+
+type ParseResult = Result<Box<dyn Message>, ProtobufError>;
+
+struct Kind {
+    prefix: &'static str,
+    type_name: &'static str,
+    parse: fn(&[u8]) -> ParseResult,
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
+fn parse<M: Message>(payload: &[u8]) -> ParseResult {
+    Ok(Box::new(M::parse_from_bytes(payload)?))
+}
+
+macro_rules! kinds {
+    ($($prefix:literal => $ty:ident),* $(,)?) => {
+        &[$(Kind {
+            prefix: $prefix,
+            type_name: stringify!($ty),
+            parse: parse::<$ty>,
+        }),*]
+    };
+}
+
+/// Known prefixes of the cache entries, keep in sync with the `*_PREFIX` in `service`.
+const KINDS: &[Kind] = kinds![
+    "/block_word" => BlockWord,
+    "/clock_in" => ClockInResponse,
+    "/favor_response" => TopicFavorResponse,
+    "/noti_v2" => Notification,
+    "/snapshot/topic" => TopicSnapshot,
+    "/topic_details_response/topic" => TopicDetailsResponse,
+    "/topic_download/topic" => TopicDownloadProgress,
+    "/vote_response" => PostVoteResponse,
+];
+
+fn kind_of(key: &[u8]) -> Option<&'static Kind> {
+    KINDS.iter().find(|k| key.starts_with(k.prefix.as_bytes()))
+}
+
+/// Prefix to group the key by, which is the first segment for unknown keys.
+fn group_of(key: &[u8]) -> String {
+    if let Some(kind) = kind_of(key) {
+        return kind.prefix.to_owned();
+    }
+    let key = String::from_utf8_lossy(key);
+    let end = key
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '/')
+        .map_or(key.len(), |(i, _)| i);
+    key[..end].to_owned()
+}
+
+/// Whether the value of a known type is encrypted, which cannot be decoded without the key.
+fn is_encrypted(key: &[u8], value: &[u8]) -> bool {
+    kind_of(key).is_some() && encryption::is_encrypted(value)
+}
+
+/// Decode the value with the type of the key, or `None` if the key is unknown.
+fn decode(key: &[u8], value: &[u8]) -> Option<(u32, ParseResult)> {
+    let kind = kind_of(key)?;
+    let (version, payload) = migration::unwrap(value);
+    Some((version, (kind.parse)(payload)))
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct PrefixStats {
+    items: u64,
+    size: u64,
+}
+
+fn prefix_stats(tree: &Tree) -> CacheResult<BTreeMap<String, PrefixStats>> {
+    let mut stats = BTreeMap::<String, PrefixStats>::new();
+    for r in tree.iter() {
+        let (k, v) = r?;
+        let entry = stats.entry(group_of(&k)).or_default();
+        entry.items += 1;
+        entry.size += (k.len() + v.len()) as u64;
+    }
+    Ok(stats)
+}
+
+#[derive(Debug, Default)]
+struct VerifyReport {
+    checked: u64,
+    unknown: u64,
+    encrypted: u64,
+    corrupt: Vec<(String, ProtobufError)>,
+}
+
+/// Parse every record with a known type, deleting the ones failed to parse if `delete`.
+fn verify(tree: &Tree, delete: bool) -> CacheResult<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut batch = Batch::default();
+    for r in tree.iter() {
+        let (k, v) = r?;
+        if is_encrypted(&k, &v) {
+            report.encrypted += 1;
+            continue;
+        }
+        match decode(&k, &v) {
+            None => report.unknown += 1,
+            Some((_, Ok(_))) => report.checked += 1,
+            Some((_, Err(e))) => {
+                report.checked += 1;
+                report
+                    .corrupt
+                    .push((String::from_utf8_lossy(&k).into_owned(), e));
+                batch.remove(k);
+            }
+        }
+    }
+    if delete {
+        tree.apply_batch(batch)?;
+    }
+    Ok(report)
+}
+
+/// Entries under the prefixes, with the values decoded into JSON if possible.
+fn dump(tree: &Tree, prefixes: &[&str]) -> CacheResult<Value> {
+    let mut entries = vec![];
+    for prefix in prefixes {
+        for r in tree.scan_prefix(prefix) {
+            let (k, v) = r?;
+            let mut entry = json!({
+                "key": String::from_utf8_lossy(&k),
+                "size": v.len(),
+            });
+            if is_encrypted(&k, &v) {
+                entry["encrypted"] = json!(true);
+                entries.push(entry);
+                continue;
+            }
+            match decode(&k, &v) {
+                Some((version, Ok(msg))) => {
+                    entry["schemaVersion"] = json!(version);
+                    entry["type"] = json!(kind_of(&k).map(|k| k.type_name));
+                    entry["value"] = protos::json::to_json(msg.as_ref());
+                }
+                Some((_, Err(e))) => entry["error"] = json!(e.to_string()),
+                None => {}
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(Value::Array(entries))
+}
+
+fn print_prefixes(tree: &Tree) -> CacheResult<()> {
+    println!(
+        "{:<32} {:<24} {:>8} {:>12}",
+        "prefix", "type", "items", "size"
+    );
+    let mut total = PrefixStats::default();
+    for (prefix, stats) in prefix_stats(tree)? {
+        let type_name = kind_of(prefix.as_bytes()).map_or("-", |k| k.type_name);
         println!(
-            "usage: {} <input> <output>",
-            args.first().map_or("repair", |a| a.as_str())
+            "{:<32} {:<24} {:>8} {:>12}",
+            prefix, type_name, stats.items, stats.size
         );
-        return;
+        total.items += stats.items;
+        total.size += stats.size;
     }
+    println!(
+        "{:<32} {:<24} {:>8} {:>12}",
+        "total", "", total.items, total.size
+    );
+    Ok(())
+}
 
-    let old = open(&args[1], false);
-    let new = open(&args[2], true);
+fn print_values(tree: &Tree, prefix: &str, limit: usize) -> CacheResult<()> {
+    for r in tree.scan_prefix(prefix).take(limit) {
+        let (k, v) = r?;
+        let key = String::from_utf8_lossy(&k);
+        if is_encrypted(&k, &v) {
+            println!("{} (encrypted, {} bytes)\n", key, v.len());
+            continue;
+        }
+        match decode(&k, &v) {
+            Some((version, Ok(msg))) => println!("{} (version {})\n{:#?}\n", key, version, msg),
+            Some((_, Err(e))) => println!("{} (corrupt: {})\n", key, e),
+            None => println!("{} (unknown type, {} bytes)\n", key, v.len()),
+        }
+    }
+    Ok(())
+}
 
-    println!("old len: {}", old.len());
-    let data = old.export();
+fn print_verify(tree: &Tree, delete: bool) -> CacheResult<()> {
+    let report = verify(tree, delete)?;
+    for (key, e) in &report.corrupt {
+        println!("corrupt: {}: {}", key, e);
+    }
+    println!(
+        "checked {} records, {} corrupt, {} of unknown type, {} encrypted",
+        report.checked,
+        report.corrupt.len(),
+        report.unknown,
+        report.encrypted
+    );
+    if delete && !report.corrupt.is_empty() {
+        println!("deleted {} corrupt records", report.corrupt.len());
+    }
+    Ok(())
+}
 
-    new.import(data);
-    new.flush().unwrap();
+fn open(path: &Path) -> CacheResult<Box<dyn Backend>> {
+    #[cfg(feature = "sqlite")]
+    if path.extension().is_some_and(|e| e == "sqlite3") {
+        return Ok(Box::new(storage::SqliteBackend::open(path)?));
+    }
+    Ok(Box::new(SledBackend::open(
+        path,
+        config::DEFAULT_SLED_CACHE_CAPACITY,
+        config::DEFAULT_SLED_FLUSH_EVERY_MS,
+    )?))
+}
+
+/// Copy with export and import of sled, which works even if some trees are broken.
+fn copy_sled(input: &Path, output: &Path) -> CacheResult<()> {
+    let open = |path: &Path, flush: bool| {
+        sled::Config::new()
+            .path(path)
+            .flush_every_ms(flush.then_some(1000))
+            .cache_capacity(50 * 1024 * 1024)
+            .open()
+    };
+    let old = open(input, false)?;
+    let new = open(output, true)?;
+
+    println!("old len: {}", old.len());
+    new.import(old.export());
+    new.flush()?;
     println!("new len: {}", new.len());
+    Ok(())
+}
+
+fn run(path: &Path, command: &str, args: &[String]) -> CacheResult<bool> {
+    if command == "copy" {
+        let [output] = args else { return Ok(false) };
+        copy_sled(path, Path::new(output))?;
+        return Ok(true);
+    }
+
+    let backend = open(path)?;
+    let tree = Tree::from(backend.open_tree(storage::DEFAULT_TREE)?);
+    match (command, args) {
+        ("prefixes", []) => print_prefixes(&tree)?,
+        ("show", [prefix]) => print_values(&tree, prefix, usize::MAX)?,
+        ("show", [prefix, limit]) => match limit.parse() {
+            Ok(limit) => print_values(&tree, prefix, limit)?,
+            Err(_) => return Ok(false),
+        },
+        ("verify", []) => print_verify(&tree, false)?,
+        ("verify", [flag]) if flag == "--delete" => {
+            print_verify(&tree, true)?;
+            backend.flush()?;
+        }
+        ("dump", [output, prefixes @ ..]) if !prefixes.is_empty() => {
+            let prefixes = prefixes.iter().map(String::as_str).collect::<Vec<_>>();
+            let value = dump(&tree, &prefixes)?;
+            let count = value.as_array().map_or(0, Vec::len);
+            let text = serde_json::to_string_pretty(&value).unwrap();
+            if let Err(e) = fs::write(output, text) {
+                eprintln!("error: cannot write {}: {}", output, e);
+                exit(1);
+            }
+            println!("dumped {} records into {}", count, output);
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [db, command, args @ ..] = args.as_slice() else {
+        println!("{}", USAGE);
+        return;
+    };
+
+    let path = Path::new(db);
+    if !path.exists() {
+        eprintln!("error: {} does not exist", db);
+        exit(2);
+    }
+    match run(path, command, args) {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cache::storage::MemoryBackend;
+
+    fn tree() -> CacheResult<Tree> {
+        let tree = Tree::from(MemoryBackend::default().open_tree(storage::DEFAULT_TREE)?);
+        let snapshot = TopicSnapshot {
+            timestamp: 42,
+            ..Default::default()
+        };
+        let payload = snapshot.write_to_bytes()?;
+        // Legacy value without the envelope.
+        tree.insert("/snapshot/topic/1", &payload)?;
+        let mut value = vec![0, 0, 0, 0, 1];
+        value.extend_from_slice(&payload);
+        tree.insert("/snapshot/topic/2", value)?;
+        tree.insert("/snapshot/topic/3", b"\xff")?;
+        tree.insert("/noti_v2/user/1/a", Notification::new().write_to_bytes()?)?;
+        tree.insert("/noti_v2/user/1/b", b"\x01encrypted")?;
+        tree.insert("/unknown/1", "x")?;
+        Ok(tree)
+    }
+
+    #[test]
+    fn test_group_of() {
+        assert_eq!(group_of(b"/noti_v2/user/1/a"), "/noti_v2");
+        assert_eq!(
+            group_of(b"/topic_details_response/topic/1/1"),
+            "/topic_details_response/topic"
+        );
+        assert_eq!(group_of(b"/unknown/1"), "/unknown");
+        assert_eq!(group_of(b"/unknown"), "/unknown");
+    }
+
+    #[test]
+    fn test_inspect() -> CacheResult<()> {
+        let tree = tree()?;
+
+        let stats = prefix_stats(&tree)?;
+        assert_eq!(
+            stats.keys().collect::<Vec<_>>(),
+            ["/noti_v2", "/snapshot/topic", "/unknown"]
+        );
+        assert_eq!(stats["/snapshot/topic"].items, 3);
+        assert_eq!(stats["/unknown"], PrefixStats { items: 1, size: 11 });
+
+        let dumped = dump(&tree, &["/snapshot/topic/"])?;
+        let entries = dumped.as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["schemaVersion"], 0);
+        assert_eq!(entries[1]["schemaVersion"], 1);
+        assert_eq!(entries[1]["type"], "TopicSnapshot");
+        assert_eq!(entries[1]["value"]["timestamp"], "42");
+        assert!(entries[2]["error"].is_string());
+        let dumped = dump(&tree, &["/noti_v2/"])?;
+        assert_eq!(dumped[1]["encrypted"], true);
+        assert!(dumped[1]["value"].is_null());
+
+        let report = verify(&tree, false)?;
+        assert_eq!(
+            (report.checked, report.unknown, report.encrypted),
+            (4, 1, 1)
+        );
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].0, "/snapshot/topic/3");
+        assert!(tree.contains_key("/snapshot/topic/3")?);
+
+        verify(&tree, true)?;
+        assert!(!tree.contains_key("/snapshot/topic/3")?);
+        assert!(verify(&tree, false)?.corrupt.is_empty());
+        Ok(())
+    }
 }
@@ -0,0 +1,435 @@
+//! At-rest encryption of the cache entries under some prefixes, like the notifications and the
+//! auth tokens of the accounts.
+//!
+//! Encrypted values are stored as `[ENCRYPTED_MAGIC, nonce (12 bytes), ciphertext...]` with
+//! ChaCha20-Poly1305. Like the envelope in `migration`, the magic is never the first byte of a
+//! protobuf message, so the entries written before enabling the encryption are still readable.
+//! Trees opened through `Cache` encrypt and decrypt the entries transparently.
+
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+};
+
+use chacha20poly1305::{
+    AeadCore, ChaCha20Poly1305, KeyInit, Nonce,
+    aead::{Aead, OsRng},
+};
+
+use crate::{
+    Batch, Cache, CacheError, CacheResult, Tree,
+    eviction::ACCESS_TIME_TREE,
+    migration::META_TREE,
+    storage::{KvIter, Storage},
+};
+
+pub const KEY_LEN: usize = 32;
+const ENCRYPTED_MAGIC: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// Key in the meta tree of a known value encrypted with the key, to detect a wrong key.
+const KEY_CHECK_KEY: &str = "encryption_check";
+const KEY_CHECK_VALUE: &[u8] = b"mnga";
+
+#[derive(Debug, Default, Clone)]
+pub struct EncryptionPolicy {
+    /// Key of `KEY_LEN` bytes, or `None` to store the entries in plaintext.
+    pub key: Option<Vec<u8>>,
+    /// Prefixes of the keys to encrypt, in any tree of the cache.
+    pub prefixes: Vec<String>,
+}
+
+pub fn is_encrypted(value: &[u8]) -> bool {
+    value.first() == Some(&ENCRYPTED_MAGIC)
+}
+
+fn cipher_of(key: &[u8]) -> CacheResult<ChaCha20Poly1305> {
+    ChaCha20Poly1305::new_from_slice(key).map_err(|_| CacheError::InvalidKey)
+}
+
+fn encrypt(cipher: &ChaCha20Poly1305, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("plaintext too long to encrypt");
+
+    let mut value = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+    value.push(ENCRYPTED_MAGIC);
+    value.extend_from_slice(&nonce);
+    value.extend_from_slice(&ciphertext);
+    value
+}
+
+fn decrypt(cipher: &ChaCha20Poly1305, value: &[u8]) -> Option<Vec<u8>> {
+    let [ENCRYPTED_MAGIC, rest @ ..] = value else {
+        return None;
+    };
+    if rest.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+}
+
+#[derive(Default)]
+pub(crate) struct Encryption {
+    cipher: Option<ChaCha20Poly1305>,
+    prefixes: Vec<String>,
+    /// Set if the cache is encrypted with another key, where the entries under the prefixes cannot
+    /// be written without the cipher.
+    locked: bool,
+}
+
+impl Encryption {
+    fn covers(&self, key: &[u8]) -> bool {
+        (self.prefixes.iter()).any(|p| key.starts_with(p.as_bytes()))
+    }
+
+    fn seal<'a>(&self, key: &[u8], value: &'a [u8]) -> CacheResult<Cow<'a, [u8]>> {
+        if !self.covers(key) {
+            return Ok(Cow::Borrowed(value));
+        }
+        match &self.cipher {
+            Some(cipher) => Ok(Cow::Owned(encrypt(cipher, value))),
+            None if self.locked => Err(CacheError::WrongKey),
+            None => Ok(Cow::Borrowed(value)),
+        }
+    }
+
+    fn open(&self, key: &[u8], value: Vec<u8>) -> CacheResult<Vec<u8>> {
+        if !is_encrypted(&value) || !self.covers(key) {
+            return Ok(value);
+        }
+        (self.cipher.as_ref())
+            .and_then(|cipher| decrypt(cipher, &value))
+            .ok_or_else(|| CacheError::Decrypt(String::from_utf8_lossy(key).into_owned()))
+    }
+}
+
+pub(crate) type SharedEncryption = Arc<RwLock<Encryption>>;
+
+/// Storage that encrypts the entries covered by the shared encryption of the cache.
+pub(crate) struct EncryptedStorage {
+    inner: Arc<dyn Storage>,
+    encryption: SharedEncryption,
+}
+
+impl EncryptedStorage {
+    pub(crate) fn wrap(inner: Arc<dyn Storage>, encryption: SharedEncryption) -> Tree {
+        let storage: Arc<dyn Storage> = Arc::new(Self { inner, encryption });
+        storage.into()
+    }
+
+    fn open_last(&self, key: &[u8], last: Option<Vec<u8>>) -> CacheResult<Option<Vec<u8>>> {
+        let encryption = self.encryption.read().unwrap();
+        last.map(|v| encryption.open(key, v)).transpose()
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn get(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let value = self.inner.get(key)?;
+        self.open_last(key, value)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let value = self
+            .encryption
+            .read()
+            .unwrap()
+            .seal(key, value)?
+            .into_owned();
+        let last = self.inner.insert(key, &value)?;
+        self.open_last(key, last)
+    }
+
+    fn remove(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let last = self.inner.remove(key)?;
+        self.open_last(key, last)
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        Box::new(self.inner.scan_prefix(prefix).map(|r| {
+            let (k, v) = r?;
+            let v = self.encryption.read().unwrap().open(&k, v)?;
+            Ok((k, v))
+        }))
+    }
+
+    fn apply_batch(&self, batch: Batch) -> CacheResult<()> {
+        let mut sealed = Batch::default();
+        {
+            let encryption = self.encryption.read().unwrap();
+            for (key, value) in batch.into_ops() {
+                match value {
+                    Some(value) => sealed.insert(&key, encryption.seal(&key, &value)?),
+                    None => sealed.remove(key),
+                }
+            }
+        }
+        self.inner.apply_batch(sealed)
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.inner.clear()
+    }
+}
+
+impl Cache {
+    /// Whether the entries under `prefix` may be encrypted with the current key.
+    pub fn is_encrypted_prefix(&self, prefix: &str) -> bool {
+        let encryption = self.encryption.read().unwrap();
+        encryption.cipher.is_some()
+            && (encryption.prefixes.iter())
+                .any(|p| p.starts_with(prefix) || prefix.starts_with(p.as_str()))
+    }
+
+    /// Trees of the backend without encryption, except the internal ones of the cache.
+    fn raw_trees(&self) -> CacheResult<Vec<Tree>> {
+        (self.backend.tree_names()?.iter())
+            .filter(|name| ![ACCESS_TIME_TREE, META_TREE].contains(&name.as_str()))
+            .map(|name| Ok(self.backend.open_tree(name)?.into()))
+            .collect()
+    }
+
+    fn check_key(&self, cipher: Option<&ChaCha20Poly1305>) -> CacheResult<()> {
+        match (self.meta.get(KEY_CHECK_KEY)?, cipher) {
+            (None, _) => Ok(()),
+            (Some(check), Some(cipher))
+                if decrypt(cipher, &check).is_some_and(|v| v == KEY_CHECK_VALUE) =>
+            {
+                Ok(())
+            }
+            (Some(_), _) => Err(CacheError::WrongKey),
+        }
+    }
+
+    fn save_key_check(&self, cipher: Option<&ChaCha20Poly1305>) -> CacheResult<()> {
+        match cipher {
+            Some(cipher) => self
+                .meta
+                .insert(KEY_CHECK_KEY, encrypt(cipher, KEY_CHECK_VALUE))?,
+            None => self.meta.remove(KEY_CHECK_KEY)?,
+        };
+        Ok(())
+    }
+
+    /// Encrypt the entries under `prefixes` in all trees with `new`, or decrypt them if `None`.
+    /// Entries already encrypted are decrypted with `old` first, or left as is unless `rekey`.
+    /// Nothing is written if any entry fails to decrypt.
+    fn reseal(
+        &self,
+        old: Option<&ChaCha20Poly1305>,
+        new: Option<&ChaCha20Poly1305>,
+        prefixes: &[String],
+        rekey: bool,
+    ) -> CacheResult<usize> {
+        let mut batches = vec![];
+        let mut count = 0;
+        for tree in self.raw_trees()? {
+            let mut batch = Batch::default();
+            for prefix in prefixes {
+                for r in tree.scan_prefix(prefix) {
+                    let (k, v) = r?;
+                    let plaintext = match (is_encrypted(&v), old) {
+                        (false, _) if new.is_none() => continue,
+                        (false, _) => v,
+                        (true, _) if !rekey => continue,
+                        (true, Some(old)) => decrypt(old, &v).ok_or_else(|| {
+                            CacheError::Decrypt(String::from_utf8_lossy(&k).into_owned())
+                        })?,
+                        (true, None) => return Err(CacheError::WrongKey),
+                    };
+                    let value = match new {
+                        Some(new) => encrypt(new, &plaintext),
+                        None => plaintext,
+                    };
+                    batch.insert(k, value);
+                    count += 1;
+                }
+            }
+            batches.push((tree, batch));
+        }
+
+        for (tree, batch) in batches {
+            tree.apply_batch(batch)?;
+        }
+        self.flush()?;
+        Ok(count)
+    }
+
+    /// Set the key and the prefixes to encrypt, then encrypt the existing plaintext entries
+    /// under the prefixes. Returns the count of the entries encrypted.
+    ///
+    /// Fails with `CacheError::WrongKey` if the cache has been encrypted with another key, where
+    /// `rekey` with the old key or `reset_encryption` is required. Until then, the prefixes are
+    /// still covered, so that the entries under them are never written in plaintext.
+    pub fn set_encryption(&self, policy: EncryptionPolicy) -> CacheResult<usize> {
+        let cipher = policy.key.as_deref().map(cipher_of).transpose()?;
+        let mut encryption = self.encryption.write().unwrap();
+        if let Err(e) = self.check_key(cipher.as_ref()) {
+            encryption.locked = encryption.cipher.is_none();
+            encryption.prefixes = policy.prefixes;
+            return Err(e);
+        }
+
+        let count = self.reseal(cipher.as_ref(), cipher.as_ref(), &policy.prefixes, false)?;
+        if cipher.is_some() && self.meta.get(KEY_CHECK_KEY)?.is_none() {
+            self.save_key_check(cipher.as_ref())?;
+        }
+        *encryption = Encryption {
+            cipher,
+            prefixes: policy.prefixes,
+            locked: false,
+        };
+        Ok(count)
+    }
+
+    /// Encrypt the entries with the new key, or decrypt them if `None`. Returns the count of the
+    /// entries rewritten.
+    pub fn rekey(&self, new_key: Option<&[u8]>) -> CacheResult<usize> {
+        let new = new_key.map(cipher_of).transpose()?;
+        let mut encryption = self.encryption.write().unwrap();
+
+        let count = self.reseal(
+            encryption.cipher.as_ref(),
+            new.as_ref(),
+            &encryption.prefixes,
+            true,
+        )?;
+        self.save_key_check(new.as_ref())?;
+        encryption.cipher = new;
+        encryption.locked = false;
+        Ok(count)
+    }
+
+    /// Discard the encrypted entries under the prefixes, which cannot be read without the lost
+    /// key, then set the policy. Returns the count of the entries discarded.
+    pub fn reset_encryption(&self, policy: EncryptionPolicy) -> CacheResult<usize> {
+        let mut count = 0;
+        {
+            let _encryption = self.encryption.write().unwrap();
+            for tree in self.raw_trees()? {
+                let mut batch = Batch::default();
+                for prefix in &policy.prefixes {
+                    for r in tree.scan_prefix(prefix) {
+                        let (k, v) = r?;
+                        if is_encrypted(&v) {
+                            batch.remove(k);
+                            count += 1;
+                        }
+                    }
+                }
+                tree.apply_batch(batch)?;
+            }
+            self.save_key_check(None)?;
+        }
+
+        let _ = self.set_encryption(policy)?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use protos::DataModel::Subject;
+
+    use super::*;
+    use crate::{
+        EvictionPolicy, EvictionRule,
+        storage::{DEFAULT_TREE, MemoryBackend},
+    };
+
+    fn subject(content: &str) -> Subject {
+        Subject {
+            content: content.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn policy(key: Option<u8>) -> EncryptionPolicy {
+        EncryptionPolicy {
+            key: key.map(|k| vec![k; KEY_LEN]),
+            prefixes: vec!["/secret".to_owned(), "account/".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_encryption() -> CacheResult<()> {
+        let cache = Cache::new(Box::new(MemoryBackend::default()))?;
+        let accounts = cache.open_tree("accounts")?;
+        let raw_db = Tree::from(cache.backend.open_tree(DEFAULT_TREE)?);
+        let raw_accounts = Tree::from(cache.backend.open_tree("accounts")?);
+        let raw = |tree: &Tree, key: &str| tree.get(key).map(Option::unwrap);
+
+        cache.set_eviction_policy(EvictionPolicy {
+            rules: vec![EvictionRule {
+                prefix: "/secret".to_owned(),
+                ttl: None,
+            }],
+            size_budget: 0,
+        });
+        cache.insert_msg("/secret/1", &subject("a"))?;
+        cache.insert_msg("/public/1", &subject("b"))?;
+        accounts.insert("account/1", "token")?;
+        assert!(matches!(
+            cache.set_encryption(EncryptionPolicy {
+                key: Some(vec![0; 16]),
+                ..policy(None)
+            }),
+            Err(CacheError::InvalidKey)
+        ));
+
+        // Existing entries are encrypted when enabled.
+        assert_eq!(cache.set_encryption(policy(Some(1)))?, 2);
+        cache.insert_msg("/secret/2", &subject("c"))?;
+        assert!(is_encrypted(&raw(&raw_db, "/secret/1")?));
+        assert!(is_encrypted(&raw(&raw_db, "/secret/2")?));
+        assert!(!is_encrypted(&raw(&raw_db, "/public/1")?));
+        assert!(is_encrypted(&raw(&raw_accounts, "account/1")?));
+        // Internal trees are never encrypted.
+        assert_eq!(raw(&cache.access, "/secret/1")?.len(), 8);
+        assert_eq!(cache.get_msg("/secret/1")?, Some(subject("a")));
+        assert_eq!(cache.scan_msg::<Subject>("/secret/").count(), 2);
+        assert_eq!(accounts.get("account/1")?, Some(b"token".to_vec()));
+        assert_eq!(cache.set_encryption(policy(Some(1)))?, 0);
+        assert!(matches!(
+            cache.set_encryption(policy(Some(2))),
+            Err(CacheError::WrongKey)
+        ));
+        assert!(matches!(
+            cache.set_encryption(policy(None)),
+            Err(CacheError::WrongKey)
+        ));
+
+        assert_eq!(cache.rekey(Some(&[2; KEY_LEN]))?, 3);
+        let old = cipher_of(&[1; KEY_LEN])?;
+        assert!(decrypt(&old, &raw(&raw_db, "/secret/1")?).is_none());
+        assert_eq!(cache.get_msg("/secret/2")?, Some(subject("c")));
+        assert!(cache.set_encryption(policy(Some(1))).is_err());
+        cache.set_encryption(policy(Some(2)))?;
+
+        assert_eq!(cache.rekey(None)?, 3);
+        assert_eq!(raw(&raw_accounts, "account/1")?, b"token");
+        cache.set_encryption(policy(None))?;
+
+        // The key is lost, where the entries are kept but not written in plaintext.
+        cache.set_encryption(policy(Some(3)))?;
+        // As if relaunched.
+        *cache.encryption.write().unwrap() = Encryption::default();
+        assert!(cache.set_encryption(policy(Some(4))).is_err());
+        assert!(matches!(
+            cache.insert_msg("/secret/4", &subject("e")),
+            Err(CacheError::WrongKey)
+        ));
+        assert!(accounts.insert("account/2", "token").is_err());
+        assert!(is_encrypted(&raw(&raw_db, "/secret/1")?));
+        cache.insert_msg("/public/2", &subject("f"))?;
+        assert_eq!(cache.reset_encryption(policy(Some(4)))?, 3);
+        assert_eq!(cache.get_msg::<Subject>("/secret/1")?, None);
+        assert_eq!(cache.get_msg("/public/1")?, Some(subject("b")));
+        cache.insert_msg("/secret/3", &subject("d"))?;
+        assert_eq!(cache.get_msg("/secret/3")?, Some(subject("d")));
+        Ok(())
+    }
+}
@@ -7,6 +7,19 @@ pub enum CacheError {
     Db(#[from] sled::Error),
     #[error(transparent)]
     Protobuf(#[from] protos::ProtobufError),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error("encryption key must be 32 bytes")]
+    InvalidKey,
+    #[error("cache is encrypted with another key")]
+    WrongKey,
+    #[error("failed to decrypt {0}")]
+    Decrypt(String),
+    #[error("storage backend {0} is not supported in this build")]
+    UnsupportedBackend(&'static str),
 }
 
 pub type CacheResult<T> = Result<T, CacheError>;
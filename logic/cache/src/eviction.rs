@@ -0,0 +1,273 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Batch, Cache, CacheResult};
+
+/// Tree of the last access time of the evictable entries, in milliseconds since the epoch.
+pub(crate) const ACCESS_TIME_TREE: &str = "access_time";
+
+/// Eviction rule of the entries under `prefix`.
+#[derive(Clone, Debug)]
+pub struct EvictionRule {
+    pub prefix: String,
+    /// Entries not accessed for this long are expired. Never expire if `None`.
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EvictionPolicy {
+    /// Rules with non-overlapping prefixes. Entries not under any of them are never evicted.
+    pub rules: Vec<EvictionRule>,
+    /// Budget of the total size of the evictable entries in bytes, beyond which the least recently
+    /// used ones are evicted. Unlimited if 0.
+    pub size_budget: u64,
+}
+
+impl EvictionPolicy {
+    fn is_evictable(&self, key: &str) -> bool {
+        self.rules.iter().any(|r| key.starts_with(&r.prefix))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub expired: usize,
+    pub evicted: usize,
+    /// Total size of the remaining evictable entries in bytes.
+    pub remaining_size: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl Cache {
+    pub fn set_eviction_policy(&self, policy: EvictionPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// Record the access of the entry if it's evictable.
+    pub(crate) fn touch(&self, key: &str) {
+        if !self.policy.read().unwrap().is_evictable(key) {
+            return;
+        }
+        if let Err(e) = self.access.insert(key, now_millis().to_be_bytes()) {
+            log::warn!("failed to record access time of {}: {}", key, e);
+        }
+    }
+
+    /// Remove the expired entries, then evict the least recently used ones until the size budget
+    /// is met. This scans all evictable entries, so it should be run in the background.
+    pub fn compact(&self) -> CacheResult<CompactionStats> {
+        self.compact_at(now_millis())
+    }
+
+    fn compact_at(&self, now: u64) -> CacheResult<CompactionStats> {
+        let plan = self.plan_compaction(now)?;
+        self.apply_compaction(plan)
+    }
+
+    fn plan_compaction(&self, now: u64) -> CacheResult<CompactionPlan> {
+        let policy = self.policy.read().unwrap().clone();
+        let mut removals = Vec::new();
+        let mut access_batch = Batch::default();
+        // (access time, size, key, raw access time) of the entries kept so far.
+        let mut entries = Vec::new();
+
+        for rule in &policy.rules {
+            for r in self.db.scan_prefix(&rule.prefix) {
+                let (key, value) = r?;
+                let seen = self.access.get(&key)?;
+                let access_time = match &seen {
+                    Some(t) => u64::from_be_bytes(t.as_slice().try_into().unwrap_or_default()),
+                    None => {
+                        // Entries from before the tracking are treated as just accessed.
+                        access_batch.insert(key.clone(), now.to_be_bytes());
+                        now
+                    }
+                };
+
+                let size = (key.len() + value.len()) as u64;
+                let expired = rule
+                    .ttl
+                    .is_some_and(|ttl| now.saturating_sub(access_time) > ttl.as_millis() as u64);
+                if expired {
+                    removals.push(Removal {
+                        key,
+                        seen,
+                        size,
+                        expired,
+                    });
+                } else {
+                    entries.push((access_time, size, key, seen));
+                }
+            }
+        }
+
+        let mut remaining_size = entries.iter().map(|e| e.1).sum::<u64>();
+        if policy.size_budget > 0 && remaining_size > policy.size_budget {
+            entries.sort_unstable_by_key(|e| e.0);
+            for (_, size, key, seen) in entries {
+                if remaining_size <= policy.size_budget {
+                    break;
+                }
+                removals.push(Removal {
+                    key,
+                    seen,
+                    size,
+                    expired: false,
+                });
+                remaining_size -= size;
+            }
+        }
+
+        // Access time of the entries removed in other ways.
+        for r in self.access.iter() {
+            let (key, _) = r?;
+            if !self.db.contains_key(&key)? {
+                access_batch.remove(key);
+            }
+        }
+
+        Ok(CompactionPlan {
+            removals,
+            access_batch,
+            remaining_size,
+        })
+    }
+
+    fn apply_compaction(&self, plan: CompactionPlan) -> CacheResult<CompactionStats> {
+        let CompactionPlan {
+            removals,
+            mut access_batch,
+            remaining_size,
+        } = plan;
+        let mut stats = CompactionStats {
+            remaining_size,
+            ..Default::default()
+        };
+        let mut batch = Batch::default();
+
+        for removal in removals {
+            // Entries accessed or inserted again since planned have a new access time.
+            if self.access.get(&removal.key)? != removal.seen {
+                stats.remaining_size += removal.size;
+                continue;
+            }
+            if removal.expired {
+                stats.expired += 1;
+            } else {
+                stats.evicted += 1;
+            }
+            batch.remove(removal.key.clone());
+            access_batch.remove(removal.key);
+        }
+
+        self.db.apply_batch(batch)?;
+        self.access.apply_batch(access_batch)?;
+        log::info!("compacted cache: {:?}", stats);
+        Ok(stats)
+    }
+}
+
+/// Entry to remove in compaction, with the raw access time when planned.
+struct Removal {
+    key: Vec<u8>,
+    seen: Option<Vec<u8>>,
+    size: u64,
+    expired: bool,
+}
+
+struct CompactionPlan {
+    removals: Vec<Removal>,
+    access_batch: Batch,
+    remaining_size: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryBackend;
+    use protos::DataModel::Subject;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    fn subject(content: &str) -> Subject {
+        Subject {
+            content: content.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compact() -> CacheResult<()> {
+        let cache = Cache::new(Box::new(MemoryBackend::default()))?;
+        cache.set_eviction_policy(EvictionPolicy {
+            rules: vec![
+                EvictionRule {
+                    prefix: "/ttl/".to_owned(),
+                    ttl: Some(Duration::from_millis(DAY)),
+                },
+                EvictionRule {
+                    prefix: "/lru/".to_owned(),
+                    ttl: None,
+                },
+            ],
+            size_budget: 0,
+        });
+
+        cache.insert_msg("/ttl/a", &subject("a"))?;
+        cache.insert_msg("/lru/b", &subject("b"))?;
+        cache.insert_msg("/lru/c", &subject("c"))?;
+        cache.insert_msg("/pinned/d", &subject("d"))?;
+        assert_eq!(cache.access.len(), 3);
+
+        let now = now_millis();
+        let stats = cache.compact_at(now)?;
+        assert_eq!((stats.expired, stats.evicted), (0, 0));
+        let entry_size = stats.remaining_size / 3;
+
+        // Accessing `b` makes `c` the least recently used one.
+        cache.access.insert("/lru/c", (now - 1).to_be_bytes())?;
+        cache.get_msg::<Subject>("/lru/b")?;
+
+        let policy = cache.policy.read().unwrap().clone();
+        cache.set_eviction_policy(EvictionPolicy {
+            size_budget: entry_size,
+            ..policy
+        });
+        let stats = cache.compact_at(now + 2 * DAY)?;
+        assert_eq!(
+            stats,
+            CompactionStats {
+                expired: 1,
+                evicted: 1,
+                remaining_size: entry_size,
+            }
+        );
+        assert!(cache.get_msg::<Subject>("/ttl/a")?.is_none());
+        assert!(cache.get_msg::<Subject>("/lru/b")?.is_some());
+        assert!(cache.get_msg::<Subject>("/lru/c")?.is_none());
+        assert!(cache.get_msg::<Subject>("/pinned/d")?.is_some());
+
+        // Entries accessed while compacting are kept.
+        cache.insert_msg("/lru/e", &subject("e"))?;
+        cache.insert_msg("/lru/f", &subject("f"))?;
+        cache.access.insert("/lru/e", (now - 2).to_be_bytes())?;
+        cache.access.insert("/lru/f", (now - 1).to_be_bytes())?;
+        let plan = cache.plan_compaction(now)?;
+        assert_eq!(plan.removals.len(), 2);
+        cache.insert_msg("/lru/e", &subject("e"))?;
+        let stats = cache.apply_compaction(plan)?;
+        assert_eq!((stats.evicted, stats.remaining_size), (1, 2 * entry_size));
+        assert!(cache.get_msg::<Subject>("/lru/e")?.is_some());
+        assert!(cache.get_msg::<Subject>("/lru/f")?.is_none());
+
+        cache.remove_prefix("/lru/")?;
+        assert!(cache.access.is_empty());
+
+        Ok(())
+    }
+}
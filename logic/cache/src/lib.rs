@@ -1,46 +1,51 @@
+mod async_api;
+pub mod encryption;
 pub mod error;
+pub mod eviction;
+pub mod migration;
+pub mod storage;
 
 use lazy_static::lazy_static;
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    sync::{RwLock, atomic::AtomicU32},
+};
 
+use encryption::EncryptedStorage;
+pub use encryption::EncryptionPolicy;
 pub use error::{CacheError, CacheResult};
+pub use eviction::{CompactionStats, EvictionPolicy, EvictionRule};
+pub use migration::{Migration, MigrationStats};
+pub use storage::{Backend, Batch, Storage, Tree};
 
 lazy_static! {
     pub static ref CACHE: Cache = {
-        let (db, is_test) = match config::CONF.get().map(|c| &c.cache_path) {
-            Some(path) => {
-                log::debug!("open db at {:?}", path);
-                let db = sled::Config::new()
-                    .path(path)
-                    .flush_every_ms(Some(3000))
-                    .cache_capacity(20 * 1024 * 1024)
-                    .open()
-                    .expect("cannot open or create cache db");
-                (db, false)
-            }
+        let backend = match config::CONF.get() {
+            Some(conf) => storage::open(conf).expect("cannot open or create cache db"),
             None => {
-                log::warn!(
-                    "no cache path conf provided, use temporary location and treat it as a test environment"
-                );
-                let db = sled::Config::new()
-                    .temporary(true)
-                    .open()
-                    .expect("cannot open or create temporary cache db");
-                (db, true)
+                log::warn!("no cache path conf provided, use memory backend");
+                Box::new(storage::MemoryBackend::default())
             }
         };
 
-        Cache::new(db, is_test)
+        Cache::new(backend).expect("cannot initialize cache")
     };
 }
 
 pub struct Cache {
-    db: sled::Db,
-    is_test: bool,
+    backend: Box<dyn Backend>,
+    encryption: encryption::SharedEncryption,
+    db: Tree,
+    access: Tree,
+    policy: RwLock<EvictionPolicy>,
+    meta: Tree,
+    /// Schema version of the values written, see `migration`.
+    schema_version: AtomicU32,
 }
 
+/// The tree of the cache entries.
 impl Deref for Cache {
-    type Target = sled::Db;
+    type Target = Tree;
 
     fn deref(&self) -> &Self::Target {
         &self.db
@@ -48,31 +53,69 @@ impl Deref for Cache {
 }
 
 impl Cache {
-    fn new(db: sled::Db, is_test: bool) -> Self {
-        Self { db, is_test }
+    fn new(backend: Box<dyn Backend>) -> CacheResult<Self> {
+        let encryption = encryption::SharedEncryption::default();
+        let db = EncryptedStorage::wrap(
+            backend.open_tree(storage::DEFAULT_TREE)?,
+            encryption.clone(),
+        );
+        let access = backend.open_tree(eviction::ACCESS_TIME_TREE)?.into();
+        let meta = backend.open_tree(migration::META_TREE)?.into();
+        let schema_version = migration::load_schema_version(&meta)?;
+        Ok(Self {
+            backend,
+            encryption,
+            db,
+            access,
+            policy: Default::default(),
+            meta,
+            schema_version: AtomicU32::new(schema_version),
+        })
+    }
+
+    /// Open the tree, where the entries are encrypted as configured by `set_encryption`.
+    pub fn open_tree(&self, name: &str) -> CacheResult<Tree> {
+        let tree = self.backend.open_tree(name)?;
+        Ok(EncryptedStorage::wrap(tree, self.encryption.clone()))
+    }
+
+    pub fn flush(&self) -> CacheResult<()> {
+        self.backend.flush()
+    }
+
+    fn encode_msg<M: protos::Message>(&self, msg: &M) -> CacheResult<Vec<u8>> {
+        let payload = msg.write_to_bytes()?;
+        Ok(migration::wrap(self.schema_version(), &payload))
+    }
+
+    /// Parse the value, logging the failure as it may be an entry not migrated properly.
+    fn decode_msg<M: protos::Message>(key: &[u8], value: &[u8]) -> Option<M> {
+        let (version, payload) = migration::unwrap(value);
+        M::parse_from_bytes(payload)
+            .inspect_err(|e| {
+                log::warn!(
+                    "failed to parse {} in version {}: {}",
+                    String::from_utf8_lossy(key),
+                    version,
+                    e
+                )
+            })
+            .ok()
     }
 
-    fn do_insert_msg<M: protos::Message>(&self, key: &str, msg: &M) -> CacheResult<Option<M>> {
+    fn insert_encoded<M: protos::Message>(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+    ) -> CacheResult<Option<M>> {
         log::info!("insert: key={}", key);
         let key_bytes = key.as_bytes();
-        let value = msg.write_to_bytes()?;
         let last = self.db.insert(key_bytes, value)?;
-        let last_msg = last.and_then(|ivec| M::parse_from_bytes(&ivec).ok());
+        self.touch(key);
+        let last_msg = last.and_then(|v| Self::decode_msg(key_bytes, &v));
         Ok(last_msg)
     }
 
-    fn do_get_msg<M: protos::Message>(&self, key: &str) -> CacheResult<Option<M>> {
-        let key_bytes = key.as_bytes();
-        let value = self.db.get(key_bytes)?;
-        let value_msg = value.and_then(|ivec| M::parse_from_bytes(&ivec).ok());
-        log::debug!(
-            "get: key={}, msg={}",
-            key,
-            if value_msg.is_some() { "Some" } else { "None" }
-        );
-        Ok(value_msg)
-    }
-
     fn do_mutate_msg<M: protos::Message>(
         &self,
         key: &str,
@@ -82,72 +125,41 @@ impl Cache {
         let key_bytes = key.as_bytes();
         let value = self.db.get(key_bytes)?;
         let mut value_msg = value
-            .and_then(|ivec| M::parse_from_bytes(&ivec).ok())
+            .and_then(|v| Self::decode_msg(key_bytes, &v))
             .or_else(|| if or_default { Some(M::new()) } else { None });
 
         if let Some(msg) = value_msg.as_mut() {
             mutate(msg);
-            let value = msg.write_to_bytes()?;
+            let value = self.encode_msg(msg)?;
             self.db.insert(key_bytes, value)?;
+            self.touch(key);
             log::debug!("mutate: key={}", key);
         }
 
         Ok(value_msg)
     }
 
-    fn do_scan_mutate_msg<M: protos::Message>(
-        &self,
-        prefix: &str,
-        mutate: impl Fn(&mut M),
-    ) -> CacheResult<()> {
-        let mut batch = sled::Batch::default();
-        for r in self.db.scan_prefix(prefix) {
-            let (k, v) = r?;
-            let mut msg = M::parse_from_bytes(&v)?;
-            mutate(&mut msg);
-            let value = msg.write_to_bytes()?;
-            batch.insert(k, value);
-        }
-        self.db.apply_batch(batch)?;
-        Ok(())
-    }
-
-    fn do_scan_msg<M: protos::Message>(&self, prefix: &str) -> impl Iterator<Item = M> {
-        self.db
-            .scan_prefix(prefix)
-            .filter_map(|r| r.ok().and_then(|(_k, v)| M::parse_from_bytes(&v).ok()))
-    }
-
-    fn do_remove_prefix(&self, prefix: &str) -> CacheResult<usize> {
-        let mut batch = sled::Batch::default();
-        let mut count = 0;
-        for r in self.db.scan_prefix(prefix) {
-            let (k, _v) = r?;
-            batch.remove(k);
-            count += 1;
-        }
-        self.db.apply_batch(batch)?;
-        self.db.flush()?;
-        Ok(count)
-    }
+    // The methods below block the current thread on the storage. They are meant for sync
+    // handlers and short lookups while parsing, prefer the `_async` variants in async code.
 
-    #[allow(unused_results)]
     pub fn insert_msg<M: protos::Message>(&self, key: &str, msg: &M) -> CacheResult<Option<M>> {
-        if self.is_test {
-            // using single threaded runtime
-            self.do_insert_msg(key, msg)
-        } else {
-            tokio::task::block_in_place(move || self.do_insert_msg(key, msg))
-        }
+        let value = self.encode_msg(msg)?;
+        self.insert_encoded(key, value)
     }
 
     pub fn get_msg<M: protos::Message>(&self, key: &str) -> CacheResult<Option<M>> {
-        if self.is_test {
-            // using single threaded runtime
-            self.do_get_msg(key)
-        } else {
-            tokio::task::block_in_place(move || self.do_get_msg(key))
+        let key_bytes = key.as_bytes();
+        let value = self.db.get(key_bytes)?;
+        let value_msg = value.and_then(|v| Self::decode_msg(key_bytes, &v));
+        if value_msg.is_some() {
+            self.touch(key);
         }
+        log::debug!(
+            "get: key={}, msg={}",
+            key,
+            if value_msg.is_some() { "Some" } else { "None" }
+        );
+        Ok(value_msg)
     }
 
     pub fn mutate_msg<M: protos::Message>(
@@ -155,11 +167,7 @@ impl Cache {
         key: &str,
         mutate: impl FnOnce(&mut M),
     ) -> CacheResult<Option<M>> {
-        if self.is_test {
-            self.do_mutate_msg(key, false, mutate)
-        } else {
-            tokio::task::block_in_place(move || self.do_mutate_msg(key, false, mutate))
-        }
+        self.do_mutate_msg(key, false, mutate)
     }
 
     pub fn mutate_msg_or_default<M: protos::Message>(
@@ -167,12 +175,7 @@ impl Cache {
         key: &str,
         mutate: impl FnOnce(&mut M),
     ) -> CacheResult<M> {
-        if self.is_test {
-            self.do_mutate_msg(key, true, mutate)
-        } else {
-            tokio::task::block_in_place(move || self.do_mutate_msg(key, true, mutate))
-        }
-        .map(Option::unwrap)
+        self.do_mutate_msg(key, true, mutate).map(Option::unwrap)
     }
 
     pub fn scan_mutate_msg<M: protos::Message>(
@@ -180,32 +183,57 @@ impl Cache {
         prefix: &str,
         mutate: impl Fn(&mut M),
     ) -> CacheResult<()> {
-        if self.is_test {
-            self.do_scan_mutate_msg(prefix, mutate)
-        } else {
-            tokio::task::block_in_place(move || self.do_scan_mutate_msg(prefix, mutate))
+        let mut batch = Batch::default();
+        for r in self.db.scan_prefix(prefix) {
+            let (k, v) = r?;
+            let mut msg = M::parse_from_bytes(migration::unwrap(&v).1)?;
+            mutate(&mut msg);
+            let value = self.encode_msg(&msg)?;
+            batch.insert(k, value);
         }
+        self.db.apply_batch(batch)?;
+        Ok(())
     }
 
     pub fn scan_msg<M: protos::Message>(&self, prefix: &str) -> impl Iterator<Item = M> {
-        if self.is_test {
-            // using single threaded runtime
-            self.do_scan_msg(prefix)
-        } else {
-            tokio::task::block_in_place(move || self.do_scan_msg(prefix))
-        }
+        self.db
+            .scan_prefix(prefix)
+            .filter_map(|r| r.ok().and_then(|(k, v)| Self::decode_msg(&k, &v)))
     }
 
     pub fn remove_prefix(&self, prefix: &str) -> CacheResult<usize> {
-        if self.is_test {
-            // using single threaded runtime
-            self.do_remove_prefix(prefix)
-        } else {
-            tokio::task::block_in_place(move || self.do_remove_prefix(prefix))
+        let mut batch = Batch::default();
+        let mut count = 0;
+        for r in self.db.scan_prefix(prefix) {
+            let (k, _v) = r?;
+            batch.remove(k);
+            count += 1;
+        }
+        self.db.apply_batch(batch)?;
+        self.flush()?;
+
+        let mut access_batch = Batch::default();
+        for r in self.access.scan_prefix(prefix) {
+            let (k, _v) = r?;
+            access_batch.remove(k);
+        }
+        self.access.apply_batch(access_batch)?;
+        Ok(count)
+    }
+
+    /// Count of the entries under `prefix` and their total size in bytes.
+    pub fn prefix_size(&self, prefix: &str) -> CacheResult<(u64, u64)> {
+        let mut items = 0;
+        let mut size = 0;
+        for r in self.db.scan_prefix(prefix) {
+            let (k, v) = r?;
+            items += 1;
+            size += (k.len() + v.len()) as u64;
         }
+        Ok((items, size))
     }
 
     pub fn total_size(&self) -> CacheResult<u64> {
-        self.db.size_on_disk().map_err(Into::into)
+        self.backend.size_on_disk()
     }
 }
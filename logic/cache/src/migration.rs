@@ -0,0 +1,266 @@
+//! Versioning of the cached values, so that the messages can be restructured without losing the
+//! entries cached by older versions.
+//!
+//! Values are stored as `[ENVELOPE_MAGIC, version (u32 BE), protobuf...]`. Field number 0 is
+//! invalid in protobuf, so the legacy values without the envelope never start with the magic,
+//! and are treated as version 0.
+
+use std::sync::atomic::Ordering;
+
+use crate::{Batch, Cache, CacheResult, Tree};
+
+/// Tree of the metadata of the cache itself.
+pub(crate) const META_TREE: &str = "meta";
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+const ENVELOPE_MAGIC: u8 = 0;
+const ENVELOPE_HEADER_LEN: usize = 5;
+
+pub(crate) fn wrap(version: u32, payload: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(ENVELOPE_HEADER_LEN + payload.len());
+    value.push(ENVELOPE_MAGIC);
+    value.extend_from_slice(&version.to_be_bytes());
+    value.extend_from_slice(payload);
+    value
+}
+
+/// Split the value into its schema version and the protobuf payload.
+pub fn unwrap(value: &[u8]) -> (u32, &[u8]) {
+    match value {
+        [ENVELOPE_MAGIC, v0, v1, v2, v3, payload @ ..] => {
+            (u32::from_be_bytes([*v0, *v1, *v2, *v3]), payload)
+        }
+        _ => (0, value),
+    }
+}
+
+pub(crate) fn load_schema_version(meta: &Tree) -> CacheResult<u32> {
+    let version = meta
+        .get(SCHEMA_VERSION_KEY)?
+        .and_then(|v| v.as_slice().try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or_default();
+    Ok(version)
+}
+
+type MigrateFn = Box<dyn Fn(&[u8]) -> CacheResult<Vec<u8>> + Send + Sync>;
+
+/// Migration of the payloads of the entries under `prefix` to the schema `version`.
+pub struct Migration {
+    /// Schema version after this migration, starting from 1.
+    pub version: u32,
+    pub prefix: &'static str,
+    pub description: &'static str,
+    migrate: MigrateFn,
+}
+
+impl Migration {
+    pub fn new(
+        version: u32,
+        prefix: &'static str,
+        description: &'static str,
+        migrate: impl Fn(&[u8]) -> CacheResult<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            version,
+            prefix,
+            description,
+            migrate: Box::new(migrate),
+        }
+    }
+
+    /// Migration that parses the payload as `From` and converts it to `To`.
+    pub fn convert<From: protos::Message, To: protos::Message>(
+        version: u32,
+        prefix: &'static str,
+        description: &'static str,
+        convert: impl Fn(From) -> To + Send + Sync + 'static,
+    ) -> Self {
+        Self::new(version, prefix, description, move |payload| {
+            let from = From::parse_from_bytes(payload)?;
+            Ok(convert(from).write_to_bytes()?)
+        })
+    }
+
+    /// Migrate the `payload` of `key` from outside of the cache, like backups, with the
+    /// applicable migrations newer than its `version`. Returns the new version and payload.
+    pub fn upgrade(
+        migrations: &[Migration],
+        key: &str,
+        version: u32,
+        mut payload: Vec<u8>,
+    ) -> CacheResult<(u32, Vec<u8>)> {
+        let mut upgraded = version;
+        for m in migrations.iter().filter(|m| m.version > version) {
+            if key.starts_with(m.prefix) {
+                payload = (m.migrate)(&payload)?;
+            }
+            upgraded = m.version;
+        }
+        Ok((upgraded, payload))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    pub migrated: usize,
+    /// Entries failed to migrate are kept untouched in their original version.
+    pub failed: usize,
+}
+
+impl Cache {
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version.load(Ordering::SeqCst)
+    }
+
+    /// Scan the raw payloads under `prefix` with their schema versions.
+    pub fn scan_payloads(
+        &self,
+        prefix: &str,
+    ) -> impl Iterator<Item = CacheResult<(String, u32, Vec<u8>)>> {
+        self.db.scan_prefix(prefix).map(|r| {
+            let (key, value) = r?;
+            let (version, payload) = unwrap(&value);
+            Ok((
+                String::from_utf8_lossy(&key).into_owned(),
+                version,
+                payload.to_vec(),
+            ))
+        })
+    }
+
+    /// Run the `migrations` newer than the schema version of the cache, in ascending order of
+    /// their versions. It's resumable, as the entries already in the target version are skipped.
+    pub fn migrate(&self, migrations: &[Migration]) -> CacheResult<MigrationStats> {
+        debug_assert!(migrations.windows(2).all(|w| w[0].version < w[1].version));
+
+        let mut stats = MigrationStats::default();
+        for m in migrations
+            .iter()
+            .filter(|m| m.version > self.schema_version())
+        {
+            let mut batch = Batch::default();
+            let (mut migrated, mut failed) = (0, 0);
+
+            for r in self.db.scan_prefix(m.prefix) {
+                let (key, value) = r?;
+                let (version, payload) = unwrap(&value);
+                if version >= m.version {
+                    continue;
+                }
+                match (m.migrate)(payload) {
+                    Ok(payload) => {
+                        batch.insert(key, wrap(m.version, &payload));
+                        migrated += 1;
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "failed to migrate {} from version {} to {}: {}",
+                            String::from_utf8_lossy(&key),
+                            version,
+                            m.version,
+                            e
+                        );
+                        failed += 1;
+                    }
+                }
+            }
+
+            self.db.apply_batch(batch)?;
+            self.meta
+                .insert(SCHEMA_VERSION_KEY, m.version.to_be_bytes())?;
+            self.schema_version.store(m.version, Ordering::SeqCst);
+            log::info!(
+                "migrated cache to version {} ({}): {} migrated, {} failed",
+                m.version,
+                m.description,
+                migrated,
+                failed
+            );
+
+            stats.migrated += migrated;
+            stats.failed += failed;
+        }
+
+        self.flush()?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryBackend;
+    use protos::{
+        DataModel::{Subject, Topic},
+        Message,
+    };
+
+    #[test]
+    fn test_envelope() {
+        let payload = b"\x0a\x03abc";
+        assert_eq!(unwrap(&wrap(42, payload)), (42, &payload[..]));
+        assert_eq!(unwrap(payload), (0, &payload[..]));
+        assert_eq!(unwrap(b""), (0, &b""[..]));
+    }
+
+    #[test]
+    fn test_migrate() -> CacheResult<()> {
+        let cache = Cache::new(Box::new(MemoryBackend::default()))?;
+        let subject = |content: &str| Subject {
+            content: content.to_owned(),
+            ..Default::default()
+        };
+
+        // Legacy values without the envelope are still readable.
+        cache.insert("/subject/legacy", subject("legacy").write_to_bytes()?)?;
+        cache.insert("/subject/broken", b"\xff")?;
+        cache.insert_msg("/subject/current", &subject("current"))?;
+        assert_eq!(
+            cache.get_msg::<Subject>("/subject/legacy")?,
+            Some(subject("legacy"))
+        );
+
+        let migrations = [
+            Migration::new(1, "/", "wrap legacy values", |p| Ok(p.to_vec())),
+            Migration::convert(2, "/subject/", "subject to topic", |s: Subject| Topic {
+                subject: Some(s).into(),
+                ..Default::default()
+            }),
+        ];
+        let stats = cache.migrate(&migrations)?;
+        assert_eq!(
+            stats,
+            MigrationStats {
+                migrated: 3 + 2,
+                failed: 1
+            }
+        );
+        assert_eq!(cache.schema_version(), 2);
+
+        for key in ["/subject/legacy", "/subject/current"] {
+            let topic = cache.get_msg::<Topic>(key)?.unwrap();
+            assert!(topic.has_subject());
+        }
+        // Kept untouched in version 1.
+        let broken = cache.get("/subject/broken")?.unwrap();
+        assert_eq!(unwrap(&broken), (1, &b"\xff"[..]));
+
+        // New values are written in the latest version, and nothing to migrate again.
+        cache.insert_msg("/subject/new", &subject("new"))?;
+        assert_eq!(unwrap(&cache.get("/subject/new")?.unwrap()).0, 2);
+        assert_eq!(cache.migrate(&migrations)?, MigrationStats::default());
+
+        // Payloads from backups are upgraded by the same migrations.
+        let payloads = cache
+            .scan_payloads("/subject/")
+            .collect::<CacheResult<Vec<_>>>()?;
+        assert_eq!(payloads.len(), 4);
+        let legacy = subject("legacy").write_to_bytes()?;
+        let (version, payload) = Migration::upgrade(&migrations, "/subject/legacy", 0, legacy)?;
+        assert_eq!(version, 2);
+        assert!(Topic::parse_from_bytes(&payload)?.has_subject());
+
+        Ok(())
+    }
+}
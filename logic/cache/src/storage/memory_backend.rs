@@ -0,0 +1,115 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use super::{Backend, Batch, KvIter, KvPair, Storage};
+use crate::CacheResult;
+
+/// Backend that keeps everything in memory, mainly used for testing.
+#[derive(Default)]
+pub struct MemoryBackend {
+    trees: Mutex<HashMap<String, Arc<MemoryTree>>>,
+}
+
+impl Backend for MemoryBackend {
+    fn open_tree(&self, name: &str) -> CacheResult<Arc<dyn Storage>> {
+        let tree: Arc<dyn Storage> = self
+            .trees
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .clone();
+        Ok(tree)
+    }
+
+    fn tree_names(&self) -> CacheResult<Vec<String>> {
+        Ok(self.trees.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn size_on_disk(&self) -> CacheResult<u64> {
+        let trees = self.trees.lock().unwrap();
+        let size = trees
+            .values()
+            .map(|tree| {
+                let map = tree.0.read().unwrap();
+                map.iter()
+                    .map(|(k, v)| (k.len() + v.len()) as u64)
+                    .sum::<u64>()
+            })
+            .sum();
+        Ok(size)
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MemoryTree(RwLock<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+impl Storage for MemoryTree {
+    fn get(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.read().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.write().unwrap().insert(key.to_vec(), value.to_vec()))
+    }
+
+    fn remove(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.write().unwrap().remove(key))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        Box::new(MemoryScan {
+            tree: self,
+            prefix: prefix.to_vec(),
+            last: None,
+        })
+    }
+
+    fn apply_batch(&self, batch: Batch) -> CacheResult<()> {
+        let mut map = self.0.write().unwrap();
+        for (key, value) in batch.into_ops() {
+            match value {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.0.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// Scan without holding the lock, so that the tree can be modified while scanning like sled.
+struct MemoryScan<'a> {
+    tree: &'a MemoryTree,
+    prefix: Vec<u8>,
+    last: Option<Vec<u8>>,
+}
+
+impl Iterator for MemoryScan<'_> {
+    type Item = CacheResult<KvPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.tree.0.read().unwrap();
+        let lower = match &self.last {
+            Some(last) => Bound::Excluded(last.as_slice()),
+            None => Bound::Included(self.prefix.as_slice()),
+        };
+        let (k, v) = map
+            .range::<[u8], _>((lower, Bound::Unbounded))
+            .next()
+            .filter(|(k, _)| k.starts_with(&self.prefix))?;
+        self.last = Some(k.clone());
+        Some(Ok((k.clone(), v.clone())))
+    }
+}
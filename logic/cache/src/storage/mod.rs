@@ -0,0 +1,207 @@
+//! Storage backends of the cache, so that the service is not tied to sled.
+//!
+//! A backend consists of named trees, each of which is an ordered key-value store. The cache
+//! entries live in the tree named `DEFAULT_TREE`.
+
+mod memory_backend;
+mod sled_backend;
+#[cfg(feature = "sqlite")]
+mod sqlite_backend;
+
+use std::sync::Arc;
+
+use protos::DataModel::StorageBackend;
+
+use crate::CacheResult;
+
+pub use memory_backend::MemoryBackend;
+pub use sled_backend::SledBackend;
+#[cfg(feature = "sqlite")]
+pub use sqlite_backend::SqliteBackend;
+
+pub const DEFAULT_TREE: &str = "__default";
+
+pub type KvPair = (Vec<u8>, Vec<u8>);
+pub type KvIter<'a> = Box<dyn Iterator<Item = CacheResult<KvPair>> + 'a>;
+
+/// Ordered key-value store of a tree.
+pub trait Storage: Send + Sync {
+    fn get(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>>;
+
+    /// Insert the entry, returning the last value.
+    fn insert(&self, key: &[u8], value: &[u8]) -> CacheResult<Option<Vec<u8>>>;
+
+    /// Remove the entry, returning the last value.
+    fn remove(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>>;
+
+    /// Entries whose keys start with `prefix`, in ascending order of the keys.
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_>;
+
+    /// Apply the operations in the batch atomically.
+    fn apply_batch(&self, batch: Batch) -> CacheResult<()>;
+
+    fn clear(&self) -> CacheResult<()>;
+}
+
+pub trait Backend: Send + Sync {
+    fn open_tree(&self, name: &str) -> CacheResult<Arc<dyn Storage>>;
+
+    /// Names of the trees that may have entries.
+    fn tree_names(&self) -> CacheResult<Vec<String>>;
+
+    /// Size of all trees in bytes, which may be estimated.
+    fn size_on_disk(&self) -> CacheResult<u64>;
+
+    fn flush(&self) -> CacheResult<()>;
+}
+
+/// Open the backend selected in the configuration.
+pub fn open(conf: &config::Conf) -> CacheResult<Box<dyn Backend>> {
+    log::info!("open {:?} storage backend", conf.storage_backend);
+    let backend: Box<dyn Backend> = match conf.storage_backend {
+        StorageBackend::SLED => Box::new(SledBackend::open(
+            &conf.cache_path,
+            conf.sled_cache_capacity,
+            conf.sled_flush_every_ms,
+        )?),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::SQLITE => Box::new(SqliteBackend::open(&conf.sqlite_cache_path)?),
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::SQLITE => return Err(crate::CacheError::UnsupportedBackend("SQLite")),
+        StorageBackend::MEMORY => Box::new(MemoryBackend::default()),
+    };
+    Ok(backend)
+}
+
+/// Operations to be applied atomically with `Tree::apply_batch`.
+#[derive(Debug, Default)]
+pub struct Batch {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl Batch {
+    pub fn insert(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
+        self.ops
+            .push((key.as_ref().to_vec(), Some(value.as_ref().to_vec())));
+    }
+
+    pub fn remove(&mut self, key: impl AsRef<[u8]>) {
+        self.ops.push((key.as_ref().to_vec(), None));
+    }
+
+    /// Operations in order, where `None` is removal.
+    pub fn into_ops(self) -> impl Iterator<Item = (Vec<u8>, Option<Vec<u8>>)> {
+        self.ops.into_iter()
+    }
+}
+
+/// Handle of a tree in the backend.
+#[derive(Clone)]
+pub struct Tree(Arc<dyn Storage>);
+
+impl Tree {
+    pub fn get(&self, key: impl AsRef<[u8]>) -> CacheResult<Option<Vec<u8>>> {
+        self.0.get(key.as_ref())
+    }
+
+    pub fn insert(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> CacheResult<Option<Vec<u8>>> {
+        self.0.insert(key.as_ref(), value.as_ref())
+    }
+
+    pub fn remove(&self, key: impl AsRef<[u8]>) -> CacheResult<Option<Vec<u8>>> {
+        self.0.remove(key.as_ref())
+    }
+
+    pub fn contains_key(&self, key: impl AsRef<[u8]>) -> CacheResult<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    pub fn scan_prefix(&self, prefix: impl AsRef<[u8]>) -> KvIter<'_> {
+        self.0.scan_prefix(prefix.as_ref())
+    }
+
+    pub fn iter(&self) -> KvIter<'_> {
+        self.scan_prefix(b"")
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn apply_batch(&self, batch: Batch) -> CacheResult<()> {
+        self.0.apply_batch(batch)
+    }
+
+    pub fn clear(&self) -> CacheResult<()> {
+        self.0.clear()
+    }
+}
+
+impl From<Arc<dyn Storage>> for Tree {
+    fn from(storage: Arc<dyn Storage>) -> Self {
+        Self(storage)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check_backend(backend: &dyn Backend) -> CacheResult<()> {
+        let tree = Tree::from(backend.open_tree(DEFAULT_TREE)?);
+        let other = Tree::from(backend.open_tree("other")?);
+
+        assert_eq!(tree.insert("/a/1", "x")?, None);
+        assert_eq!(tree.insert("/a/1", "y")?, Some(b"x".to_vec()));
+        tree.insert("/a/2", "z")?;
+        tree.insert("/b/1", "w")?;
+        other.insert("/a/3", "v")?;
+        let names = backend.tree_names()?;
+        assert!(names.iter().any(|n| n == DEFAULT_TREE));
+        assert!(names.iter().any(|n| n == "other"));
+
+        assert_eq!(tree.get("/a/1")?, Some(b"y".to_vec()));
+        assert_eq!(tree.get("/a/3")?, None);
+        let keys = |prefix: &str| -> CacheResult<Vec<Vec<u8>>> {
+            tree.scan_prefix(prefix)
+                .map(|r| r.map(|(k, _)| k))
+                .collect()
+        };
+        assert_eq!(keys("/a/")?, [b"/a/1".to_vec(), b"/a/2".to_vec()]);
+        assert_eq!(keys("/")?.len(), 3);
+        assert_eq!(keys("/c")?.len(), 0);
+
+        let mut batch = Batch::default();
+        batch.remove("/a/1");
+        batch.insert("/c/1", "u");
+        tree.apply_batch(batch)?;
+        assert!(!tree.contains_key("/a/1")?);
+        assert_eq!(tree.remove("/c/1")?, Some(b"u".to_vec()));
+        assert_eq!(tree.len(), 2);
+
+        tree.clear()?;
+        assert!(tree.is_empty());
+        assert_eq!(other.len(), 1);
+
+        backend.flush()?;
+        backend.size_on_disk()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_backends() -> CacheResult<()> {
+        check_backend(&MemoryBackend::default())?;
+        check_backend(&SledBackend::temporary()?)?;
+        #[cfg(feature = "sqlite")]
+        check_backend(&SqliteBackend::open_in_memory()?)?;
+        Ok(())
+    }
+}
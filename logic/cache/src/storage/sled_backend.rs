@@ -0,0 +1,100 @@
+use std::{path::Path, sync::Arc};
+
+use super::{Backend, Batch, DEFAULT_TREE, KvIter, Storage};
+use crate::CacheResult;
+
+pub struct SledBackend {
+    db: sled::Db,
+}
+
+impl SledBackend {
+    pub fn open(path: &Path, cache_capacity: u64, flush_every_ms: u64) -> CacheResult<Self> {
+        log::debug!("open db at {:?}", path);
+        let db = sled::Config::new()
+            .path(path)
+            .flush_every_ms(Some(flush_every_ms))
+            .cache_capacity(cache_capacity)
+            .open()?;
+        Ok(Self { db })
+    }
+
+    pub fn temporary() -> CacheResult<Self> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(Self { db })
+    }
+}
+
+impl Backend for SledBackend {
+    fn open_tree(&self, name: &str) -> CacheResult<Arc<dyn Storage>> {
+        // The cache entries have always been in the default tree of sled.
+        let tree = if name == DEFAULT_TREE {
+            (*self.db).clone()
+        } else {
+            self.db.open_tree(name)?
+        };
+        Ok(Arc::new(SledTree(tree)))
+    }
+
+    fn tree_names(&self) -> CacheResult<Vec<String>> {
+        let default = self.db.name();
+        let names = (self.db.tree_names().into_iter())
+            .map(|name| {
+                if name == default {
+                    DEFAULT_TREE.to_owned()
+                } else {
+                    String::from_utf8_lossy(&name).into_owned()
+                }
+            })
+            .collect();
+        Ok(names)
+    }
+
+    fn size_on_disk(&self) -> CacheResult<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+struct SledTree(sled::Tree);
+
+impl Storage for SledTree {
+    fn get(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.insert(key, value)?.map(|v| v.to_vec()))
+    }
+
+    fn remove(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        Ok(self.0.remove(key)?.map(|v| v.to_vec()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        Box::new(self.0.scan_prefix(prefix).map(|r| {
+            let (k, v) = r?;
+            Ok((k.to_vec(), v.to_vec()))
+        }))
+    }
+
+    fn apply_batch(&self, batch: Batch) -> CacheResult<()> {
+        let mut sled_batch = sled::Batch::default();
+        for (key, value) in batch.into_ops() {
+            match value {
+                Some(value) => sled_batch.insert(key, value),
+                None => sled_batch.remove(key),
+            }
+        }
+        self.0.apply_batch(sled_batch)?;
+        Ok(())
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.0.clear()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::{Backend, Batch, KvIter, KvPair, Storage};
+use crate::CacheResult;
+
+/// Rows fetched at a time when scanning.
+const SCAN_PAGE_SIZE: usize = 128;
+
+/// Backend on SQLite, where all trees share a table keyed by `(tree, key)`.
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> CacheResult<Self> {
+        log::debug!("open sqlite db at {:?}", path);
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Self::new(conn)
+    }
+
+    pub fn open_in_memory() -> CacheResult<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(conn: Connection) -> CacheResult<Self> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS kv (
+                tree TEXT NOT NULL,
+                key BLOB NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (tree, key)
+            ) WITHOUT ROWID",
+            [],
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn open_tree(&self, name: &str) -> CacheResult<Arc<dyn Storage>> {
+        Ok(Arc::new(SqliteTree {
+            conn: self.conn.clone(),
+            tree: name.to_owned(),
+        }))
+    }
+
+    fn tree_names(&self) -> CacheResult<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT DISTINCT tree FROM kv")?;
+        let names = stmt
+            .query_map([], |r| r.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+
+    fn size_on_disk(&self) -> CacheResult<u64> {
+        let conn = self.conn.lock().unwrap();
+        let page_count: u64 = conn.query_row("PRAGMA page_count", [], |r| r.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |r| r.get(0))?;
+        Ok(page_count * page_size)
+    }
+
+    fn flush(&self) -> CacheResult<()> {
+        // Transactions are durable once committed, only the WAL is checkpointed here.
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+struct SqliteTree {
+    conn: Arc<Mutex<Connection>>,
+    tree: String,
+}
+
+fn get(conn: &Connection, tree: &str, key: &[u8]) -> rusqlite::Result<Option<Vec<u8>>> {
+    conn.query_row(
+        "SELECT value FROM kv WHERE tree = ?1 AND key = ?2",
+        params![tree, key],
+        |r| r.get(0),
+    )
+    .optional()
+}
+
+impl SqliteTree {
+    /// Entries with keys not less than `lower` (or greater than it if `exclusive`).
+    fn scan_page(&self, lower: &[u8], exclusive: bool) -> CacheResult<Vec<KvPair>> {
+        let sql = if exclusive {
+            "SELECT key, value FROM kv WHERE tree = ?1 AND key > ?2 ORDER BY key LIMIT ?3"
+        } else {
+            "SELECT key, value FROM kv WHERE tree = ?1 AND key >= ?2 ORDER BY key LIMIT ?3"
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(sql)?;
+        let rows = stmt
+            .query_map(params![self.tree, lower, SCAN_PAGE_SIZE as i64], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+}
+
+impl Storage for SqliteTree {
+    fn get(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(get(&conn, &self.tree, key)?)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let last = get(&conn, &self.tree, key)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO kv (tree, key, value) VALUES (?1, ?2, ?3)",
+            params![self.tree, key, value],
+        )?;
+        Ok(last)
+    }
+
+    fn remove(&self, key: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let last = get(&conn, &self.tree, key)?;
+        conn.execute(
+            "DELETE FROM kv WHERE tree = ?1 AND key = ?2",
+            params![self.tree, key],
+        )?;
+        Ok(last)
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> KvIter<'_> {
+        Box::new(SqliteScan {
+            tree: self,
+            prefix: prefix.to_vec(),
+            last: None,
+            page: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    fn apply_batch(&self, batch: Batch) -> CacheResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (key, value) in batch.into_ops() {
+            match value {
+                Some(value) => tx.execute(
+                    "INSERT OR REPLACE INTO kv (tree, key, value) VALUES (?1, ?2, ?3)",
+                    params![self.tree, key, value],
+                )?,
+                None => tx.execute(
+                    "DELETE FROM kv WHERE tree = ?1 AND key = ?2",
+                    params![self.tree, key],
+                )?,
+            };
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM kv WHERE tree = ?1", params![self.tree])?;
+        Ok(())
+    }
+}
+
+/// Scan page by page, so that neither the lock is held nor all entries are loaded at once.
+struct SqliteScan<'a> {
+    tree: &'a SqliteTree,
+    prefix: Vec<u8>,
+    last: Option<Vec<u8>>,
+    page: VecDeque<KvPair>,
+    done: bool,
+}
+
+impl Iterator for SqliteScan<'_> {
+    type Item = CacheResult<KvPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            let page = match &self.last {
+                Some(last) => self.tree.scan_page(last, true),
+                None => self.tree.scan_page(&self.prefix, false),
+            };
+            match page {
+                Ok(page) => {
+                    self.done = page.len() < SCAN_PAGE_SIZE;
+                    self.page = page.into();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let (k, v) = self.page.pop_front()?;
+        if !k.starts_with(&self.prefix) {
+            self.done = true;
+            self.page.clear();
+            return None;
+        }
+        self.last = Some(k.clone());
+        Some(Ok((k, v)))
+    }
+}
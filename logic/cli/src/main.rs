@@ -0,0 +1,344 @@
+use std::{env, process::exit};
+
+use protos::{
+    DataModel::{AuthInfo, CacheOperation, CacheType, Configuration, ForumId, ForumId_oneof_id},
+    Message,
+    Service::*,
+};
+use service::{
+    dispatch_async, dispatch_sync,
+    error::{ServiceError, ServiceResult},
+};
+
+mod render;
+
+const USAGE: &str = "\
+usage: mnga-cli [--json] <command> [args...]
+
+Responses of listing and reading are summarized as text, and the others are printed as JSON,
+which is also used for all with `--json`.
+
+commands:
+  topics <fid> [page]                 list topics in the forum
+  topic <tid> [page]                  show posts of the topic
+  search <key> [fid] [page]           search topics, in all forums if `fid` is not given
+  local-search <query>                search cached topics and posts
+  notis                               fetch notifications
+  msgs [page]                         list short messages
+  msg <id> [page]                     show the short message conversation
+  cache <check|clear|compact> [type]  check, clear or compact the cache of given type,
+                                      one of `all`, `history`, `details`, `notis`,
+                                      `favorites` and `blocks`
+  cache rekey [key]                   encrypt the sensitive cache entries with the key
+                                      in hex, or decrypt them if not given
+  cache reset-encryption              discard the cache entries encrypted with a lost key
+  backup [type...]                    back up the user data of given types into an archive
+  restore <path>                      restore the user data from an archive
+  accounts                            list the authenticated accounts
+
+environment (also read from `.env`):
+  MNGA_UID, MNGA_TOKEN                auth info of the account
+  MNGA_DOCUMENT_DIR                   directory for the cache, a temporary one if not set
+  MNGA_CACHE_KEY                      key in hex to encrypt the sensitive cache entries";
+
+enum Request {
+    Sync(SyncRequest_oneof_value),
+    Async(AsyncRequest_oneof_value),
+}
+
+struct Args {
+    json: bool,
+    request: Request,
+}
+
+fn forum_id(fid: &str) -> ForumId {
+    ForumId {
+        id: Some(ForumId_oneof_id::fid(fid.to_owned())),
+        ..Default::default()
+    }
+}
+
+fn parse_page(arg: Option<&String>) -> Result<u32, String> {
+    arg.map_or(Ok(1), |p| {
+        p.parse().map_err(|_| format!("invalid page `{}`", p))
+    })
+}
+
+fn parse_cache_type(arg: Option<&String>) -> Result<CacheType, String> {
+    match arg.map(String::as_str) {
+        None | Some("all") => Ok(CacheType::ALL),
+        Some("history") => Ok(CacheType::TOPIC_HISTORY),
+        Some("details") => Ok(CacheType::TOPIC_DETAILS),
+        Some("notis") => Ok(CacheType::NOTIFICATION),
+        Some("favorites") => Ok(CacheType::FAVORITE),
+        Some("blocks") => Ok(CacheType::BLOCK_LIST),
+        Some(t) => Err(format!("unknown cache type `{}`", t)),
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid hex `{}`", hex);
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    use AsyncRequest_oneof_value as A;
+
+    let json = args.iter().any(|a| a == "--json");
+    let args = args.iter().filter(|a| *a != "--json").collect::<Vec<_>>();
+    let (command, args) = args.split_first().ok_or("no command given")?;
+    let arg = |i: usize| args.get(i).copied();
+    let required = |i: usize, name: &str| arg(i).ok_or(format!("missing argument <{}>", name));
+
+    let request = match command.as_str() {
+        "topics" => A::topic_list(TopicListRequest {
+            id: Some(forum_id(required(0, "fid")?)).into(),
+            page: parse_page(arg(1))?,
+            ..Default::default()
+        }),
+        "topic" => A::topic_details(TopicDetailsRequest {
+            topic_id: required(0, "tid")?.to_owned(),
+            page: parse_page(arg(1))?,
+            ..Default::default()
+        }),
+        "search" => A::topic_search(TopicSearchRequest {
+            key: required(0, "key")?.to_owned(),
+            id: arg(1).map(|fid| forum_id(fid)).into(),
+            page: parse_page(arg(2))?,
+            ..Default::default()
+        }),
+        "local-search" => A::local_search(LocalSearchRequest {
+            query: required(0, "query")?.to_owned(),
+            ..Default::default()
+        }),
+        "notis" => A::fetch_notification(FetchNotificationRequest::new()),
+        "msgs" => A::short_message_list(ShortMessageListRequest {
+            page: parse_page(arg(0))?,
+            ..Default::default()
+        }),
+        "msg" => A::short_message_details(ShortMessageDetailsRequest {
+            id: required(0, "id")?.to_owned(),
+            page: parse_page(arg(1))?,
+            ..Default::default()
+        }),
+        "cache" if arg(0).map(String::as_str) == Some("rekey") => A::cache(CacheRequest {
+            operation: CacheOperation::REKEY,
+            new_encryption_key: arg(1)
+                .map(|k| parse_hex(k))
+                .transpose()?
+                .unwrap_or_default(),
+            ..Default::default()
+        }),
+        "cache" if arg(0).map(String::as_str) == Some("reset-encryption") => {
+            A::cache(CacheRequest {
+                operation: CacheOperation::RESET_ENCRYPTION,
+                ..Default::default()
+            })
+        }
+        "cache" => {
+            let operation =
+                match required(0, "check|clear|compact|rekey|reset-encryption")?.as_str() {
+                    "check" => CacheOperation::CHECK,
+                    "clear" => CacheOperation::CLEAR,
+                    "compact" => CacheOperation::COMPACT,
+                    op => return Err(format!("unknown cache operation `{}`", op)),
+                };
+            A::cache(CacheRequest {
+                field_type: parse_cache_type(arg(1))?,
+                operation,
+                ..Default::default()
+            })
+        }
+        "backup" => A::cache_backup(CacheBackupRequest {
+            types: args
+                .iter()
+                .map(|t| parse_cache_type(Some(*t)))
+                .collect::<Result<_, _>>()?,
+            ..Default::default()
+        }),
+        "restore" => A::cache_restore(CacheRestoreRequest {
+            path: required(0, "path")?.to_owned(),
+            ..Default::default()
+        }),
+        "accounts" => {
+            let request = SyncRequest_oneof_value::account_list(AccountListRequest::new());
+            return Ok(Args {
+                json,
+                request: Request::Sync(request),
+            });
+        }
+        c => return Err(format!("unknown command `{}`", c)),
+    };
+
+    Ok(Args {
+        json,
+        request: Request::Async(request),
+    })
+}
+
+/// Configure the service and sync the auth info like the app does on launch.
+fn setup() -> ServiceResult<()> {
+    dotenv::dotenv().ok();
+
+    if let Ok(dir) = env::var("MNGA_DOCUMENT_DIR") {
+        let _ = dispatch_sync(SyncRequest_oneof_value::configure(ConfigureRequest {
+            config: Some(Configuration {
+                document_dir_path: dir,
+                cache_encryption_key: match env::var("MNGA_CACHE_KEY") {
+                    Ok(key) => parse_hex(&key).map_err(|e| {
+                        ServiceError::MngaInternal(format!("MNGA_CACHE_KEY: {}", e))
+                    })?,
+                    Err(_) => Vec::new(),
+                },
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        }))?;
+    }
+
+    let info = AuthInfo {
+        uid: env::var("MNGA_UID").unwrap_or_default(),
+        token: env::var("MNGA_TOKEN").unwrap_or_default(),
+        ..Default::default()
+    };
+    let _ = dispatch_sync(SyncRequest_oneof_value::auth(AuthRequest {
+        info: Some(info).into(),
+        ..Default::default()
+    }))?;
+
+    Ok(())
+}
+
+async fn run(request: Request) -> ServiceResult<Box<dyn Message>> {
+    let resetting_encryption = matches!(
+        &request,
+        Request::Async(AsyncRequest_oneof_value::cache(r))
+            if r.get_operation() == CacheOperation::RESET_ENCRYPTION
+    );
+    match setup() {
+        // Configuring fails with a lost key until the encrypted entries are discarded.
+        Err(e) if resetting_encryption => eprintln!("warning: {}", e.to_app_string()),
+        result => result?,
+    }
+    match request {
+        Request::Sync(request) => dispatch_sync(request),
+        Request::Async(request) => dispatch_async(request).await,
+    }
+}
+
+fn print_response(response: &dyn Message, json: bool) {
+    match render::render_text(response) {
+        Some(text) if !json => println!("{}", text),
+        _ => {
+            let value = protos::json::to_json(response);
+            println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+
+    match run(args.request).await {
+        Ok(response) => print_response(response.as_ref(), args.json),
+        Err(e) => {
+            eprintln!("error: {}", e.to_app_string());
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        let args = args
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse("--json topics -7 2").unwrap();
+        assert!(args.json);
+        match args.request {
+            Request::Async(AsyncRequest_oneof_value::topic_list(r)) => {
+                assert_eq!(r.get_id().get_fid(), "-7");
+                assert_eq!(r.get_page(), 2);
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        match parse("search 原神").unwrap().request {
+            Request::Async(AsyncRequest_oneof_value::topic_search(r)) => {
+                assert_eq!(r.get_key(), "原神");
+                assert!(!r.has_id());
+                assert_eq!(r.get_page(), 1);
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        match parse("cache clear notis").unwrap().request {
+            Request::Async(AsyncRequest_oneof_value::cache(r)) => {
+                assert_eq!(r.get_operation(), CacheOperation::CLEAR);
+                assert_eq!(r.get_field_type(), CacheType::NOTIFICATION);
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        match parse("backup history blocks").unwrap().request {
+            Request::Async(AsyncRequest_oneof_value::cache_backup(r)) => {
+                assert_eq!(
+                    r.get_types(),
+                    [CacheType::TOPIC_HISTORY, CacheType::BLOCK_LIST]
+                );
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        assert!(parse("").is_err());
+        assert!(parse("topic").is_err());
+        assert!(parse("topics -7 next").is_err());
+        match parse("cache rekey 00ff").unwrap().request {
+            Request::Async(AsyncRequest_oneof_value::cache(r)) => {
+                assert_eq!(r.get_operation(), CacheOperation::REKEY);
+                assert_eq!(r.get_new_encryption_key(), [0x00, 0xff]);
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        match parse("cache reset-encryption").unwrap().request {
+            Request::Async(AsyncRequest_oneof_value::cache(r)) => {
+                assert_eq!(r.get_operation(), CacheOperation::RESET_ENCRYPTION);
+            }
+            _ => panic!("unexpected request"),
+        }
+
+        assert!(parse("cache purge").is_err());
+        assert!(parse("cache rekey 0g").is_err());
+        assert!(parse("cache rekey 123").is_err());
+        assert!(parse("restore").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::{collections::HashMap, fmt::Write};
+
+use chrono::{DateTime, FixedOffset};
+use protos::{
+    DataModel::{Notification_Type, Subject, Topic, User, UserName},
+    Message,
+    Service::*,
+};
+
+/// Characters of the summary of a notification or a search hit.
+const SUMMARY_CHARS: usize = 80;
+
+/// Dates are shown in the time zone of NGA, like the web pages.
+fn format_date(timestamp: u64) -> String {
+    const HOUR: i32 = 3600;
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.with_timezone(&FixedOffset::east_opt(8 * HOUR).unwrap()))
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn subject_line(subject: &Subject) -> String {
+    let tags = (subject.get_tags().iter())
+        .map(|t| format!("[{}]", t))
+        .collect::<String>();
+    format!("{}{}", tags, subject.get_content())
+}
+
+fn user_name(name: &UserName) -> &str {
+    if name.get_anonymous().is_empty() {
+        name.get_normal()
+    } else {
+        name.get_anonymous()
+    }
+}
+
+/// Names of the users by id, falling back to the id if not found.
+struct Users<'a>(HashMap<&'a str, &'a str>);
+
+impl<'a> Users<'a> {
+    fn new(users: &'a [User]) -> Self {
+        Self(
+            (users.iter())
+                .map(|u| (u.get_id(), user_name(u.get_name())))
+                .collect(),
+        )
+    }
+
+    fn name(&self, id: &'a str) -> &'a str {
+        self.0.get(id).copied().unwrap_or(id)
+    }
+}
+
+fn topics(out: &mut String, topics: &[Topic], pages: u32) {
+    for topic in topics {
+        let _ = writeln!(
+            out,
+            "{:>10}  {}\n            {} · {} · {} replies",
+            topic.get_id(),
+            subject_line(topic.get_subject()),
+            user_name(topic.get_author_name()),
+            format_date(topic.get_last_post_date()),
+            topic.get_replies_num(),
+        );
+    }
+    let _ = write!(out, "{} topics, {} pages", topics.len(), pages);
+}
+
+fn topic_list(response: &TopicListResponse) -> String {
+    let mut out = String::new();
+    if response.has_forum() {
+        let _ = writeln!(out, "# {}\n", response.get_forum().get_name());
+    }
+    topics(&mut out, response.get_topics(), response.get_pages());
+    out
+}
+
+fn topic_search(response: &TopicSearchResponse) -> String {
+    let mut out = String::new();
+    topics(&mut out, response.get_topics(), response.get_pages());
+    out
+}
+
+fn topic_details(response: &TopicDetailsResponse) -> String {
+    let users = Users::new(response.get_in_place_users());
+    let mut out = format!(
+        "# {}\n{} · {} pages{}\n",
+        subject_line(response.get_topic().get_subject()),
+        response.get_forum_name(),
+        response.get_pages(),
+        if response.get_is_local_cache() {
+            " · from local cache"
+        } else {
+            ""
+        },
+    );
+    for post in response.get_replies() {
+        let _ = write!(
+            out,
+            "\n#{} {} · {}\n{}\n",
+            post.get_floor(),
+            users.name(post.get_author_id()),
+            format_date(post.get_post_date()),
+            text::to_plain_text(post.get_content().get_spans()),
+        );
+    }
+    out.pop();
+    out
+}
+
+fn local_search(response: &LocalSearchResponse) -> String {
+    let mut out = String::new();
+    for hit in response.get_hits() {
+        let topic = hit.get_topic().get_topic_snapshot();
+        let _ = writeln!(
+            out,
+            "{:>10}  {}",
+            topic.get_id(),
+            subject_line(topic.get_subject())
+        );
+        if hit.has_post() {
+            let post = hit.get_post();
+            let _ = writeln!(
+                out,
+                "            #{} {}",
+                post.get_floor(),
+                text::summarize(post.get_content().get_spans(), SUMMARY_CHARS),
+            );
+        }
+    }
+    let _ = write!(out, "{} hits", response.get_hits().len());
+    out
+}
+
+fn notis(response: &FetchNotificationResponse) -> String {
+    let mut out = String::new();
+    for noti in response.get_notis() {
+        let action = match noti.get_field_type() {
+            Notification_Type::REPLY_TOPIC => "replied to your topic",
+            Notification_Type::REPLY_POST => "replied to your post",
+            Notification_Type::AT_TOPIC | Notification_Type::AT_POST => "mentioned you",
+            Notification_Type::SHORT_MESSAGE_START => "started a conversation",
+            Notification_Type::SHORT_MESSAGE => "sent you a message",
+            Notification_Type::VOTE => "voted on your post",
+            Notification_Type::UNKNOWN => "notified you",
+        };
+        let _ = writeln!(
+            out,
+            "{} {} {} {}\n    {}",
+            if noti.get_read() { " " } else { "*" },
+            format_date(noti.get_timestamp()),
+            user_name(noti.get_other_user().get_name()),
+            action,
+            subject_line(noti.get_topic_subject()),
+        );
+    }
+    let unread = (response.get_notis().iter())
+        .filter(|n| !n.get_read())
+        .count();
+    let _ = write!(
+        out,
+        "{} notifications, {} unread",
+        response.get_notis().len(),
+        unread
+    );
+    out
+}
+
+fn short_message_list(response: &ShortMessageListResponse) -> String {
+    let mut out = String::new();
+    for msg in response.get_messages() {
+        let names = (msg.get_user_names().iter())
+            .map(user_name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(
+            out,
+            "{:>10}  {}\n            {} · {} · {} posts",
+            msg.get_id(),
+            msg.get_subject(),
+            names,
+            format_date(msg.get_last_post_date()),
+            msg.get_post_num(),
+        );
+    }
+    let _ = write!(
+        out,
+        "{} conversations, {} pages",
+        response.get_messages().len(),
+        response.get_pages()
+    );
+    out
+}
+
+fn short_message_details(response: &ShortMessageDetailsResponse) -> String {
+    let users = Users::new(response.get_users());
+    let mut out = String::new();
+    for post in response.get_posts() {
+        let _ = write!(
+            out,
+            "{} · {}\n{}\n\n",
+            users.name(post.get_author_id()),
+            format_date(post.get_post_date()),
+            text::to_plain_text(post.get_content().get_spans()),
+        );
+    }
+    let _ = write!(out, "{} pages", response.get_pages());
+    out
+}
+
+/// Render a readable summary of the response, or `None` if there's no text form of it.
+pub fn render_text(response: &dyn Message) -> Option<String> {
+    let any = response.as_any();
+    macro_rules! try_render {
+        ($($ty:ty => $render:ident),* $(,)?) => {
+            $(if let Some(r) = any.downcast_ref::<$ty>() {
+                return Some($render(r));
+            })*
+        };
+    }
+    try_render! {
+        TopicListResponse => topic_list,
+        TopicSearchResponse => topic_search,
+        TopicDetailsResponse => topic_details,
+        LocalSearchResponse => local_search,
+        FetchNotificationResponse => notis,
+        ShortMessageListResponse => short_message_list,
+        ShortMessageDetailsResponse => short_message_details,
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protos::DataModel::{Post, PostContent};
+
+    #[test]
+    fn test_render_topic_details() {
+        let response = TopicDetailsResponse {
+            topic: Some(Topic {
+                subject: Some(Subject {
+                    tags: vec!["讨论".to_owned()].into(),
+                    content: "标题".to_owned(),
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            })
+            .into(),
+            forum_name: "大漩涡".to_owned(),
+            pages: 2,
+            replies: vec![Post {
+                floor: 1,
+                author_id: "42".to_owned(),
+                post_date: 1_600_000_000,
+                content: Some(PostContent {
+                    spans: text::parse_content("正文[img]./a.jpg[/img]").spans,
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            }]
+            .into(),
+            in_place_users: vec![User {
+                id: "42".to_owned(),
+                name: Some(UserName {
+                    normal: "BugenZhao".to_owned(),
+                    ..Default::default()
+                })
+                .into(),
+                ..Default::default()
+            }]
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            render_text(&response).unwrap(),
+            "# [讨论]标题\n大漩涡 · 2 pages\n\n#1 BugenZhao · 2020-09-13 20:26\n正文[Image]"
+        );
+        assert!(render_text(&CacheResponse::new()).is_none());
+    }
+}
@@ -1,39 +1,299 @@
+use log::LevelFilter;
 use once_cell::sync::OnceCell;
-use protos::DataModel;
-use std::path::PathBuf;
+use protos::DataModel::{self, Configuration_LogLevel as LogLevel};
+use std::{path::PathBuf, sync::RwLock, time::Duration};
+use thiserror::Error;
 
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("`{field}` should be at most {max}, got {value}")]
+    TooLarge {
+        field: &'static str,
+        value: u64,
+        max: u64,
+    },
+    #[error("`{field}` should be at least {min}, got {value}")]
+    TooSmall {
+        field: &'static str,
+        value: u64,
+        min: u64,
+    },
+}
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
+
+/// Configuration that only takes effect on the first `set_config` after launch.
 #[derive(Debug)]
 pub struct Conf {
     pub document_dir_path: PathBuf,
+    pub storage_backend: DataModel::StorageBackend,
     pub cache_path: PathBuf,
+    pub sqlite_cache_path: PathBuf,
+    pub sled_cache_capacity: u64,
+    pub sled_flush_every_ms: u64,
     pub test_path: PathBuf,
 }
 
 pub static CONF: OnceCell<Conf> = OnceCell::new();
 
-pub fn set_config(config: DataModel::Configuration) {
+/// Configuration that can be changed by `set_config` at any time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub log_level: LevelFilter,
+    /// Rows per page to count the pages of the topics or posts if not given in the response,
+    /// which do not change the page sizes of NGA.
+    pub fallback_topic_rows_per_page: u32,
+    pub fallback_post_rows_per_page: u32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        DEFAULT_TUNING
+    }
+}
+
+const DEFAULT_TUNING: Tuning = Tuning {
+    connect_timeout: Duration::from_secs(5),
+    read_timeout: Duration::from_secs(20),
+    log_level: if cfg!(debug_assertions) {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Info
+    },
+    fallback_topic_rows_per_page: 35,
+    fallback_post_rows_per_page: 20,
+};
+pub const DEFAULT_SLED_CACHE_CAPACITY: u64 = 20 * MIB;
+pub const DEFAULT_SLED_FLUSH_EVERY_MS: u64 = 3000;
+
+static TUNING: RwLock<Tuning> = RwLock::new(DEFAULT_TUNING);
+
+/// Get a snapshot of the current tuning.
+pub fn tuning() -> Tuning {
+    *TUNING.read().unwrap()
+}
+
+/// Resolve the zero value to `default`, then check that it's in `min..=max`.
+fn checked(field: &'static str, value: u64, default: u64, min: u64, max: u64) -> ConfigResult<u64> {
+    match value {
+        0 => Ok(default),
+        v if v < min => Err(ConfigError::TooSmall {
+            field,
+            value: v,
+            min,
+        }),
+        v if v > max => Err(ConfigError::TooLarge {
+            field,
+            value: v,
+            max,
+        }),
+        v => Ok(v),
+    }
+}
+
+fn checked_ms(
+    field: &'static str,
+    value: u64,
+    default: Duration,
+    max: Duration,
+) -> ConfigResult<Duration> {
+    let ms = checked(
+        field,
+        value,
+        default.as_millis() as u64,
+        1,
+        max.as_millis() as u64,
+    )?;
+    Ok(Duration::from_millis(ms))
+}
+
+fn checked_page_size(field: &'static str, value: u32, default: u32) -> ConfigResult<u32> {
+    checked(field, value.into(), default.into(), 1, 200).map(|v| v as u32)
+}
+
+fn log_level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::DEFAULT => DEFAULT_TUNING.log_level,
+        LogLevel::OFF => LevelFilter::Off,
+        LogLevel::ERROR => LevelFilter::Error,
+        LogLevel::WARN => LevelFilter::Warn,
+        LogLevel::INFO => LevelFilter::Info,
+        LogLevel::DEBUG => LevelFilter::Debug,
+        LogLevel::TRACE => LevelFilter::Trace,
+    }
+}
+
+fn log_level_of(filter: LevelFilter) -> LogLevel {
+    match filter {
+        LevelFilter::Off => LogLevel::OFF,
+        LevelFilter::Error => LogLevel::ERROR,
+        LevelFilter::Warn => LogLevel::WARN,
+        LevelFilter::Info => LogLevel::INFO,
+        LevelFilter::Debug => LogLevel::DEBUG,
+        LevelFilter::Trace => LogLevel::TRACE,
+    }
+}
+
+fn tuning_of(config: &DataModel::Configuration) -> ConfigResult<Tuning> {
+    Ok(Tuning {
+        connect_timeout: checked_ms(
+            "connect_timeout_ms",
+            config.connect_timeout_ms,
+            DEFAULT_TUNING.connect_timeout,
+            Duration::from_secs(60),
+        )?,
+        read_timeout: checked_ms(
+            "read_timeout_ms",
+            config.read_timeout_ms,
+            DEFAULT_TUNING.read_timeout,
+            Duration::from_secs(5 * 60),
+        )?,
+        log_level: log_level_filter(config.log_level),
+        fallback_topic_rows_per_page: checked_page_size(
+            "fallback_topic_rows_per_page",
+            config.fallback_topic_rows_per_page,
+            DEFAULT_TUNING.fallback_topic_rows_per_page,
+        )?,
+        fallback_post_rows_per_page: checked_page_size(
+            "fallback_post_rows_per_page",
+            config.fallback_post_rows_per_page,
+            DEFAULT_TUNING.fallback_post_rows_per_page,
+        )?,
+    })
+}
+
+fn conf_of(config: DataModel::Configuration) -> ConfigResult<Conf> {
     let document_dir_path = PathBuf::from(config.document_dir_path);
     let cache_path = {
         let mut path = document_dir_path.clone();
         path.push("logic_cache.sled");
         path
     };
+    let sqlite_cache_path = document_dir_path.join("logic_cache.sqlite3");
     let test_path = {
         let mut path = document_dir_path.clone();
         path.push("test.txt");
         path
     };
 
-    let conf = Conf {
+    Ok(Conf {
+        sled_cache_capacity: checked(
+            "sled_cache_capacity",
+            config.sled_cache_capacity,
+            DEFAULT_SLED_CACHE_CAPACITY,
+            MIB,
+            1024 * MIB,
+        )?,
+        sled_flush_every_ms: checked(
+            "sled_flush_every_ms",
+            config.sled_flush_every_ms,
+            DEFAULT_SLED_FLUSH_EVERY_MS,
+            1,
+            60 * 1000,
+        )?,
         document_dir_path,
+        storage_backend: config.storage_backend,
         cache_path,
+        sqlite_cache_path,
         test_path,
+    })
+}
+
+/// Validate and apply the configuration, returning the effective one. Only the tuning is applied
+/// if it has been configured before, since the cache has been opened with the rest.
+pub fn set_config(config: DataModel::Configuration) -> ConfigResult<DataModel::Configuration> {
+    let tuning = tuning_of(&config)?;
+    let conf = conf_of(config)?;
+
+    if CONF.get().is_some() {
+        log::info!("reconfigure, only the tuning is applied");
+    } else if CONF.set(conf).is_err() {
+        log::warn!("failed to set configuration, maybe already set?")
+    }
+
+    log::set_max_level(tuning.log_level);
+    *TUNING.write().unwrap() = tuning;
+    Ok(effective_config())
+}
+
+/// The configuration in effect with the defaults filled in. The encryption key is taken by the
+/// service instead, and never reported back.
+pub fn effective_config() -> DataModel::Configuration {
+    let tuning = tuning();
+    let mut config = DataModel::Configuration {
+        connect_timeout_ms: tuning.connect_timeout.as_millis() as u64,
+        read_timeout_ms: tuning.read_timeout.as_millis() as u64,
+        log_level: log_level_of(tuning.log_level),
+        fallback_topic_rows_per_page: tuning.fallback_topic_rows_per_page,
+        fallback_post_rows_per_page: tuning.fallback_post_rows_per_page,
+        ..Default::default()
     };
+    if let Some(conf) = CONF.get() {
+        config.document_dir_path = conf.document_dir_path.to_string_lossy().into_owned();
+        config.storage_backend = conf.storage_backend;
+        config.sled_cache_capacity = conf.sled_cache_capacity;
+        config.sled_flush_every_ms = conf.sled_flush_every_ms;
+    }
+    config
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_config() -> ConfigResult<()> {
+        let config = |f: fn(&mut DataModel::Configuration)| {
+            let mut config = DataModel::Configuration {
+                document_dir_path: "/tmp/mnga".to_owned(),
+                cache_encryption_key: vec![7; 32],
+                ..Default::default()
+            };
+            f(&mut config);
+            config
+        };
+
+        let effective = set_config(config(|_| {}))?;
+        assert_eq!(effective.get_document_dir_path(), "/tmp/mnga");
+        assert_eq!(effective.get_connect_timeout_ms(), 5000);
+        assert_eq!(
+            effective.get_sled_cache_capacity(),
+            DEFAULT_SLED_CACHE_CAPACITY
+        );
+        assert_ne!(effective.get_log_level(), LogLevel::DEFAULT);
+        assert!(effective.get_cache_encryption_key().is_empty());
+        assert_eq!(tuning(), Tuning::default());
+
+        assert_eq!(
+            set_config(config(|c| c.read_timeout_ms = 10 * 60 * 1000)),
+            Err(ConfigError::TooLarge {
+                field: "read_timeout_ms",
+                value: 10 * 60 * 1000,
+                max: 5 * 60 * 1000,
+            })
+        );
+        assert!(matches!(
+            set_config(config(|c| c.sled_cache_capacity = 1024)),
+            Err(ConfigError::TooSmall { .. })
+        ));
+        assert!(set_config(config(|c| c.fallback_post_rows_per_page = 201)).is_err());
+        assert_eq!(tuning(), Tuning::default());
+
+        // Only the tuning is applied on reconfiguration.
+        let effective = set_config(config(|c| {
+            c.document_dir_path = "/tmp/other".to_owned();
+            c.fallback_post_rows_per_page = 50;
+            c.log_level = LogLevel::WARN;
+        }))?;
+        assert_eq!(effective.get_document_dir_path(), "/tmp/mnga");
+        assert_eq!(effective.get_fallback_post_rows_per_page(), 50);
+        assert_eq!(effective.get_log_level(), LogLevel::WARN);
+        assert_eq!(tuning().log_level, LevelFilter::Warn);
 
-    match CONF.set(conf) {
-        Ok(_) => {}
-        Err(_) => {
-            log::warn!("failed to set configuration, maybe already set?")
-        }
+        Ok(())
     }
 }
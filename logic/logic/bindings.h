@@ -7,14 +7,27 @@ typedef struct ByteBuffer {
   const uint8_t *ptr;
   uintptr_t len;
   uintptr_t cap;
-  const char *err;
+  /**
+   * Serialized `ServiceErrorResponse` of `err_len` bytes if the request failed, or null.
+   */
+  const uint8_t *err;
+  uintptr_t err_len;
 } ByteBuffer;
 
+/**
+ * Token of a pending async request, which can be used to cancel it.
+ */
+typedef uint64_t RequestToken;
+
 typedef void (*CallbackFn)(const void*, struct ByteBuffer);
 
 typedef struct Callback {
   const void *user_data;
   CallbackFn callback;
+  /**
+   * Called with a serialized `AsyncProgress` before `callback`, can be null.
+   */
+  void (*progress)(const void*, struct ByteBuffer);
 } Callback;
 
 /**
@@ -30,10 +43,35 @@ void rust_init(void);
 struct ByteBuffer rust_call(const uint8_t *data, uintptr_t len);
 
 /**
+ * Same as `rust_call`, but the request and the response are UTF-8 JSON.
+ *
+ * # Safety
+ * totally unsafe
+ */
+struct ByteBuffer rust_call_json(const uint8_t *data, uintptr_t len);
+
+/**
+ * Returns a token for cancelling the request with `rust_cancel`.
+ *
  * # Safety
  * totally unsafe
  */
-void rust_call_async(const uint8_t *data, uintptr_t len, struct Callback callback);
+RequestToken rust_call_async(const uint8_t *data, uintptr_t len, struct Callback callback);
+
+/**
+ * Same as `rust_call_async`, but the request, the progress and the response are UTF-8 JSON.
+ * Returns 0 if the request is invalid, whose error is passed to the callback.
+ *
+ * # Safety
+ * totally unsafe
+ */
+RequestToken rust_call_async_json(const uint8_t *data, uintptr_t len, struct Callback callback);
+
+/**
+ * Cancel the async request, whose callback will still be called with a cancellation error.
+ * Returns false if the request has already finished.
+ */
+bool rust_cancel(RequestToken token);
 
 /**
  * # Safety
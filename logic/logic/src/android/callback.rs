@@ -3,6 +3,7 @@ use jni::{
     JNIEnv, JavaVM,
     objects::{GlobalRef, JObject, JValue},
 };
+use protos::Message;
 use service::error::ServiceResult;
 
 pub struct AndroidCallback {
@@ -27,6 +28,14 @@ impl CallbackTrait for AndroidCallback {
         format!("{:?}", *self.callback.as_obj() as *const _)
     }
 
+    fn progress(&self, data: Vec<u8>) {
+        let env = self.jvm.attach_current_thread().unwrap();
+        let jdata = env.byte_array_from_slice(&data).unwrap();
+
+        env.call_method(self.callback.as_obj(), "progress", "([B)V", &[jdata.into()])
+            .unwrap();
+    }
+
     fn run(self, result: ServiceResult<Vec<u8>>) {
         let env = self.jvm.attach_current_thread().unwrap();
 
@@ -36,17 +45,13 @@ impl CallbackTrait for AndroidCallback {
                 [jdata.into(), JObject::null().into()]
             }
             Err(err) => {
-                let jerr = env.new_string(err.to_app_string()).unwrap();
+                let err = err.to_response().write_to_bytes().unwrap();
+                let jerr = env.byte_array_from_slice(&err).unwrap();
                 [JObject::null().into(), jerr.into()]
             }
         };
 
-        env.call_method(
-            self.callback.as_obj(),
-            "run",
-            "([BLjava/lang/String;)V",
-            &args,
-        )
-        .unwrap();
+        env.call_method(self.callback.as_obj(), "run", "([B[B)V", &args)
+            .unwrap();
     }
 }
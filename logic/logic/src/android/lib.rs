@@ -1,23 +1,47 @@
 use crate::{
-    android::callback::AndroidCallback, r#async::serve_request_async,
-    callback_trait::CallbackTrait, init::may_init, sync::serve_request_sync,
+    android::callback::AndroidCallback,
+    r#async::{cancel_request_async, serve_request_async},
+    callback_trait::CallbackTrait,
+    encoding::Encoding,
+    init::may_init,
+    sync::serve_request_sync,
 };
 use jni::{
     JNIEnv,
-    objects::{JClass, JObject},
-    sys::jbyteArray,
+    objects::{JClass, JObject, JThrowable},
+    sys::{jboolean, jbyteArray, jlong},
 };
 use protos::{
     Message,
     Service::{AsyncRequest, SyncRequest},
 };
+use service::error::ServiceResult;
+
+/// Thrown by `rustCall` with the serialized `ServiceErrorResponse`.
+const LOGIC_EXCEPTION_CLASS: &str = "com/bugenzhao/mnga/LogicException";
 
 fn parse_from_j<T: Message>(env: &JNIEnv, data: jbyteArray) -> T {
     let bytes = env.convert_byte_array(data).unwrap();
     T::parse_from_bytes(&bytes).expect("invalid request")
 }
 
-#[no_mangle]
+/// Return the response, or throw the error as a `LogicException` and return null.
+fn return_to_j(env: &JNIEnv, response_buf: ServiceResult<Vec<u8>>) -> jbyteArray {
+    match response_buf {
+        Ok(data) => env.byte_array_from_slice(&data).unwrap(),
+        Err(err) => {
+            let err = err.to_response().write_to_bytes().unwrap();
+            let jerr = env.byte_array_from_slice(&err).unwrap();
+            let exception = env
+                .new_object(LOGIC_EXCEPTION_CLASS, "([B)V", &[jerr.into()])
+                .unwrap();
+            env.throw(JThrowable::from(exception)).unwrap();
+            JObject::null().into_inner()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
 pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCall(
     env: JNIEnv,
     _: JClass,
@@ -26,27 +50,71 @@ pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCall(
     may_init();
     let request = parse_from_j::<SyncRequest>(&env, data);
     log::info!("request {:?}", request);
-    let response_buf = serve_request_sync(request);
+    let response_buf = serve_request_sync(request, Encoding::Protobuf);
+    return_to_j(&env, response_buf)
+}
 
-    match response_buf {
-        Ok(data) => env.byte_array_from_slice(&data).unwrap(),
-        Err(err) => {
-            env.throw(err.to_app_string()).unwrap();
-            unreachable!()
-        }
-    }
+/// Same as `rustCall`, but the request and the response are UTF-8 JSON. Errors are still thrown
+/// as `LogicException`, including an invalid request.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCallJson(
+    env: JNIEnv,
+    _: JClass,
+    data: jbyteArray,
+) -> jbyteArray {
+    may_init();
+    let bytes = env.convert_byte_array(data).unwrap();
+    let response_buf = Encoding::Json
+        .decode::<SyncRequest>(&bytes)
+        .inspect(|request| log::info!("json request {:?}", request))
+        .and_then(|request| serve_request_sync(request, Encoding::Json));
+    return_to_j(&env, response_buf)
 }
 
-#[no_mangle]
+#[unsafe(no_mangle)]
 pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCallAsync(
     env: JNIEnv,
     _: JClass,
     data: jbyteArray,
     jcallback: JObject,
-) {
+) -> jlong {
     may_init();
     let request = parse_from_j::<AsyncRequest>(&env, data);
     let callback = AndroidCallback::new(&env, jcallback);
     log::info!("async request #{:?} {:?}", callback.id(), request);
-    serve_request_async(request, callback);
+    serve_request_async(request, callback, Encoding::Protobuf) as jlong
+}
+
+/// Same as `rustCallAsync`, but the request, the progress and the response are UTF-8 JSON.
+/// Returns 0 if the request is invalid, whose error is passed to the callback.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCallAsyncJson(
+    env: JNIEnv,
+    _: JClass,
+    data: jbyteArray,
+    jcallback: JObject,
+) -> jlong {
+    may_init();
+    let bytes = env.convert_byte_array(data).unwrap();
+    let callback = AndroidCallback::new(&env, jcallback);
+    match Encoding::Json.decode::<AsyncRequest>(&bytes) {
+        Ok(request) => {
+            log::info!("async json request #{} {:?}", callback.id(), request);
+            serve_request_async(request, callback, Encoding::Json) as jlong
+        }
+        Err(e) => {
+            callback.run(Err(e));
+            0
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_com_bugenzhao_mnga_LogicKt_rustCancel(
+    _env: JNIEnv,
+    _: JClass,
+    token: jlong,
+) -> jboolean {
+    log::info!("cancel async request token={}", token);
+    cancel_request_async(token as u64) as jboolean
 }
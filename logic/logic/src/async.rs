@@ -1,36 +1,83 @@
-use crate::callback_trait::CallbackTrait;
+use crate::{callback_trait::CallbackTrait, encoding::Encoding};
 use lazy_static::lazy_static;
 use protos::Service::*;
-use service::dispatch_async;
-use std::thread;
-use tokio::runtime::Runtime;
+use service::{
+    dispatch_async,
+    error::ServiceError,
+    progress::{ProgressSink, with_progress},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+};
+
+/// Token of a pending async request, which can be used to cancel it.
+pub type RequestToken = u64;
 
 lazy_static! {
     pub static ref RUNTIME: Runtime = {
         log::debug!("creating tokio runtime");
         Runtime::new().expect("failed to create tokio runtime")
     };
+    static ref PENDING: Mutex<HashMap<RequestToken, oneshot::Sender<()>>> = Default::default();
 }
 
-pub fn serve_request_async<Cb>(request: AsyncRequest, callback: Cb)
+// Starts from 1 so that 0 is never a valid token.
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+pub fn serve_request_async<Cb>(
+    request: AsyncRequest,
+    callback: Cb,
+    encoding: Encoding,
+) -> RequestToken
 where
     Cb: CallbackTrait,
 {
     let _guard = RUNTIME.enter();
 
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let (cancel_tx, mut cancel_rx) = oneshot::channel();
+    PENDING.lock().unwrap().insert(token, cancel_tx);
+    // Progress is passed back to this task, so that the callback is only used in one place.
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel::<AsyncProgress>();
+    let sink: ProgressSink = Arc::new(move |progress| {
+        let _ = progress_tx.send(progress);
+    });
+
     tokio::spawn(async move {
         let _ = &request;
         log::debug!("serving async request on {:?}", thread::current());
 
         let request = request.value.expect("no async req");
-        let response = dispatch_async(request).await;
+        let dispatch = with_progress(sink, dispatch_async(request));
+        tokio::pin!(dispatch);
+        // Dropping the future aborts the request, including the fetches it's waiting for.
+        let response = loop {
+            tokio::select! {
+                biased;
+                Ok(()) = &mut cancel_rx => break Err(ServiceError::Cancelled),
+                Some(progress) = progress_rx.recv() => {
+                    callback.progress(encoding.encode(&progress))
+                }
+                response = &mut dispatch => break response,
+            }
+        };
+        PENDING.lock().unwrap().remove(&token);
+        // Progress reported right before finishing.
+        while let Ok(progress) = progress_rx.try_recv() {
+            callback.progress(encoding.encode(&progress));
+        }
 
         let result = response
-            .map(|response| {
-                let mut response_buf = Vec::with_capacity(response.compute_size() as usize + 1);
-                response.write_to_vec(&mut response_buf).unwrap();
-                response_buf
-            })
+            .map(|response| encoding.encode(response.as_ref()))
             .inspect_err(|e| {
                 log::error!(
                     "error when serving async request #{}: {}",
@@ -41,4 +88,15 @@ where
 
         callback.run(result);
     });
+
+    token
+}
+
+/// Cancel the pending async request, whose callback will then receive `ServiceError::Cancelled`.
+/// Returns false if the request has already finished.
+pub fn cancel_request_async(token: RequestToken) -> bool {
+    match PENDING.lock().unwrap().remove(&token) {
+        Some(cancel_tx) => cancel_tx.send(()).is_ok(),
+        None => false,
+    }
 }
@@ -0,0 +1,23 @@
+use std::{env, net::SocketAddr, process::exit};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:8964";
+
+fn main() {
+    let addr = env::args().nth(1);
+    let addr = addr.as_deref().unwrap_or(DEFAULT_ADDR);
+    let addr = match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => {
+            eprintln!(
+                "error: invalid address `{}`\n\nusage: mnga-gateway [addr]",
+                addr
+            );
+            exit(2);
+        }
+    };
+
+    if let Err(e) = logic::gateway::run(addr) {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
@@ -1,14 +1,18 @@
-use std::{ffi, mem, os::raw::c_char, ptr};
+use std::{mem, ptr};
 
 use service::error::ServiceResult;
 
+use crate::encoding::Encoding;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ByteBuffer {
     pub ptr: *const u8,
     pub len: usize,
     pub cap: usize,
-    pub err: *const c_char,
+    /// Serialized `ServiceErrorResponse` of `err_len` bytes if the request failed, or null.
+    pub err: *const u8,
+    pub err_len: usize,
 }
 
 impl From<Vec<u8>> for ByteBuffer {
@@ -19,6 +23,7 @@ impl From<Vec<u8>> for ByteBuffer {
             len: v.len(),
             cap: v.capacity(),
             err: ptr::null(),
+            err_len: 0,
         };
         log::trace!("new buffer {:?}", ret);
         mem::forget(v);
@@ -27,22 +32,22 @@ impl From<Vec<u8>> for ByteBuffer {
 }
 
 impl ByteBuffer {
-    fn from_err_string(string: String) -> Self {
-        let err_string = ffi::CString::new(string).unwrap();
+    fn from_err(err: Vec<u8>) -> Self {
+        let err = Box::into_raw(err.into_boxed_slice());
         Self {
             ptr: ptr::null(),
             len: 0,
             cap: 0,
-            err: err_string.into_raw(),
+            err: err as *const u8,
+            err_len: err.len(),
         }
     }
-}
 
-impl From<ServiceResult<Vec<u8>>> for ByteBuffer {
-    fn from(result: ServiceResult<Vec<u8>>) -> Self {
+    /// Create from the result of a request, whose error is encoded in the same way as the response.
+    pub fn from_result(result: ServiceResult<Vec<u8>>, encoding: Encoding) -> Self {
         match result {
             Ok(v) => Self::from(v),
-            Err(e) => Self::from_err_string(e.to_app_string()),
+            Err(e) => Self::from_err(encoding.encode(&e.to_response())),
         }
     }
 }
@@ -1,5 +1,5 @@
 use super::byte_buffer::ByteBuffer;
-use crate::callback_trait::CallbackTrait;
+use crate::{callback_trait::CallbackTrait, encoding::Encoding};
 use service::error::ServiceResult;
 use std::{ffi::c_void, mem};
 
@@ -10,6 +10,8 @@ type CallbackFn = extern "C" fn(*const c_void, ByteBuffer);
 pub struct Callback {
     pub user_data: *const c_void,
     pub callback: CallbackFn,
+    /// Called with a serialized `AsyncProgress` before `callback`, can be null.
+    pub progress: Option<extern "C" fn(*const c_void, ByteBuffer)>,
 }
 unsafe impl Send for Callback {}
 
@@ -17,17 +19,29 @@ impl Callback {
     /// # Safety
     /// total unsafe
     #[allow(dead_code)]
-    pub unsafe fn new(user_data: *const c_void, callback: *const c_void) -> Self {
+    pub unsafe fn new(
+        user_data: *const c_void,
+        callback: *const c_void,
+        progress: *const c_void,
+    ) -> Self {
         Self {
             user_data,
             callback: unsafe { mem::transmute::<*const c_void, CallbackFn>(callback) },
+            progress: (!progress.is_null())
+                .then(|| unsafe { mem::transmute::<*const c_void, CallbackFn>(progress) }),
         }
     }
 }
 
 impl CallbackTrait for Callback {
+    fn progress(&self, data: Vec<u8>) {
+        if let Some(progress) = self.progress {
+            progress(self.user_data, ByteBuffer::from(data))
+        }
+    }
+
     fn run(self, result: ServiceResult<Vec<u8>>) {
-        let byte_buffer = ByteBuffer::from(result);
+        let byte_buffer = ByteBuffer::from_result(result, Encoding::Protobuf);
         (self.callback)(self.user_data, byte_buffer)
     }
 
@@ -36,6 +50,25 @@ impl CallbackTrait for Callback {
     }
 }
 
+/// Callback of the requests in JSON, whose errors are also in JSON.
+#[derive(Debug)]
+pub struct JsonCallback(pub Callback);
+
+impl CallbackTrait for JsonCallback {
+    fn progress(&self, data: Vec<u8>) {
+        self.0.progress(data)
+    }
+
+    fn run(self, result: ServiceResult<Vec<u8>>) {
+        let byte_buffer = ByteBuffer::from_result(result, Encoding::Json);
+        (self.0.callback)(self.0.user_data, byte_buffer)
+    }
+
+    fn id(&self) -> String {
+        self.0.id()
+    }
+}
+
 impl Drop for Callback {
     fn drop(&mut self) {
         log::trace!("{:?} at {:?} dropped!", self, &self as *const _)
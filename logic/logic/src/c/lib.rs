@@ -1,12 +1,18 @@
-use super::{byte_buffer::ByteBuffer, callback::Callback};
+use super::{
+    byte_buffer::ByteBuffer,
+    callback::{Callback, JsonCallback},
+};
 use crate::{
-    r#async::serve_request_async, callback_trait::CallbackTrait, sync::serve_request_sync,
+    r#async::{RequestToken, cancel_request_async, serve_request_async},
+    callback_trait::CallbackTrait,
+    encoding::Encoding,
+    sync::serve_request_sync,
 };
 use protos::{
     Message,
     Service::{AsyncRequest, SyncRequest},
 };
-use std::{ffi, slice};
+use std::{ptr, slice};
 
 unsafe fn parse_from_raw<T: Message>(data: *const u8, len: usize) -> T {
     let bytes = unsafe { slice::from_raw_parts(data, len) };
@@ -27,18 +33,71 @@ pub unsafe extern "C" fn rust_init() {
 pub unsafe extern "C" fn rust_call(data: *const u8, len: usize) -> ByteBuffer {
     let request = unsafe { parse_from_raw::<SyncRequest>(data, len) };
     log::info!("request {:?}", request);
-    let response_buf = serve_request_sync(request);
-    ByteBuffer::from(response_buf)
+    let response_buf = serve_request_sync(request, Encoding::Protobuf);
+    ByteBuffer::from_result(response_buf, Encoding::Protobuf)
 }
 
+/// Same as `rust_call`, but the request and the response are UTF-8 JSON.
+///
 /// # Safety
 /// totally unsafe
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn rust_call_async(data: *const u8, len: usize, callback: Callback) {
+pub unsafe extern "C" fn rust_call_json(data: *const u8, len: usize) -> ByteBuffer {
+    let bytes = unsafe { slice::from_raw_parts(data, len) };
+    let response_buf = Encoding::Json
+        .decode::<SyncRequest>(bytes)
+        .inspect(|request| log::info!("json request {:?}", request))
+        .and_then(|request| serve_request_sync(request, Encoding::Json));
+    ByteBuffer::from_result(response_buf, Encoding::Json)
+}
+
+/// Returns a token for cancelling the request with `rust_cancel`.
+///
+/// # Safety
+/// totally unsafe
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rust_call_async(
+    data: *const u8,
+    len: usize,
+    callback: Callback,
+) -> RequestToken {
     log::trace!("get {:?} at {:?}", callback, &callback as *const _);
     let request = unsafe { parse_from_raw::<AsyncRequest>(data, len) };
     log::info!("async request #{} {:?}", callback.id(), request);
-    serve_request_async(request, callback);
+    serve_request_async(request, callback, Encoding::Protobuf)
+}
+
+/// Same as `rust_call_async`, but the request, the progress and the response are UTF-8 JSON.
+/// Returns 0 if the request is invalid, whose error is passed to the callback.
+///
+/// # Safety
+/// totally unsafe
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rust_call_async_json(
+    data: *const u8,
+    len: usize,
+    callback: Callback,
+) -> RequestToken {
+    let callback = JsonCallback(callback);
+    let bytes = unsafe { slice::from_raw_parts(data, len) };
+    match Encoding::Json.decode::<AsyncRequest>(bytes) {
+        Ok(request) => {
+            log::info!("async json request #{} {:?}", callback.id(), request);
+            serve_request_async(request, callback, Encoding::Json)
+        }
+        Err(e) => {
+            callback.run(Err(e));
+            0
+        }
+    }
+}
+
+/// Cancel the async request, whose callback will still be called with a cancellation error.
+/// Returns false if the request has already finished.
+#[unsafe(no_mangle)]
+pub extern "C" fn rust_cancel(token: RequestToken) -> bool {
+    log::info!("cancel async request token={}", token);
+    cancel_request_async(token)
 }
 
 /// # Safety
@@ -46,14 +105,20 @@ pub unsafe extern "C" fn rust_call_async(data: *const u8, len: usize, callback:
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn rust_free(byte_buffer: ByteBuffer) {
     log::trace!("free buffer {:?}", byte_buffer);
-    let ByteBuffer { ptr, len, cap, err } = byte_buffer;
+    let ByteBuffer {
+        ptr,
+        len,
+        cap,
+        err,
+        err_len,
+    } = byte_buffer;
 
     if !ptr.is_null() {
         let buf = unsafe { Vec::from_raw_parts(ptr as *mut u8, len, cap) };
         drop(buf);
     }
     if !err.is_null() {
-        let err_string = unsafe { ffi::CString::from_raw(err as *mut _) };
-        drop(err_string)
+        let err = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(err as *mut u8, err_len)) };
+        drop(err)
     }
 }
@@ -2,5 +2,7 @@ use service::error::ServiceResult;
 
 pub trait CallbackTrait: Send + 'static {
     fn id(&self) -> String;
+    /// Called with a serialized `AsyncProgress` zero or more times before `run`.
+    fn progress(&self, data: Vec<u8>);
     fn run(self, result: ServiceResult<Vec<u8>>);
 }
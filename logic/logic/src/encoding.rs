@@ -0,0 +1,30 @@
+use protos::Message;
+use service::error::ServiceResult;
+
+/// Encoding of the requests and responses across the FFI boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Protobuf,
+    /// UTF-8 JSON following the proto3 JSON mapping, which is easier to debug.
+    Json,
+}
+
+impl Encoding {
+    pub fn decode<M: Message>(self, bytes: &[u8]) -> ServiceResult<M> {
+        Ok(match self {
+            Encoding::Protobuf => M::parse_from_bytes(bytes)?,
+            Encoding::Json => protos::json::parse_from_str(&String::from_utf8_lossy(bytes))?,
+        })
+    }
+
+    pub fn encode(self, message: &dyn Message) -> Vec<u8> {
+        match self {
+            Encoding::Protobuf => {
+                let mut buf = Vec::with_capacity(message.compute_size() as usize + 1);
+                message.write_to_vec(&mut buf).unwrap();
+                buf
+            }
+            Encoding::Json => protos::json::print_to_string(message).into_bytes(),
+        }
+    }
+}
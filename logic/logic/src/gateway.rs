@@ -0,0 +1,274 @@
+//! Local HTTP gateway of the dispatch layer, for driving the logic from scripts and desktop
+//! prototypes without FFI.
+//!
+//! - `POST /sync/{variant}` and `POST /async/{variant}` serve the request of the oneof variant
+//!   of `SyncRequest` and `AsyncRequest`, e.g. `TopicListRequest` for `/async/topic_list`. The
+//!   body is in protobuf, or in JSON if `Content-Type` is `application/json`, and so is the
+//!   response.
+//! - `GET /notifications?interval=60` streams the new notifications in JSON as server-sent
+//!   events, by fetching them every `interval` seconds.
+//!
+//! Errors are responded as plain text of `ServiceError::to_app_string`, like the FFI does.
+
+use crate::{
+    r#async::{RUNTIME, RequestToken, cancel_request_async, serve_request_async},
+    callback_trait::CallbackTrait,
+    encoding::Encoding,
+    sync::serve_request_sync,
+};
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use futures::{Stream, StreamExt, stream};
+use protos::{
+    CodedOutputStream, Message,
+    Service::{AsyncRequest, AsyncRequest_oneof_value, FetchNotificationResponse, SyncRequest},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use service::{
+    dispatch_async,
+    error::{ServiceError, ServiceResult},
+};
+use std::{collections::HashSet, convert::Infallible, net::SocketAddr, time::Duration};
+use tokio::{net::TcpListener, sync::oneshot};
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+const DEFAULT_NOTI_INTERVAL_SECS: u64 = 60;
+const MIN_NOTI_INTERVAL_SECS: u64 = 10;
+
+fn encoding_of(headers: &HeaderMap) -> Encoding {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with(JSON_CONTENT_TYPE) {
+        Encoding::Json
+    } else {
+        Encoding::Protobuf
+    }
+}
+
+fn error_response(status: StatusCode, error: ServiceError) -> Response {
+    (status, error.to_app_string()).into_response()
+}
+
+fn respond(result: ServiceResult<Vec<u8>>, encoding: Encoding) -> Response {
+    match result {
+        Ok(body) => {
+            let content_type = match encoding {
+                Encoding::Protobuf => PROTOBUF_CONTENT_TYPE,
+                Encoding::Json => JSON_CONTENT_TYPE,
+            };
+            ([(CONTENT_TYPE, content_type)], body).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// Build the `SyncRequest` or `AsyncRequest` whose oneof `variant` is set to the message in `body`.
+fn build_request<M: Message>(variant: &str, body: &[u8], encoding: Encoding) -> ServiceResult<M> {
+    let field = M::descriptor_static()
+        .fields()
+        .iter()
+        .find(|f| f.name() == variant)
+        .ok_or_else(|| ServiceError::MngaInternal(format!("Unknown request `{}`", variant)))?;
+
+    match encoding {
+        Encoding::Protobuf => {
+            // The request only consists of the variant field, which is length-delimited.
+            let mut buf = Vec::with_capacity(body.len() + 8);
+            let mut os = CodedOutputStream::vec(&mut buf);
+            os.write_bytes(field.proto().get_number() as u32, body)?;
+            os.flush()?;
+            drop(os);
+            Encoding::Protobuf.decode(&buf)
+        }
+        Encoding::Json => {
+            let value = if body.is_empty() {
+                Value::Object(Default::default())
+            } else {
+                serde_json::from_slice(body)?
+            };
+            let request = Value::Object([(variant.to_owned(), value)].into_iter().collect());
+            Ok(protos::json::parse_from_json(&request)?)
+        }
+    }
+}
+
+async fn serve_sync(Path(variant): Path<String>, headers: HeaderMap, body: Bytes) -> Response {
+    let encoding = encoding_of(&headers);
+    let request = match build_request::<SyncRequest>(&variant, &body, encoding) {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    log::info!("gateway request {:?}", request);
+
+    let result = tokio::task::spawn_blocking(move || serve_request_sync(request, encoding))
+        .await
+        .unwrap_or_else(|e| Err(ServiceError::Panic(e.to_string())));
+    respond(result, encoding)
+}
+
+struct GatewayCallback {
+    id: String,
+    tx: oneshot::Sender<ServiceResult<Vec<u8>>>,
+}
+
+impl CallbackTrait for GatewayCallback {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn progress(&self, _data: Vec<u8>) {}
+
+    fn run(self, result: ServiceResult<Vec<u8>>) {
+        let _ = self.tx.send(result);
+    }
+}
+
+/// Cancel the async request if the client goes away, which is a no-op if it has finished.
+struct CancelOnDrop(RequestToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        cancel_request_async(self.0);
+    }
+}
+
+async fn serve_async(Path(variant): Path<String>, headers: HeaderMap, body: Bytes) -> Response {
+    let encoding = encoding_of(&headers);
+    let request = match build_request::<AsyncRequest>(&variant, &body, encoding) {
+        Ok(request) => request,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    let (tx, rx) = oneshot::channel();
+    let callback = GatewayCallback { id: variant, tx };
+    log::info!("gateway async request #{} {:?}", callback.id(), request);
+    let _cancel = CancelOnDrop(serve_request_async(request, callback, encoding));
+
+    let result = rx
+        .await
+        .unwrap_or_else(|_| Err(ServiceError::Panic("request dropped".to_owned())));
+    respond(result, encoding)
+}
+
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    interval: Option<u64>,
+}
+
+/// Fetch the notifications, and return the events of the unread ones not in `seen`.
+async fn poll_notifications(seen: &mut HashSet<String>) -> Vec<Event> {
+    let request = AsyncRequest_oneof_value::fetch_notification(Default::default());
+    let response = match dispatch_async(request).await {
+        Ok(response) => response,
+        Err(e) => return vec![Event::default().event("error").data(e.to_app_string())],
+    };
+    let Some(response) = response
+        .as_any()
+        .downcast_ref::<FetchNotificationResponse>()
+    else {
+        return vec![];
+    };
+
+    response
+        .get_notis()
+        .iter()
+        .filter(|noti| seen.insert(noti.get_id().to_owned()) && !noti.get_read())
+        .map(|noti| {
+            Event::default()
+                .event("notification")
+                .data(protos::json::print_to_string(noti))
+        })
+        .collect()
+}
+
+async fn stream_notifications(
+    Query(query): Query<NotificationsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let interval = query
+        .interval
+        .unwrap_or(DEFAULT_NOTI_INTERVAL_SECS)
+        .max(MIN_NOTI_INTERVAL_SECS);
+    let ticker = tokio::time::interval(Duration::from_secs(interval));
+
+    let events = stream::unfold(
+        (ticker, HashSet::new()),
+        |(mut ticker, mut seen)| async move {
+            ticker.tick().await;
+            let events = poll_notifications(&mut seen).await;
+            Some((stream::iter(events).map(Ok), (ticker, seen)))
+        },
+    )
+    .flatten();
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn router() -> Router {
+    Router::new()
+        .route("/sync/{variant}", post(serve_sync))
+        .route("/async/{variant}", post(serve_async))
+        .route("/notifications", get(stream_notifications))
+}
+
+/// Serve the gateway on `addr` with the runtime of the async requests, until it fails.
+pub fn run(addr: SocketAddr) -> std::io::Result<()> {
+    crate::init::init();
+
+    RUNTIME.block_on(async move {
+        let listener = TcpListener::bind(addr).await?;
+        log::info!("gateway listening on {}", listener.local_addr()?);
+        axum::serve(listener, router()).await
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protos::Service::{SyncRequest_oneof_value, TopicListRequest};
+
+    #[test]
+    fn test_build_request() {
+        let topic_list = TopicListRequest {
+            page: 2,
+            ..Default::default()
+        };
+
+        let body = topic_list.write_to_bytes().unwrap();
+        let request =
+            build_request::<AsyncRequest>("topic_list", &body, Encoding::Protobuf).unwrap();
+        assert_eq!(
+            request.value,
+            Some(AsyncRequest_oneof_value::topic_list(topic_list.clone()))
+        );
+
+        let request =
+            build_request::<AsyncRequest>("topic_list", br#"{"page": 2}"#, Encoding::Json).unwrap();
+        assert_eq!(
+            request.value,
+            Some(AsyncRequest_oneof_value::topic_list(topic_list))
+        );
+
+        let request = build_request::<SyncRequest>("account_list", b"", Encoding::Json).unwrap();
+        assert!(matches!(
+            request.value,
+            Some(SyncRequest_oneof_value::account_list(_))
+        ));
+
+        assert!(build_request::<SyncRequest>("topic_list", b"", Encoding::Json).is_err());
+        assert!(build_request::<AsyncRequest>("topic_list", b"{", Encoding::Json).is_err());
+    }
+}
@@ -1,11 +1,8 @@
 pub(crate) fn init() {
     #[cfg(not(target_os = "android"))]
     env_logger::builder()
-        .filter_level(if cfg!(debug_assertions) {
-            log::LevelFilter::Trace
-        } else {
-            log::LevelFilter::Info
-        })
+        // Filtered by the max level instead, which can be configured at runtime.
+        .filter_level(log::LevelFilter::Trace)
         .filter_module("sled", log::LevelFilter::Info) // too verbose
         .init();
 
@@ -16,5 +13,7 @@ pub(crate) fn init() {
     #[cfg(target_os = "android")]
     android_logger::init_once(android_logger::Config::default().with_min_level(log::Level::Debug));
 
+    log::set_max_level(config::tuning().log_level);
+
     log::info!("initialized logic");
 }
@@ -1,8 +1,12 @@
 mod r#async;
 mod callback_trait;
+mod encoding;
 mod init;
 mod sync;
 
+#[cfg(feature = "gateway")]
+pub mod gateway;
+
 #[cfg(target_os = "android")]
 mod android;
 #[cfg(not(target_os = "android"))]
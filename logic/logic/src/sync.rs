@@ -3,9 +3,9 @@ use service::dispatch_sync;
 use service::error::ServiceResult;
 use std::thread;
 
-use crate::r#async::RUNTIME;
+use crate::{r#async::RUNTIME, encoding::Encoding};
 
-pub fn serve_request_sync(request: SyncRequest) -> ServiceResult<Vec<u8>> {
+pub fn serve_request_sync(request: SyncRequest, encoding: Encoding) -> ServiceResult<Vec<u8>> {
     // Still enter the async runtime in case of calling `tokio::spawn`.
     let _guard = RUNTIME.enter();
 
@@ -17,11 +17,7 @@ pub fn serve_request_sync(request: SyncRequest) -> ServiceResult<Vec<u8>> {
     let response = dispatch_sync(request);
 
     response
-        .map(|response| {
-            let mut response_buf = Vec::with_capacity(response.compute_size() as usize + 1);
-            response.write_to_vec(&mut response_buf).unwrap();
-            response_buf
-        })
+        .map(|response| encoding.encode(response.as_ref()))
         .inspect_err(|e| {
             log::error!(
                 "error when serving sync request #{}: {}",
@@ -0,0 +1,443 @@
+//! JSON encoding of the messages, following the proto3 JSON mapping.
+//!
+//! rust-protobuf 2 has no JSON support and its reflection is read-only. So messages are printed
+//! with the reflection, and parsed by transcoding the JSON into the binary format with the
+//! descriptors of the proto files.
+
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use lazy_static::lazy_static;
+use protobuf::{
+    CodedOutputStream, Message, ProtobufError,
+    descriptor::{
+        DescriptorProto, EnumDescriptorProto, FieldDescriptorProto,
+        FieldDescriptorProto_Label as Label, FieldDescriptorProto_Type as Type,
+        FileDescriptorProto,
+    },
+    reflect::{ReflectFieldRef, ReflectValueRef},
+};
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+use crate::generated::{DataModel, Service};
+
+#[derive(Error, Debug)]
+pub enum JsonError {
+    #[error(transparent)]
+    Syntax(#[from] serde_json::Error),
+    #[error(transparent)]
+    Protobuf(#[from] ProtobufError),
+    #[error("{0}")]
+    Mapping(String),
+}
+
+pub type JsonResult<T> = Result<T, JsonError>;
+
+fn mapping_error<T>(message: String) -> JsonResult<T> {
+    Err(JsonError::Mapping(message))
+}
+
+enum TypeProto {
+    Message(&'static DescriptorProto),
+    Enum(&'static EnumDescriptorProto),
+}
+
+fn collect_types(
+    prefix: &str,
+    messages: &'static [DescriptorProto],
+    enums: &'static [EnumDescriptorProto],
+    types: &mut HashMap<String, TypeProto>,
+) {
+    for e in enums {
+        types.insert(format!("{}.{}", prefix, e.get_name()), TypeProto::Enum(e));
+    }
+    for m in messages {
+        let name = format!("{}.{}", prefix, m.get_name());
+        collect_types(&name, m.get_nested_type(), m.get_enum_type(), types);
+        types.insert(name, TypeProto::Message(m));
+    }
+}
+
+lazy_static! {
+    /// Descriptors of all messages and enums, keyed by the full name like `.Topic`.
+    static ref TYPES: HashMap<String, TypeProto> = {
+        let files: [&'static FileDescriptorProto; 2] = [
+            DataModel::file_descriptor_proto(),
+            Service::file_descriptor_proto(),
+        ];
+        let mut types = HashMap::new();
+        for file in files {
+            let prefix = match file.get_package() {
+                "" => String::new(),
+                package => format!(".{}", package),
+            };
+            collect_types(&prefix, file.get_message_type(), file.get_enum_type(), &mut types);
+        }
+        types
+    };
+}
+
+/// The lowerCamelCase name of the field, e.g. `topicId` for `topic_id`.
+fn json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            json_name.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+fn float_to_json(v: f64) -> Value {
+    match Number::from_f64(v) {
+        Some(n) => Value::Number(n),
+        None if v.is_nan() => "NaN".into(),
+        None if v > 0.0 => "Infinity".into(),
+        None => "-Infinity".into(),
+    }
+}
+
+fn value_to_json(value: ReflectValueRef) -> Value {
+    match value {
+        ReflectValueRef::U32(v) => v.into(),
+        // 64-bit integers are strings to avoid losing precision in JavaScript.
+        ReflectValueRef::U64(v) => v.to_string().into(),
+        ReflectValueRef::I32(v) => v.into(),
+        ReflectValueRef::I64(v) => v.to_string().into(),
+        ReflectValueRef::F32(v) => float_to_json(v as f64),
+        ReflectValueRef::F64(v) => float_to_json(v),
+        ReflectValueRef::Bool(v) => v.into(),
+        ReflectValueRef::String(v) => v.into(),
+        ReflectValueRef::Bytes(v) => STANDARD.encode(v).into(),
+        ReflectValueRef::Enum(v) => v.name().into(),
+        ReflectValueRef::Message(m) => to_json(m),
+    }
+}
+
+/// Convert the message to a JSON object. Fields with default values are omitted.
+pub fn to_json(message: &dyn Message) -> Value {
+    let mut object = Map::new();
+    for field in message.descriptor().fields() {
+        let value = match field.get_reflect(message) {
+            ReflectFieldRef::Repeated(repeated) if repeated.len() > 0 => Value::Array(
+                (repeated.into_iter())
+                    .map(|v| value_to_json(v.as_ref()))
+                    .collect(),
+            ),
+            ReflectFieldRef::Map(map) if map.len() > 0 => Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| {
+                        let key = match value_to_json(k.as_ref()) {
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        };
+                        (key, value_to_json(v.as_ref()))
+                    })
+                    .collect(),
+            ),
+            ReflectFieldRef::Optional(Some(value)) => value_to_json(value),
+            _ => continue,
+        };
+        object.insert(json_name(field.name()), value);
+    }
+    Value::Object(object)
+}
+
+pub fn print_to_string(message: &dyn Message) -> String {
+    to_json(message).to_string()
+}
+
+fn parse_int<T: std::str::FromStr + TryFrom<i64> + TryFrom<u64>>(value: &Value) -> Option<T> {
+    match value {
+        Value::Number(n) => {
+            if let Some(v) = n.as_u64() {
+                T::try_from(v).ok()
+            } else if let Some(v) = n.as_i64() {
+                T::try_from(v).ok()
+            } else {
+                // Integers may be written in the exponent notation like `1e3`.
+                let v = n.as_f64()?;
+                (v.fract() == 0.0 && v.abs() < 2f64.powi(63))
+                    .then(|| T::try_from(v as i64).ok())
+                    .flatten()
+            }
+        }
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+fn parse_enum(type_name: &str, value: &Value) -> Option<i32> {
+    let Some(TypeProto::Enum(e)) = TYPES.get(type_name) else {
+        return None;
+    };
+    match value {
+        Value::String(s) => (e.get_value().iter())
+            .find(|v| v.get_name() == s)
+            .map(|v| v.get_number()),
+        // Unknown values are preserved as is.
+        value => parse_int(value),
+    }
+}
+
+fn message_type(type_name: &str) -> JsonResult<&'static DescriptorProto> {
+    match TYPES.get(type_name) {
+        Some(TypeProto::Message(m)) => Ok(m),
+        _ => mapping_error(format!("unknown message type `{}`", type_name)),
+    }
+}
+
+/// Write a single value of the field to the output.
+fn write_value(
+    field: &FieldDescriptorProto,
+    value: &Value,
+    os: &mut CodedOutputStream,
+) -> JsonResult<()> {
+    let number = field.get_number() as u32;
+    let invalid = || {
+        JsonError::Mapping(format!(
+            "invalid value for field `{}`: {}",
+            field.get_name(),
+            value
+        ))
+    };
+
+    match field.get_field_type() {
+        Type::TYPE_DOUBLE => os.write_double(number, parse_float(value).ok_or_else(invalid)?)?,
+        Type::TYPE_FLOAT => {
+            os.write_float(number, parse_float(value).ok_or_else(invalid)? as f32)?
+        }
+        Type::TYPE_INT64 => os.write_int64(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_SINT64 => os.write_sint64(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_SFIXED64 => os.write_sfixed64(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_UINT64 => os.write_uint64(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_FIXED64 => os.write_fixed64(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_INT32 => os.write_int32(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_SINT32 => os.write_sint32(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_SFIXED32 => os.write_sfixed32(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_UINT32 => os.write_uint32(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_FIXED32 => os.write_fixed32(number, parse_int(value).ok_or_else(invalid)?)?,
+        Type::TYPE_BOOL => {
+            let v = match value {
+                Value::Bool(b) => *b,
+                // Map keys are always strings.
+                Value::String(s) => s.parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            };
+            os.write_bool(number, v)?
+        }
+        Type::TYPE_STRING => os.write_string(number, value.as_str().ok_or_else(invalid)?)?,
+        Type::TYPE_BYTES => {
+            let bytes = (value.as_str())
+                .and_then(|s| STANDARD.decode(s).ok())
+                .ok_or_else(invalid)?;
+            os.write_bytes(number, &bytes)?
+        }
+        Type::TYPE_ENUM => {
+            let v = parse_enum(field.get_type_name(), value).ok_or_else(invalid)?;
+            os.write_enum(number, v)?
+        }
+        Type::TYPE_MESSAGE => {
+            let bytes = transcode(message_type(field.get_type_name())?, value)?;
+            os.write_bytes(number, &bytes)?
+        }
+        Type::TYPE_GROUP => return Err(invalid()),
+    }
+    Ok(())
+}
+
+/// Transcode the JSON object into the binary format of the message type.
+fn transcode(message: &DescriptorProto, json: &Value) -> JsonResult<Vec<u8>> {
+    let Value::Object(object) = json else {
+        return mapping_error(format!(
+            "expect an object for `{}`, got {}",
+            message.get_name(),
+            json
+        ));
+    };
+
+    let mut bytes = Vec::new();
+    let mut os = CodedOutputStream::vec(&mut bytes);
+
+    for (key, value) in object {
+        let field = (message.get_field().iter())
+            .find(|f| f.get_name() == key || json_name(f.get_name()) == *key)
+            .ok_or_else(|| {
+                JsonError::Mapping(format!(
+                    "unknown field `{}` of `{}`",
+                    key,
+                    message.get_name()
+                ))
+            })?;
+
+        match value {
+            Value::Null => {}
+            Value::Array(values) if field.get_label() == Label::LABEL_REPEATED => {
+                for value in values {
+                    write_value(field, value, &mut os)?;
+                }
+            }
+            Value::Object(entries) if field.get_label() == Label::LABEL_REPEATED => {
+                // Map fields are repeated entries with `key` and `value` fields.
+                let entry = message_type(field.get_type_name())?;
+                if !entry.get_options().get_map_entry() {
+                    return mapping_error(format!("expect an array for `{}`", key));
+                }
+                for (k, v) in entries {
+                    let entry_json = serde_json::json!({ "key": k, "value": v });
+                    let entry_bytes = transcode(entry, &entry_json)?;
+                    os.write_bytes(field.get_number() as u32, &entry_bytes)?;
+                }
+            }
+            _ if field.get_label() == Label::LABEL_REPEATED => {
+                return mapping_error(format!("expect an array for `{}`", key));
+            }
+            value => write_value(field, value, &mut os)?,
+        }
+    }
+
+    os.flush()?;
+    drop(os);
+    Ok(bytes)
+}
+
+/// Merge the fields in the JSON object into the message.
+pub fn merge_from_json(message: &mut dyn Message, json: &Value) -> JsonResult<()> {
+    let bytes = transcode(message.descriptor().get_proto(), json)?;
+    message.merge_from_bytes(&bytes)?;
+    Ok(())
+}
+
+pub fn parse_from_json<M: Message>(json: &Value) -> JsonResult<M> {
+    let mut message = M::new();
+    merge_from_json(&mut message, json)?;
+    Ok(message)
+}
+
+pub fn parse_from_str<M: Message>(s: &str) -> JsonResult<M> {
+    parse_from_json(&serde_json::from_str(s)?)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        DataModel::{ForumId, ForumId_oneof_id, Topic},
+        Service::{
+            AsyncProgress, AsyncRequest, AsyncRequest_oneof_value, HotTopicListRequest,
+            HotTopicListRequest_DateRange, TopicListRequest,
+        },
+    };
+
+    #[test]
+    fn test_to_json() {
+        let request = TopicListRequest {
+            id: Some(ForumId {
+                id: Some(ForumId_oneof_id::fid("-7".to_owned())),
+                ..Default::default()
+            })
+            .into(),
+            page: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            to_json(&request),
+            json!({ "id": { "fid": "-7" }, "page": 2 })
+        );
+
+        let topic = Topic {
+            id: "233".to_owned(),
+            tags: vec!["Tag".to_owned()].into(),
+            post_date: 1625000000,
+            ..Default::default()
+        };
+        assert_eq!(
+            to_json(&topic),
+            json!({ "id": "233", "tags": ["Tag"], "postDate": "1625000000" })
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let request = AsyncRequest {
+            value: Some(AsyncRequest_oneof_value::hot_topic_list(
+                HotTopicListRequest {
+                    id: Some(ForumId {
+                        id: Some(ForumId_oneof_id::stid("12345".to_owned())),
+                        ..Default::default()
+                    })
+                    .into(),
+                    range: HotTopicListRequest_DateRange::WEEK,
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        let json = print_to_string(&request);
+        assert_eq!(
+            json,
+            r#"{"hotTopicList":{"id":{"stid":"12345"},"range":"WEEK"}}"#
+        );
+        assert_eq!(parse_from_str::<AsyncRequest>(&json).unwrap(), request);
+
+        let progress = AsyncProgress {
+            done: u64::MAX,
+            partial: vec![0, 1, 2, 255],
+            ..Default::default()
+        };
+        let json = to_json(&progress);
+        assert_eq!(
+            json,
+            json!({ "done": "18446744073709551615", "partial": "AAEC/w==" })
+        );
+        assert_eq!(parse_from_json::<AsyncProgress>(&json).unwrap(), progress);
+    }
+
+    #[test]
+    fn test_parse() {
+        // Original field names, numbers of enums and integers are also accepted.
+        let json = json!({
+            "hot_topic_list": { "id": { "fid": "-7" }, "range": 2, "limit": 20 },
+        });
+        let request = parse_from_json::<AsyncRequest>(&json).unwrap();
+        let hot = request.get_hot_topic_list();
+        assert_eq!(hot.get_id().get_fid(), "-7");
+        assert_eq!(hot.get_range(), HotTopicListRequest_DateRange::MONTH);
+        assert_eq!(hot.get_limit(), 20);
+
+        for invalid in [
+            json!({ "topicList": { "page": "next" } }),
+            json!({ "topicList": { "unknown": 1 } }),
+            json!({ "topicList": [] }),
+            json!({ "hotTopicList": { "range": "YEAR" } }),
+        ] {
+            assert!(
+                parse_from_json::<AsyncRequest>(&invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -5,6 +5,7 @@
 #[rustfmt::skip]
 mod generated;
 
+pub mod json;
 mod mock;
 mod to_value;
 
@@ -12,9 +13,11 @@ pub use generated::*;
 pub use mock::*;
 pub use to_value::*;
 
+pub use protobuf::CodedOutputStream;
 pub use protobuf::Message;
 pub use protobuf::ProtobufEnum;
 pub use protobuf::ProtobufError;
+pub use protobuf::reflect;
 
 mod impls {
     use std::fmt::Display;
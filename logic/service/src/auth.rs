@@ -1,13 +1,36 @@
+use cache::{Batch, CACHE, CacheResult, Tree};
 use lazy_static::lazy_static;
-use protos::DataModel::AuthInfo;
+use protos::{DataModel::AuthInfo, Message};
 use std::sync::RwLock;
 
+use crate::{
+    clock_in::CLOCK_IN_PREFIX,
+    error::{ServiceError, ServiceResult},
+    noti::NOTI_PREFIX,
+    post::VOTE_RESPONSE_PREFIX,
+    topic::FAVOR_RESPONSE_PREFIX,
+};
+
+static ACCOUNTS_TREE: &str = "accounts";
+pub(crate) static ACCOUNT_PREFIX: &str = "account/";
+static MIGRATED_KEY: &str = "migrated/user_namespace";
+
+/// Prefixes of the cache entries that belong to some user, which are namespaced as
+/// `{prefix}/user/{uid}`.
+static USER_PREFIXES: [&str; 4] = [
+    FAVOR_RESPONSE_PREFIX,
+    VOTE_RESPONSE_PREFIX,
+    NOTI_PREFIX,
+    CLOCK_IN_PREFIX,
+];
+
 #[cfg(test)]
 fn default_auth_info() -> AuthInfo {
     dotenv::dotenv().ok();
+    // Tests replaying fixtures do not require a real account.
     AuthInfo {
-        uid: dotenv::var("AUTH_DEBUG_UID").expect("uid for debug is not set"),
-        token: dotenv::var("AUTH_DEBUG_TOKEN").expect("token for debug is not set"),
+        uid: dotenv::var("AUTH_DEBUG_UID").unwrap_or_default(),
+        token: dotenv::var("AUTH_DEBUG_TOKEN").unwrap_or_default(),
         ..Default::default()
     }
 }
@@ -19,12 +42,178 @@ fn default_auth_info() -> AuthInfo {
 
 lazy_static! {
     pub static ref AUTH_INFO: RwLock<AuthInfo> = RwLock::new(default_auth_info());
+    // Kept out of the main tree so that clearing the cache does not log the user out.
+    static ref ACCOUNTS: Tree = CACHE
+        .open_tree(ACCOUNTS_TREE)
+        .expect("cannot open accounts tree");
+}
+
+fn account_key(uid: &str) -> String {
+    format!("{}{}", ACCOUNT_PREFIX, uid)
 }
 
-pub fn set_auth(info: AuthInfo) {
+/// Set the current user. The account is also remembered for later switching, unless the user
+/// is not logged in.
+pub fn set_auth(info: AuthInfo) -> ServiceResult<()> {
+    if !info.get_uid().is_empty() {
+        add_account(&info)?;
+        migrate_once(info.get_uid())?;
+    }
     *AUTH_INFO.write().unwrap() = info;
+    Ok(())
 }
 
 pub fn current_uid() -> String {
     AUTH_INFO.read().unwrap().get_uid().to_owned()
 }
+
+/// The namespace of the cache entries with `prefix` for the current user.
+pub fn user_prefix(prefix: &str) -> String {
+    user_prefix_of(prefix, &current_uid())
+}
+
+fn user_prefix_of(prefix: &str, uid: &str) -> String {
+    format!("{}/user/{}", prefix, uid)
+}
+
+fn add_account(info: &AuthInfo) -> CacheResult<()> {
+    ACCOUNTS.insert(account_key(info.get_uid()), info.write_to_bytes()?)?;
+    Ok(())
+}
+
+pub fn list_accounts() -> CacheResult<Vec<AuthInfo>> {
+    let mut accounts = vec![];
+    for r in ACCOUNTS.scan_prefix(ACCOUNT_PREFIX) {
+        let (_k, v) = r?;
+        accounts.push(AuthInfo::parse_from_bytes(&v)?);
+    }
+    Ok(accounts)
+}
+
+fn get_account(uid: &str) -> CacheResult<Option<AuthInfo>> {
+    let value = ACCOUNTS.get(account_key(uid))?;
+    Ok(value.map(|v| AuthInfo::parse_from_bytes(&v)).transpose()?)
+}
+
+pub fn switch_account(uid: &str) -> ServiceResult<AuthInfo> {
+    let info = get_account(uid)?
+        .ok_or_else(|| ServiceError::MngaInternal(format!("Account {} not found", uid)))?;
+    set_auth(info.clone())?;
+    Ok(info)
+}
+
+/// Forget the account, and log out if it's the current user. Entries of this account are
+/// removed from the cache if `clear_cache` is set.
+pub fn remove_account(uid: &str, clear_cache: bool) -> CacheResult<Vec<AuthInfo>> {
+    let _ = ACCOUNTS.remove(account_key(uid))?;
+    if current_uid() == uid {
+        *AUTH_INFO.write().unwrap() = AuthInfo::new();
+    }
+    if clear_cache {
+        for prefix in USER_PREFIXES {
+            let user_prefix = user_prefix_of(prefix, uid);
+            // Some entries like the clock-in are keyed by the user prefix itself.
+            let _ = CACHE.remove(user_prefix.as_bytes())?;
+            // Trailing slash to avoid matching other uids with the same prefix.
+            let _ = CACHE.remove_prefix(&format!("{}/", user_prefix))?;
+        }
+    }
+    list_accounts()
+}
+
+/// Entries were shared by all users before namespacing, which are moved to the first user
+/// logged in since then, as there was at most one account at that time.
+fn migrate_once(uid: &str) -> CacheResult<()> {
+    if ACCOUNTS.get(MIGRATED_KEY)?.is_none() {
+        let moved = migrate_legacy_entries(uid)?;
+        log::info!("migrated {} legacy cache entries to user {}", moved, uid);
+        let _ = ACCOUNTS.insert(MIGRATED_KEY, uid.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn migrate_legacy_entries(uid: &str) -> CacheResult<usize> {
+    let mut batch = Batch::default();
+    let mut count = 0;
+    for prefix in USER_PREFIXES {
+        let namespaced = format!("{}/user/", prefix);
+        for r in CACHE.scan_prefix(format!("{}/", prefix)) {
+            let (k, v) = r?;
+            let key = String::from_utf8_lossy(&k);
+            if key.starts_with(&namespaced) {
+                continue;
+            }
+            let new_key = format!("{}{}", user_prefix_of(prefix, uid), &key[prefix.len()..]);
+            batch.remove(k);
+            batch.insert(new_key.as_bytes(), v);
+            count += 1;
+        }
+    }
+    CACHE.apply_batch(batch)?;
+    CACHE.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use protos::Service::{ClockInResponse, TopicFavorResponse};
+
+    use super::*;
+    use crate::utils::get_unique_id;
+
+    #[test]
+    fn test_accounts() -> ServiceResult<()> {
+        let info = AuthInfo {
+            uid: get_unique_id(),
+            token: "token".to_owned(),
+            ..Default::default()
+        };
+        add_account(&info)?;
+        assert!(list_accounts()?.contains(&info));
+
+        let key = format!(
+            "{}/topic/1",
+            user_prefix_of(FAVOR_RESPONSE_PREFIX, &info.uid)
+        );
+        let clock_in_key = user_prefix_of(CLOCK_IN_PREFIX, &info.uid);
+        CACHE.insert_msg(&key, &TopicFavorResponse::new())?;
+        CACHE.insert_msg(&clock_in_key, &ClockInResponse::new())?;
+        let accounts = remove_account(&info.uid, true)?;
+        assert!(!accounts.contains(&info));
+        assert!(CACHE.get_msg::<TopicFavorResponse>(&key)?.is_none());
+        assert!(CACHE.get_msg::<ClockInResponse>(&clock_in_key)?.is_none());
+
+        assert!(switch_account(&info.uid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_legacy_entries() -> ServiceResult<()> {
+        let uid = get_unique_id();
+        let id = get_unique_id();
+        let legacy_key = format!("{}/topic/{}", FAVOR_RESPONSE_PREFIX, id);
+        let other_key = format!(
+            "{}/topic/{}",
+            user_prefix_of(FAVOR_RESPONSE_PREFIX, "1"),
+            id
+        );
+        let response = TopicFavorResponse {
+            is_favored: true,
+            ..Default::default()
+        };
+        CACHE.insert_msg(&legacy_key, &response)?;
+        CACHE.insert_msg(&other_key, &response)?;
+
+        assert!(migrate_legacy_entries(&uid)? >= 1);
+        let migrated_key = format!(
+            "{}/topic/{}",
+            user_prefix_of(FAVOR_RESPONSE_PREFIX, &uid),
+            id
+        );
+        assert_eq!(CACHE.get_msg(&migrated_key)?, Some(response.clone()));
+        assert!(CACHE.get_msg::<TopicFavorResponse>(&legacy_key)?.is_none());
+        // Entries already namespaced are untouched.
+        assert_eq!(CACHE.get_msg(&other_key)?, Some(response));
+        Ok(())
+    }
+}
@@ -0,0 +1,463 @@
+//! Backup and restore of the user data in the cache, so that the reading history and other states
+//! survive reinstalling the app.
+//!
+//! Entries of the selected types are archived as a `CacheArchive`. Each entry keeps the schema
+//! version of its value, so that archives from older versions are migrated when restored.
+//!
+//! Drafts of posts are kept by the front ends rather than in the cache, so they are not archived
+//! here.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cache::{CACHE, Migration};
+use chrono::Utc;
+use protos::{
+    DataModel::{
+        BlockWord, CacheArchive, CacheArchive_Entry, CacheType, Notification, TopicSnapshot,
+    },
+    Message,
+    Service::{
+        CacheBackupRequest, CacheBackupResponse, CacheRestoreRequest, CacheRestoreResponse,
+        TopicFavorResponse,
+    },
+};
+
+use crate::{
+    block::invalidate_block_list,
+    cache::{migrations, type_to_prefix},
+    error::{ServiceError, ServiceResult},
+};
+
+const BACKUP_DIR: &str = "backups";
+const ARCHIVE_VERSION: u32 = 1;
+
+/// Types of the user data that can be backed up, which are also the default ones.
+const BACKUP_TYPES: [CacheType; 4] = [
+    CacheType::TOPIC_HISTORY,
+    CacheType::NOTIFICATION,
+    CacheType::FAVORITE,
+    CacheType::BLOCK_LIST,
+];
+
+/// Messages that can be merged with the local ones when restored.
+trait Restorable: Message {
+    /// Merge the archived message into the local one, returning whether the local one is changed.
+    fn merge_archived(&mut self, archived: Self) -> bool;
+}
+
+impl Restorable for TopicSnapshot {
+    /// The newer snapshot wins, while the farthest progress of both is kept.
+    fn merge_archived(&mut self, mut archived: Self) -> bool {
+        let local_highest = self.get_topic_snapshot().get_highest_viewed_floor();
+        let highest = local_highest.max(archived.get_topic_snapshot().get_highest_viewed_floor());
+
+        if archived.get_timestamp() > self.get_timestamp() {
+            archived
+                .mut_topic_snapshot()
+                .set_highest_viewed_floor(highest);
+            *self = archived;
+            true
+        } else if highest > local_highest {
+            self.mut_topic_snapshot().set_highest_viewed_floor(highest);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Restorable for Notification {
+    /// Notifications read on either side are read.
+    fn merge_archived(&mut self, archived: Self) -> bool {
+        if archived.get_read() && !self.get_read() {
+            self.set_read(true);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Restorable for TopicFavorResponse {
+    /// Favored states have no timestamp, and the local ones are likely fresher.
+    fn merge_archived(&mut self, _archived: Self) -> bool {
+        false
+    }
+}
+
+impl Restorable for BlockWord {
+    /// Block words are keyed by themselves, so there's nothing to merge.
+    fn merge_archived(&mut self, _archived: Self) -> bool {
+        false
+    }
+}
+
+/// Restore of a parsed entry, returning whether the cache is changed.
+type PendingRestore = Box<dyn FnOnce() -> ServiceResult<bool>>;
+
+/// Parse the entry, returning the restore of it.
+fn restore_entry<M: Restorable>(key: &str, payload: &[u8]) -> ServiceResult<PendingRestore> {
+    let archived = M::parse_from_bytes(payload)?;
+    let key = key.to_owned();
+    Ok(Box::new(move || {
+        let restored = match CACHE.get_msg::<M>(&key)? {
+            Some(mut local) => {
+                let changed = local.merge_archived(archived);
+                if changed {
+                    CACHE.insert_msg(&key, &local)?;
+                }
+                changed
+            }
+            None => {
+                CACHE.insert_msg(&key, &archived)?;
+                true
+            }
+        };
+        Ok(restored)
+    }))
+}
+
+type RestoreFn = fn(&str, &[u8]) -> ServiceResult<PendingRestore>;
+
+fn restore_fn(t: CacheType) -> Option<RestoreFn> {
+    let f: RestoreFn = match t {
+        CacheType::TOPIC_HISTORY => restore_entry::<TopicSnapshot>,
+        CacheType::NOTIFICATION => restore_entry::<Notification>,
+        CacheType::FAVORITE => restore_entry::<TopicFavorResponse>,
+        CacheType::BLOCK_LIST => restore_entry::<BlockWord>,
+        _ => return None,
+    };
+    Some(f)
+}
+
+fn restore_fn_of_key(key: &str) -> Option<RestoreFn> {
+    BACKUP_TYPES
+        .into_iter()
+        .find(|t| type_to_prefix(*t).iter().any(|p| key.starts_with(p)))
+        .and_then(restore_fn)
+}
+
+fn backup_dir() -> ServiceResult<PathBuf> {
+    config::CONF
+        .get()
+        .map(|conf| conf.document_dir_path.join(BACKUP_DIR))
+        .ok_or_else(|| {
+            ServiceError::MngaInternal("Document directory is not configured".to_owned())
+        })
+}
+
+/// Whether the cache of the type is encrypted at rest, which should not be archived in plaintext.
+fn is_encrypted_type(t: CacheType) -> bool {
+    type_to_prefix(t)
+        .iter()
+        .any(|p| CACHE.is_encrypted_prefix(p))
+}
+
+fn backup_cache_to_dir(
+    request: CacheBackupRequest,
+    dir: &Path,
+) -> ServiceResult<CacheBackupResponse> {
+    let mut types = if request.get_types().is_empty() {
+        let (encrypted, types): (Vec<_>, Vec<_>) = BACKUP_TYPES
+            .into_iter()
+            .partition(|t| is_encrypted_type(*t));
+        if !encrypted.is_empty() {
+            log::warn!("skipped encrypted cache in backup: {:?}", encrypted);
+        }
+        types
+    } else {
+        request.types
+    };
+    types.sort_by_key(|t| *t as i32);
+    types.dedup();
+
+    let mut entries = Vec::new();
+    for &t in &types {
+        if restore_fn(t).is_none() {
+            return Err(ServiceError::MngaInternal(format!(
+                "Cache of type {:?} cannot be backed up",
+                t
+            )));
+        }
+        if is_encrypted_type(t) {
+            return Err(ServiceError::MngaInternal(format!(
+                "Cache of type {:?} is encrypted and cannot be backed up in plaintext",
+                t
+            )));
+        }
+        for prefix in type_to_prefix(t) {
+            for r in CACHE.scan_payloads(prefix) {
+                let (key, schema_version, value) = r?;
+                entries.push(CacheArchive_Entry {
+                    key,
+                    schema_version,
+                    value,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    let now = Utc::now();
+    let archive = CacheArchive {
+        version: ARCHIVE_VERSION,
+        created_at: now.timestamp_millis() as u64,
+        types,
+        entries: entries.into(),
+        ..Default::default()
+    };
+
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("mnga-backup-{}.pb", now.format("%Y%m%d-%H%M%S")));
+    fs::write(&path, archive.write_to_bytes()?)?;
+    log::info!(
+        "backed up {} cache entries to {}",
+        archive.entries.len(),
+        path.display()
+    );
+
+    Ok(CacheBackupResponse {
+        path: path.to_string_lossy().into_owned(),
+        items: archive.entries.len() as u64,
+        ..Default::default()
+    })
+}
+
+/// Back up the user data in the cache into an archive under `Configuration.document_dir_path`.
+pub async fn backup_cache(request: CacheBackupRequest) -> ServiceResult<CacheBackupResponse> {
+    let dir = backup_dir()?;
+    tokio::task::spawn_blocking(move || backup_cache_to_dir(request, &dir))
+        .await
+        .map_err(|e| ServiceError::Panic(e.to_string()))?
+}
+
+fn restore_cache_from(path: &Path) -> ServiceResult<CacheRestoreResponse> {
+    let archive = CacheArchive::parse_from_bytes(&fs::read(path)?)?;
+    if archive.get_version() > ARCHIVE_VERSION {
+        return Err(ServiceError::MngaInternal(
+            "Backup is created by a newer version of MNGA".to_owned(),
+        ));
+    }
+
+    if (archive.entries.iter()).any(|e| e.schema_version > CACHE.schema_version()) {
+        return Err(ServiceError::MngaInternal(
+            "Backup is created by a newer version of MNGA".to_owned(),
+        ));
+    }
+
+    // Migrate and parse all entries before writing any, so that a broken archive changes nothing.
+    let migrations = migrations();
+    let mut response = CacheRestoreResponse::new();
+    let mut pending = Vec::new();
+    for entry in archive.entries {
+        let CacheArchive_Entry {
+            key,
+            schema_version,
+            value,
+            ..
+        } = entry;
+        let Some(restore) = restore_fn_of_key(&key) else {
+            log::warn!("skipped unknown entry in backup: {}", key);
+            response.skipped += 1;
+            continue;
+        };
+
+        let (_, payload) = Migration::upgrade(&migrations, &key, schema_version, value)?;
+        pending.push(restore(&key, &payload)?);
+    }
+
+    for restore in pending {
+        if restore()? {
+            response.restored += 1;
+        } else {
+            response.skipped += 1;
+        }
+    }
+
+    if archive.types.contains(&CacheType::BLOCK_LIST) {
+        invalidate_block_list();
+    }
+    log::info!(
+        "restored {} cache entries from {}, {} skipped",
+        response.restored,
+        path.display(),
+        response.skipped
+    );
+    Ok(response)
+}
+
+/// Restore the cache from an archive of `backup_cache`, merging with the current one.
+pub async fn restore_cache(request: CacheRestoreRequest) -> ServiceResult<CacheRestoreResponse> {
+    let path = PathBuf::from(request.get_path());
+    tokio::task::spawn_blocking(move || restore_cache_from(&path))
+        .await
+        .map_err(|e| ServiceError::Panic(e.to_string()))?
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+    use crate::{
+        cache::test::{ENCRYPTION_TEST_LOCK, cover_encryption_prefixes},
+        history::TOPIC_SNAPSHOT_PREFIX,
+        noti::NOTI_PREFIX,
+        utils::get_unique_id,
+    };
+    use protos::DataModel::Topic;
+
+    fn snapshot(timestamp: u64, highest_floor: u32) -> TopicSnapshot {
+        let mut topic = Topic::new();
+        topic.set_highest_viewed_floor(highest_floor);
+        TopicSnapshot {
+            topic_snapshot: Some(topic).into(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_snapshot() {
+        let mut local = snapshot(2, 10);
+        assert!(!local.merge_archived(snapshot(1, 5)));
+
+        assert!(local.merge_archived(snapshot(1, 20)));
+        assert_eq!(local, snapshot(2, 20));
+
+        assert!(local.merge_archived(snapshot(3, 5)));
+        assert_eq!(local, snapshot(3, 20));
+    }
+
+    #[test]
+    fn test_backup_and_restore() -> ServiceResult<()> {
+        let _guard = ENCRYPTION_TEST_LOCK.blocking_lock();
+        let id = get_unique_id();
+        let snapshot_key = format!("{}/{}", TOPIC_SNAPSHOT_PREFIX, id);
+        let noti_key = format!("{}/user/backup/{}", NOTI_PREFIX, id);
+        let noti = |read: bool| Notification {
+            id: id.clone(),
+            read,
+            ..Default::default()
+        };
+
+        CACHE.insert_msg(&snapshot_key, &snapshot(2, 10))?;
+        CACHE.insert_msg(&noti_key, &noti(true))?;
+
+        let dir = env::temp_dir().join(format!("mnga-backup-{}", id));
+        let backup = backup_cache_to_dir(
+            CacheBackupRequest {
+                types: vec![CacheType::TOPIC_HISTORY, CacheType::NOTIFICATION],
+                ..Default::default()
+            },
+            &dir,
+        )?;
+        assert!(backup.get_items() >= 2);
+
+        // Restored into the cache with some newer and some missing entries.
+        CACHE.insert_msg(&snapshot_key, &snapshot(3, 5))?;
+        CACHE.insert_msg(&noti_key, &noti(false))?;
+
+        let restore = restore_cache_from(Path::new(backup.get_path()))?;
+        assert!(restore.get_restored() >= 2);
+        assert_eq!(
+            CACHE.get_msg::<TopicSnapshot>(&snapshot_key)?,
+            Some(snapshot(3, 10))
+        );
+        assert_eq!(CACHE.get_msg::<Notification>(&noti_key)?, Some(noti(true)));
+
+        // Nothing changed if restored again.
+        let restore = restore_cache_from(Path::new(backup.get_path()))?;
+        assert_eq!(restore.get_skipped(), backup.get_items());
+
+        // Encrypted notifications are not archived in plaintext.
+        cover_encryption_prefixes()?;
+        CACHE.rekey(Some(&[7; 32]))?;
+        let backup_notis = backup_cache_to_dir(
+            CacheBackupRequest {
+                types: vec![CacheType::NOTIFICATION],
+                ..Default::default()
+            },
+            &dir,
+        );
+        let backup_default = backup_cache_to_dir(CacheBackupRequest::new(), &dir);
+        CACHE.rekey(None)?;
+        assert!(backup_notis.is_err());
+        let archive = CacheArchive::parse_from_bytes(&fs::read(backup_default?.get_path())?)?;
+        assert!(!archive.get_types().contains(&CacheType::NOTIFICATION));
+        assert!(
+            archive
+                .get_entries()
+                .iter()
+                .all(|e| !e.key.starts_with(NOTI_PREFIX))
+        );
+
+        assert!(
+            backup_cache_to_dir(
+                CacheBackupRequest {
+                    types: vec![CacheType::TOPIC_DETAILS],
+                    ..Default::default()
+                },
+                &dir,
+            )
+            .is_err()
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_nothing_if_broken() -> ServiceResult<()> {
+        let id = get_unique_id();
+        let key = format!("{}/{}", TOPIC_SNAPSHOT_PREFIX, id);
+        let entry = |key: &str, schema_version: u32, value: Vec<u8>| CacheArchive_Entry {
+            key: key.to_owned(),
+            schema_version,
+            value,
+            ..Default::default()
+        };
+        let version = CACHE.schema_version();
+        let valid = entry(&key, version, snapshot(1, 5).write_to_bytes()?);
+        let path = env::temp_dir().join(format!("mnga-backup-{}.pb", id));
+        let write_archive = |entries: Vec<CacheArchive_Entry>| {
+            let archive = CacheArchive {
+                version: ARCHIVE_VERSION,
+                entries: entries.into(),
+                ..Default::default()
+            };
+            fs::write(&path, archive.write_to_bytes()?)
+        };
+
+        write_archive(vec![
+            valid.clone(),
+            entry(&format!("{}/newer", key), version + 1, vec![]),
+        ])?;
+        assert!(matches!(
+            restore_cache_from(&path),
+            Err(ServiceError::MngaInternal(_))
+        ));
+        assert!(CACHE.get_msg::<TopicSnapshot>(&key)?.is_none());
+
+        write_archive(vec![
+            valid.clone(),
+            entry(&format!("{}/corrupt", key), version, b"\xff".to_vec()),
+        ])?;
+        assert!(matches!(
+            restore_cache_from(&path),
+            Err(ServiceError::Protobuf(_))
+        ));
+        assert!(CACHE.get_msg::<TopicSnapshot>(&key)?.is_none());
+
+        // The valid entry alone is restored.
+        write_archive(vec![valid])?;
+        assert_eq!(restore_cache_from(&path)?.get_restored(), 1);
+        assert!(CACHE.get_msg::<TopicSnapshot>(&key)?.is_some());
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Local block list of users and keywords.
+//!
+//! Topics and notifications matching the list are dropped, while posts are only marked as
+//! `blocked`, so that the floors of a topic stay intact.
+
+use std::sync::{Arc, RwLock};
+
+use cache::CACHE;
+use protos::{
+    DataModel::{BlockWord, BlockWord_Type, Notification, Post, Topic},
+    Service::{
+        BlockWordListRequest, BlockWordListResponse, BlockWordModifyRequest,
+        BlockWordModifyRequest_Operation, BlockWordModifyResponse,
+    },
+};
+use regex::Regex;
+
+use crate::error::{ServiceError, ServiceResult};
+
+pub static BLOCK_WORD_PREFIX: &str = "/block_word";
+fn block_word_key(word: &BlockWord) -> String {
+    match word.get_field_type() {
+        BlockWord_Type::KEYWORD => format!("{}/keyword/{}", BLOCK_WORD_PREFIX, word.get_word()),
+        BlockWord_Type::REGEX => format!("{}/regex/{}", BLOCK_WORD_PREFIX, word.get_word()),
+        BlockWord_Type::USER => format!("{}/user/{}", BLOCK_WORD_PREFIX, word.get_user_id()),
+    }
+}
+
+#[derive(Default)]
+struct BlockList {
+    user_ids: Vec<String>,
+    keywords: Vec<String>,
+    regexes: Vec<Regex>,
+}
+
+impl BlockList {
+    fn load() -> Self {
+        let mut list = Self::default();
+        for word in CACHE.scan_msg::<BlockWord>(BLOCK_WORD_PREFIX) {
+            match word.get_field_type() {
+                BlockWord_Type::KEYWORD => list.keywords.push(word.word),
+                // Regexes are validated before inserted.
+                BlockWord_Type::REGEX => list.regexes.extend(Regex::new(&word.word).ok()),
+                BlockWord_Type::USER => list.user_ids.push(word.user_id),
+            }
+        }
+        list
+    }
+
+    fn is_empty(&self) -> bool {
+        self.user_ids.is_empty() && self.keywords.is_empty() && self.regexes.is_empty()
+    }
+
+    fn matches<'a>(&self, user_id: &str, texts: impl IntoIterator<Item = &'a str>) -> bool {
+        self.user_ids.iter().any(|id| id == user_id)
+            || texts.into_iter().any(|text| {
+                self.keywords.iter().any(|k| text.contains(k.as_str()))
+                    || self.regexes.iter().any(|r| r.is_match(text))
+            })
+    }
+
+    fn matches_topic(&self, topic: &Topic) -> bool {
+        let subject = topic.get_subject();
+        let texts = subject.get_tags().iter().map(String::as_str);
+        self.matches(topic.get_author_id(), texts.chain([subject.get_content()]))
+    }
+
+    fn matches_post(&self, post: &Post) -> bool {
+        self.matches(post.get_author_id(), [post.get_content().get_raw()])
+    }
+
+    fn matches_noti(&self, noti: &Notification) -> bool {
+        self.matches(
+            noti.get_other_user().get_id(),
+            [noti.get_topic_subject().get_content()],
+        )
+    }
+}
+
+/// Compiled block list, which is loaded lazily and dropped once the entries change.
+static BLOCK_LIST: RwLock<Option<Arc<BlockList>>> = RwLock::new(None);
+
+fn block_list() -> Arc<BlockList> {
+    if let Some(list) = BLOCK_LIST.read().unwrap().as_ref() {
+        return list.clone();
+    }
+    BLOCK_LIST
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(BlockList::load()))
+        .clone()
+}
+
+/// Reload the block list on next use, should be called once the cache is modified.
+pub fn invalidate_block_list() {
+    BLOCK_LIST.write().unwrap().take();
+}
+
+pub fn filter_topics(topics: Vec<Topic>) -> Vec<Topic> {
+    let list = block_list();
+    if list.is_empty() {
+        return topics;
+    }
+    topics
+        .into_iter()
+        .filter(|t| !list.matches_topic(t))
+        .collect()
+}
+
+pub fn filter_notis(notis: Vec<Notification>) -> Vec<Notification> {
+    let list = block_list();
+    if list.is_empty() {
+        return notis;
+    }
+    notis
+        .into_iter()
+        .filter(|n| !list.matches_noti(n))
+        .collect()
+}
+
+/// Mark the posts with their hot replies and comments. The previous marks are always
+/// overridden, since posts may come from the local cache.
+pub fn mark_posts<'a>(posts: impl IntoIterator<Item = &'a mut Post>) {
+    fn mark(list: &BlockList, post: &mut Post) {
+        post.set_blocked(list.matches_post(post));
+        post.mut_hot_replies()
+            .iter_mut()
+            .for_each(|p| mark(list, p));
+        post.mut_comments().iter_mut().for_each(|p| mark(list, p));
+    }
+
+    let list = block_list();
+    posts.into_iter().for_each(|p| mark(&list, p));
+}
+
+pub fn get_block_words(_request: BlockWordListRequest) -> ServiceResult<BlockWordListResponse> {
+    let words = CACHE
+        .scan_msg::<BlockWord>(BLOCK_WORD_PREFIX)
+        .collect::<Vec<_>>();
+
+    Ok(BlockWordListResponse {
+        words: words.into(),
+        ..Default::default()
+    })
+}
+
+pub fn modify_block_word(
+    request: BlockWordModifyRequest,
+) -> ServiceResult<BlockWordModifyResponse> {
+    let word = request.get_word();
+    let key = block_word_key(word);
+
+    match request.get_operation() {
+        BlockWordModifyRequest_Operation::ADD => {
+            match word.get_field_type() {
+                BlockWord_Type::KEYWORD if word.get_word().is_empty() => {
+                    return Err(ServiceError::MngaInternal("Empty keyword".to_owned()));
+                }
+                BlockWord_Type::REGEX => {
+                    Regex::new(word.get_word()).map_err(|e| {
+                        ServiceError::MngaInternal(format!("Invalid regular expression: {}", e))
+                    })?;
+                }
+                BlockWord_Type::USER if word.get_user_id().is_empty() => {
+                    return Err(ServiceError::MngaInternal("Empty user id".to_owned()));
+                }
+                _ => {}
+            }
+            CACHE.insert_msg(&key, word)?;
+        }
+        BlockWordModifyRequest_Operation::REMOVE => {
+            CACHE.remove(key.as_bytes())?;
+        }
+    }
+    invalidate_block_list();
+
+    let words = get_block_words(Default::default())?.words;
+    Ok(BlockWordModifyResponse {
+        words,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fake_nga::{FORUM_ID, FakeNga},
+        forum::make_fid,
+        noti::fetch_notis,
+        topic::{get_topic_details, get_topic_list},
+        utils::get_unique_id,
+    };
+    use protos::Service::{TopicDetailsRequest, TopicListRequest};
+
+    fn modify(word: BlockWord, operation: BlockWordModifyRequest_Operation) -> ServiceResult<()> {
+        modify_block_word(BlockWordModifyRequest {
+            word: Some(word).into(),
+            operation,
+            ..Default::default()
+        })
+        .map(|_| ())
+    }
+
+    fn word(field_type: BlockWord_Type, word: &str) -> BlockWord {
+        BlockWord {
+            word: word.to_owned(),
+            field_type,
+            ..Default::default()
+        }
+    }
+
+    fn user(user_id: &str) -> BlockWord {
+        BlockWord {
+            field_type: BlockWord_Type::USER,
+            user_id: user_id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_modify_block_words() -> ServiceResult<()> {
+        use BlockWordModifyRequest_Operation::*;
+
+        let keyword = word(BlockWord_Type::KEYWORD, &get_unique_id());
+        modify(keyword.clone(), ADD)?;
+        let words = get_block_words(Default::default())?.words;
+        assert!(words.contains(&keyword));
+
+        modify(keyword.clone(), REMOVE)?;
+        let words = get_block_words(Default::default())?.words;
+        assert!(!words.contains(&keyword));
+
+        assert!(modify(word(BlockWord_Type::REGEX, "(unclosed"), ADD).is_err());
+        assert!(modify(word(BlockWord_Type::KEYWORD, ""), ADD).is_err());
+        assert!(modify(user(""), ADD).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_block_list() -> ServiceResult<()> {
+        use BlockWordModifyRequest_Operation::*;
+
+        let server = FakeNga::start();
+        let marker = get_unique_id();
+        let (topic, other) = {
+            let mut state = server.state();
+            state.topics[0].subject = format!("Topic {}", marker);
+            (state.topics[0].clone(), state.users[1].clone())
+        };
+        let topic_list = || {
+            get_topic_list(TopicListRequest {
+                id: make_fid(FORUM_ID.to_owned()).into(),
+                page: 1,
+                ..Default::default()
+            })
+        };
+        let find_topic = |topics: &[Topic]| topics.iter().any(|t| t.get_id() == topic.tid);
+
+        server
+            .scope(async {
+                assert!(find_topic(topic_list().await?.get_topics()));
+
+                let regex = word(BlockWord_Type::REGEX, &format!("^Topic {}$", marker));
+                modify(regex.clone(), ADD)?;
+                assert!(!find_topic(topic_list().await?.get_topics()));
+                modify(regex, REMOVE)?;
+                assert!(find_topic(topic_list().await?.get_topics()));
+
+                modify(user(&other.id), ADD)?;
+
+                let details = get_topic_details(TopicDetailsRequest {
+                    topic_id: topic.tid.clone(),
+                    page: 1,
+                    ..Default::default()
+                })
+                .await?;
+                let blocked = details
+                    .get_replies()
+                    .iter()
+                    .map(|p| p.get_blocked())
+                    .collect::<Vec<_>>();
+                assert_eq!(blocked, [false, true]);
+
+                let notis = fetch_notis(Default::default()).await?;
+                assert!(
+                    notis
+                        .get_notis()
+                        .iter()
+                        .all(|n| n.get_other_user().get_id() != other.id)
+                );
+
+                modify(user(&other.id), REMOVE)?;
+                Ok(())
+            })
+            .await
+    }
+}
@@ -1,56 +1,285 @@
-use cache::CACHE;
+use cache::{CACHE, CacheResult, EncryptionPolicy, EvictionPolicy, EvictionRule, Migration};
 use protos::{
     DataModel::{CacheOperation, CacheType},
-    Service::{CacheRequest, CacheResponse},
+    ProtobufEnum,
+    Service::{CacheRequest, CacheResponse, CacheResponse_TypeSize},
+};
+use std::{
+    sync::{Once, RwLock},
+    time::Duration,
 };
 
 use crate::{
+    auth::ACCOUNT_PREFIX,
+    block::{BLOCK_WORD_PREFIX, invalidate_block_list},
+    download::TOPIC_DOWNLOAD_PREFIX,
     error::ServiceResult,
     history::TOPIC_SNAPSHOT_PREFIX,
     noti::NOTI_PREFIX,
+    post::VOTE_RESPONSE_PREFIX,
+    search::clear_index,
     topic::{FAVOR_RESPONSE_PREFIX, TOPIC_DETAILS_PREFIX},
 };
 
-fn type_to_prefix(t: CacheType) -> Vec<&'static str> {
+pub fn type_to_prefix(t: CacheType) -> Vec<&'static str> {
     match t {
         CacheType::ALL => vec!["/"],
         CacheType::TOPIC_HISTORY => vec![TOPIC_SNAPSHOT_PREFIX],
-        CacheType::TOPIC_DETAILS => vec![TOPIC_DETAILS_PREFIX, FAVOR_RESPONSE_PREFIX],
+        CacheType::TOPIC_DETAILS => vec![
+            TOPIC_DETAILS_PREFIX,
+            FAVOR_RESPONSE_PREFIX,
+            TOPIC_DOWNLOAD_PREFIX,
+        ],
         CacheType::NOTIFICATION => vec![NOTI_PREFIX],
+        CacheType::FAVORITE => vec![FAVOR_RESPONSE_PREFIX],
+        CacheType::BLOCK_LIST => vec![BLOCK_WORD_PREFIX],
+    }
+}
+
+/// Migrations of the cached values. Append one with the next version when restructuring the
+/// cached messages, instead of bumping the prefix like `/noti_v2`.
+pub fn migrations() -> Vec<Migration> {
+    vec![Migration::new(
+        1,
+        "/",
+        "wrap the legacy values in the versioned envelope",
+        |payload| Ok(payload.to_vec()),
+    )]
+}
+
+/// Migrate the cache to the latest schema, which should be run before serving any request.
+pub fn migrate() -> ServiceResult<()> {
+    let stats = CACHE.migrate(&migrations())?;
+    if stats.failed > 0 {
+        log::warn!("{} cache entries failed to migrate", stats.failed);
+    }
+    Ok(())
+}
+
+/// Notifications quote the messages and replies of the user, and the accounts contain the auth
+/// tokens, so they are encrypted at rest if a key is configured.
+fn encryption_policy(key: Option<Vec<u8>>) -> EncryptionPolicy {
+    EncryptionPolicy {
+        key,
+        prefixes: vec![NOTI_PREFIX.to_owned(), ACCOUNT_PREFIX.to_owned()],
+    }
+}
+
+/// Key of the encryption, taken from the configuration until the cache is set up, then changed by
+/// `CacheOperation::REKEY` only.
+static ENCRYPTION_KEY: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+
+fn configured_encryption_policy() -> EncryptionPolicy {
+    encryption_policy(ENCRYPTION_KEY.read().unwrap().clone())
+}
+
+/// Apply the encryption key, which should be run before migrating the cache. Fails with
+/// `CacheError::WrongKey` if the cache has been encrypted with another key, where the encrypted
+/// entries are kept unreadable until discarded with `CacheOperation::RESET_ENCRYPTION`, and the
+/// sensitive entries cannot be written. Configure again with the right key to retry.
+pub fn setup_encryption(key: Option<Vec<u8>>) -> ServiceResult<()> {
+    *ENCRYPTION_KEY.write().unwrap() = key;
+    let count = CACHE.set_encryption(configured_encryption_policy())?;
+    if count > 0 {
+        log::info!("encrypted {} cache entries", count);
     }
+    Ok(())
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const CACHE_SIZE_BUDGET: u64 = 200 * 1024 * 1024;
+const COMPACTION_DELAY: Duration = Duration::from_secs(60);
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Only the entries that can be fetched again are evictable. Topic history, downloads and
+/// notifications, whose read state is user data, are kept until cleared by the user.
+fn eviction_policy() -> EvictionPolicy {
+    let rule = |prefix: &str, days: u32| EvictionRule {
+        prefix: prefix.to_owned(),
+        ttl: Some(DAY * days),
+    };
+
+    EvictionPolicy {
+        rules: vec![
+            rule(TOPIC_DETAILS_PREFIX, 14),
+            rule(FAVOR_RESPONSE_PREFIX, 7),
+            rule(VOTE_RESPONSE_PREFIX, 30),
+        ],
+        size_budget: CACHE_SIZE_BUDGET,
+    }
+}
+
+/// Apply the eviction policy and compact the cache periodically in the background, for once.
+pub fn start_compaction() {
+    static START: Once = Once::new();
+
+    START.call_once(|| {
+        CACHE.set_eviction_policy(eviction_policy());
+        let spawned = std::thread::Builder::new()
+            .name("cache-compaction".to_owned())
+            .spawn(|| {
+                // Do not slow down the launch.
+                std::thread::sleep(COMPACTION_DELAY);
+                loop {
+                    if let Err(e) = CACHE.compact() {
+                        log::error!("failed to compact cache: {}", e);
+                    }
+                    std::thread::sleep(COMPACTION_INTERVAL);
+                }
+            });
+        if let Err(e) = spawned {
+            log::error!("failed to spawn cache compaction: {}", e);
+        }
+    });
+}
+
+fn type_sizes() -> CacheResult<Vec<CacheResponse_TypeSize>> {
+    CacheType::values()
+        .iter()
+        .filter(|t| **t != CacheType::ALL)
+        .map(|&t| {
+            let mut type_size = CacheResponse_TypeSize {
+                field_type: t,
+                ..Default::default()
+            };
+            for prefix in type_to_prefix(t) {
+                let (items, size) = CACHE.prefix_size(prefix)?;
+                type_size.items += items;
+                type_size.size += size;
+            }
+            Ok(type_size)
+        })
+        .collect()
 }
 
 pub async fn manipulate_cache(request: CacheRequest) -> ServiceResult<CacheResponse> {
     let mut items = 0;
 
+    if request.get_operation() == CacheOperation::COMPACT {
+        let stats = CACHE.run_blocking(|cache| cache.compact()).await?;
+        log::info!("compacted cache on request: {:?}", stats);
+    }
+    if request.get_operation() == CacheOperation::REKEY {
+        let new_key = Some(request.get_new_encryption_key().to_vec()).filter(|k| !k.is_empty());
+        let count = CACHE
+            .run_blocking(move |cache| {
+                let count = cache.rekey(new_key.as_deref())?;
+                *ENCRYPTION_KEY.write().unwrap() = new_key;
+                Ok(count)
+            })
+            .await?;
+        log::info!("rekeyed {} cache entries", count);
+    }
+    if request.get_operation() == CacheOperation::RESET_ENCRYPTION {
+        let count = CACHE
+            .run_blocking(|cache| cache.reset_encryption(configured_encryption_policy()))
+            .await?;
+        log::warn!(
+            "discarded {} cache entries encrypted with another key",
+            count
+        );
+    }
+
     let prefixes = type_to_prefix(request.get_field_type());
     for prefix in prefixes {
-        items += match request.get_operation() {
-            CacheOperation::CHECK => CACHE.scan_prefix(prefix).count(),
-            CacheOperation::CLEAR => {
-                let _removed_count = CACHE.remove_prefix(prefix)?;
-                CACHE.scan_prefix(prefix).count()
-            }
-        };
+        if request.get_operation() == CacheOperation::CLEAR {
+            let _removed_count = CACHE.remove_prefix_async(prefix).await?;
+        }
+        items += CACHE
+            .run_blocking(move |cache| Ok(cache.scan_prefix(prefix).count()))
+            .await?;
     }
-    let total_size = CACHE.total_size()?;
+    if request.get_operation() == CacheOperation::CLEAR {
+        invalidate_block_list();
+        if request.get_field_type() != CacheType::NOTIFICATION {
+            // The index will be rebuilt from the rest of the cache on next search.
+            clear_index()?;
+        }
+    }
+    let (total_size, type_sizes) = CACHE
+        .run_blocking(|cache| Ok((cache.total_size()?, type_sizes()?)))
+        .await?;
 
     Ok(CacheResponse {
         items: items as u64,
         total_size,
+        type_sizes: type_sizes.into(),
         ..Default::default()
     })
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use crate::utils::get_unique_id;
 
     use super::*;
 
     use protos::DataModel::{CacheType::*, Subject};
 
-    #[ignore = "manual: requires network or mutable external state"]
+    #[tokio::test]
+    async fn test_compact_cache() -> ServiceResult<()> {
+        let key = format!("{}/test_compact_cache", TOPIC_DETAILS_PREFIX);
+        CACHE.insert_msg(&key, &Subject::new())?;
+
+        let response = manipulate_cache(CacheRequest {
+            field_type: TOPIC_DETAILS,
+            operation: CacheOperation::COMPACT,
+            ..Default::default()
+        })
+        .await?;
+        assert!(response.get_items() >= 1);
+
+        let type_sizes = response.get_type_sizes();
+        assert_eq!(type_sizes.len(), CacheType::values().len() - 1);
+        let details = type_sizes
+            .iter()
+            .find(|s| s.get_field_type() == TOPIC_DETAILS)
+            .unwrap();
+        assert!(details.get_items() >= 1 && details.get_size() >= key.len() as u64);
+
+        Ok(())
+    }
+
+    /// Held by the tests that change the encryption of the global cache.
+    pub(crate) static ENCRYPTION_TEST_LOCK: tokio::sync::Mutex<()> =
+        tokio::sync::Mutex::const_new(());
+
+    /// Cover the sensitive prefixes without a key, so that the tests may rekey.
+    pub(crate) fn cover_encryption_prefixes() -> CacheResult<()> {
+        CACHE.set_encryption(encryption_policy(None))?;
+        *ENCRYPTION_KEY.write().unwrap() = None;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rekey_cache() -> ServiceResult<()> {
+        let _guard = ENCRYPTION_TEST_LOCK.lock().await;
+        cover_encryption_prefixes()?;
+        let key = format!("{}/test_rekey_cache", NOTI_PREFIX);
+        let subject = Subject {
+            content: "Content".to_owned(),
+            ..Default::default()
+        };
+        CACHE.insert_msg(&key, &subject)?;
+
+        let rekey = |new_key: Vec<u8>| {
+            manipulate_cache(CacheRequest {
+                operation: CacheOperation::REKEY,
+                new_encryption_key: new_key,
+                ..Default::default()
+            })
+        };
+        assert!(rekey(vec![0; 16]).await.is_err());
+        rekey(vec![7; 32]).await?;
+        assert!(CACHE.is_encrypted_prefix(NOTI_PREFIX));
+        assert_eq!(CACHE.get_msg::<Subject>(&key)?, Some(subject.clone()));
+        rekey(vec![]).await?;
+        assert_eq!(CACHE.get_msg::<Subject>(&key)?, Some(subject));
+
+        Ok(())
+    }
+
+    #[ignore = "manual: counts and clears the whole cache shared with the other tests"]
     #[tokio::test]
     async fn test_clear_cache() -> ServiceResult<()> {
         let insert = |tp: CacheType, count: u64| {
@@ -2,15 +2,18 @@ use cache::CACHE;
 use protos::Service::{ClockInRequest, ClockInResponse};
 
 use crate::{
-    auth::current_uid, error::ServiceResult, fetch::fetch_json_value, utils::server_today_string,
+    auth::user_prefix, error::ServiceResult, fetch::fetch_json_value, utils::server_today_string,
 };
 
+pub static CLOCK_IN_PREFIX: &str = "/clock_in";
 fn clock_in_key() -> String {
-    format!("/clock_in/user/{}", current_uid())
+    user_prefix(CLOCK_IN_PREFIX)
 }
 
-fn clocked_in_today() -> ServiceResult<bool> {
-    let last = CACHE.get_msg::<ClockInResponse>(&clock_in_key())?;
+async fn clocked_in_today() -> ServiceResult<bool> {
+    let last = CACHE
+        .get_msg_async::<ClockInResponse>(&clock_in_key())
+        .await?;
     Ok(last
         .map(|r| r.date == server_today_string())
         .unwrap_or_default())
@@ -22,14 +25,14 @@ pub async fn clock_in(_request: ClockInRequest) -> ServiceResult<ClockInResponse
         ..Default::default()
     };
 
-    if !clocked_in_today()? {
+    if !clocked_in_today().await? {
         let _value = fetch_json_value(
             "nuke.php",
             vec![("__lib", "check_in"), ("__act", "check_in")],
             vec![],
         )
         .await?;
-        let _ = CACHE.insert_msg(&clock_in_key(), &response)?;
+        let _ = CACHE.insert_msg_async(&clock_in_key(), &response).await?;
         response.is_first_time = true;
     }
 
@@ -39,12 +42,15 @@ pub async fn clock_in(_request: ClockInRequest) -> ServiceResult<ClockInResponse
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fixture::with_fixtures;
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_clock_in() -> ServiceResult<()> {
-        clock_in(ClockInRequest::default()).await?;
-        assert!(clocked_in_today().unwrap());
-        Ok(())
+        with_fixtures(async {
+            clock_in(ClockInRequest::default()).await?;
+            assert!(clocked_in_today().await.unwrap());
+            Ok(())
+        })
+        .await
     }
 }
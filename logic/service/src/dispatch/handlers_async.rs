@@ -1,7 +1,10 @@
 use crate::{
+    backup::{backup_cache, restore_cache},
     cache::manipulate_cache,
     clock_in::clock_in,
+    download::download_topic,
     error::ServiceResult,
+    export::export_topic,
     forum::{
         get_favorite_forum_list, get_forum_list, modify_favorite_forum, search_forum,
         set_subforum_filter,
@@ -12,6 +15,7 @@ use crate::{
     post::{
         get_user_post_list, post_reply, post_reply_fetch_content, post_vote, upload_attachment,
     },
+    search::search_local,
     topic::{
         create_favorite_folder, get_favorite_folder_list, get_favorite_topic_list,
         get_hot_topic_list, get_topic_details, get_topic_list, get_user_topic_list,
@@ -61,3 +65,8 @@ handle!(topic_search, search_topic);
 handle!(clock_in, clock_in);
 handle!(cache, manipulate_cache);
 handle!(user_signature_update, update_signature);
+handle!(topic_download, download_topic);
+handle!(topic_export, export_topic);
+handle!(local_search, search_local);
+handle!(cache_backup, backup_cache);
+handle!(cache_restore, restore_cache);
@@ -1,17 +1,46 @@
 use crate::{
-    auth, error::ServiceResult, fetch::invalidate_global_client, history, noti::mark_noti_read,
-    request, user::UserController,
+    auth, block, download, error::ServiceResult, fetch::invalidate_global_client, history,
+    noti::mark_noti_read, request, user::UserController,
 };
 use log::info;
 use protos::Service::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the cache has been set up by `handle_configure`, which is retried with the key of the
+/// next configuration if failed, like with a wrong encryption key.
+static CACHE_SET_UP: AtomicBool = AtomicBool::new(false);
 
 pub fn handle_configure(request: ConfigureRequest) -> ServiceResult<ConfigureResponse> {
-    config::set_config(request.config.unwrap());
+    let last_tuning = config::tuning();
+    let config = request.config.unwrap();
+    let encryption_key = Some(config.cache_encryption_key.clone()).filter(|k| !k.is_empty());
+    let effective_config = config::set_config(config)?;
+
+    if CACHE_SET_UP.load(Ordering::SeqCst) {
+        let tuning = config::tuning();
+        if (tuning.connect_timeout, tuning.read_timeout)
+            != (last_tuning.connect_timeout, last_tuning.read_timeout)
+        {
+            invalidate_global_client(false);
+        }
+        return Ok(ConfigureResponse {
+            effective_config: Some(effective_config).into(),
+            ..Default::default()
+        });
+    }
+
     if request.debug {
         cache::CACHE.clear().expect("failed to clear the cache");
         info!("cleared the cache");
     }
-    Ok(ConfigureResponse::new())
+    crate::cache::setup_encryption(encryption_key)?;
+    crate::cache::migrate()?;
+    crate::cache::start_compaction();
+    CACHE_SET_UP.store(true, Ordering::SeqCst);
+    Ok(ConfigureResponse {
+        effective_config: Some(effective_config).into(),
+        ..Default::default()
+    })
 }
 
 pub fn handle_local_user(request: LocalUserRequest) -> ServiceResult<LocalUserResponse> {
@@ -24,14 +53,50 @@ pub fn handle_local_user(request: LocalUserRequest) -> ServiceResult<LocalUserRe
 }
 
 pub fn handle_auth(request: AuthRequest) -> ServiceResult<AuthResponse> {
-    auth::set_auth(request.info.unwrap());
+    auth::set_auth(request.info.unwrap())?;
     Ok(Default::default())
 }
 
+pub fn handle_account_list(_request: AccountListRequest) -> ServiceResult<AccountListResponse> {
+    Ok(AccountListResponse {
+        accounts: auth::list_accounts()?.into(),
+        current_uid: auth::current_uid(),
+        ..Default::default()
+    })
+}
+
+pub fn handle_account_switch(
+    request: AccountSwitchRequest,
+) -> ServiceResult<AccountSwitchResponse> {
+    let info = auth::switch_account(request.get_uid())?;
+    Ok(AccountSwitchResponse {
+        info: Some(info).into(),
+        ..Default::default()
+    })
+}
+
+pub fn handle_account_remove(
+    request: AccountRemoveRequest,
+) -> ServiceResult<AccountRemoveResponse> {
+    let accounts = auth::remove_account(request.get_uid(), request.get_clear_cache())?;
+    Ok(AccountRemoveResponse {
+        accounts: accounts.into(),
+        ..Default::default()
+    })
+}
+
 pub fn handle_content_parse(request: ContentParseRequest) -> ServiceResult<ContentParseResponse> {
     let content = text::parse_content(request.get_raw());
+    let plain_text = text::to_plain_text(content.get_spans());
+    let summary = match request.get_summary_length() {
+        0 => String::new(),
+        length => text::summarize(content.get_spans(), length as usize),
+    };
+
     Ok(ContentParseResponse {
         content: Some(content).into(),
+        plain_text,
+        summary,
         ..Default::default()
     })
 }
@@ -70,3 +135,21 @@ pub fn handle_update_topic_progress(
     history::update_topic_progress(request);
     Ok(Default::default())
 }
+
+pub fn handle_block_word_list(
+    request: BlockWordListRequest,
+) -> ServiceResult<BlockWordListResponse> {
+    block::get_block_words(request)
+}
+
+pub fn handle_block_word_modify(
+    request: BlockWordModifyRequest,
+) -> ServiceResult<BlockWordModifyResponse> {
+    block::modify_block_word(request)
+}
+
+pub fn handle_topic_download_progress(
+    request: TopicDownloadProgressRequest,
+) -> ServiceResult<TopicDownloadProgressResponse> {
+    download::get_topic_download_progress(request)
+}
@@ -51,6 +51,11 @@ mod dispatch_async {
             clock_in(r) => r!(handle_clock_in(r)),
             cache(r) => r!(handle_cache(r)),
             user_signature_update(r) => r!(handle_user_signature_update(r)),
+            topic_download(r) => r!(handle_topic_download(r)),
+            topic_export(r) => r!(handle_topic_export(r)),
+            local_search(r) => r!(handle_local_search(r)),
+            cache_backup(r) => r!(handle_cache_backup(r)),
+            cache_restore(r) => r!(handle_cache_restore(r)),
         }
     }
 }
@@ -81,6 +86,12 @@ mod dispatch_sync {
             set_request_option(r) => r!(handle_set_request_option(r)),
             invalidate_client(r) => r!(handle_invalidate_client(r)),
             update_topic_progress(r) => r!(handle_update_topic_progress(r)),
+            block_word_list(r) => r!(handle_block_word_list(r)),
+            block_word_modify(r) => r!(handle_block_word_modify(r)),
+            topic_download_progress(r) => r!(handle_topic_download_progress(r)),
+            account_list(r) => r!(handle_account_list(r)),
+            account_switch(r) => r!(handle_account_switch(r)),
+            account_remove(r) => r!(handle_account_remove(r)),
         }
     }
 }
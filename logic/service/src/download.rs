@@ -0,0 +1,220 @@
+use cache::CACHE;
+use chrono::Utc;
+use protos::{
+    DataModel::TopicDownloadProgress,
+    Service::{
+        TopicDetailsRequest, TopicDetailsResponse, TopicDownloadProgressRequest,
+        TopicDownloadProgressResponse, TopicDownloadRequest, TopicDownloadResponse,
+    },
+};
+
+use crate::{
+    error::ServiceResult,
+    progress,
+    topic::{get_topic_details, topic_details_response_key},
+};
+
+pub static TOPIC_DOWNLOAD_PREFIX: &str = "/topic_download/topic";
+fn topic_download_key(topic_id: &str) -> String {
+    format!("{}/{}", TOPIC_DOWNLOAD_PREFIX, topic_id)
+}
+
+async fn save_progress(progress: &mut TopicDownloadProgress) {
+    progress.updated_at = Utc::now().timestamp_millis() as u64;
+    let _ = CACHE
+        .insert_msg_async(&topic_download_key(progress.get_topic_id()), progress)
+        .await;
+}
+
+/// Whether the page is recorded as downloaded and still exists in the cache.
+async fn is_downloaded(progress: &TopicDownloadProgress, request: &TopicDetailsRequest) -> bool {
+    if !progress.get_downloaded_pages().contains(&request.page) {
+        return false;
+    }
+    let Some(key) = topic_details_response_key(request) else {
+        return false;
+    };
+    matches!(
+        CACHE.get_msg_async::<TopicDetailsResponse>(&key).await,
+        Ok(Some(_))
+    )
+}
+
+/// Download all pages of a topic into the cache, so that they can be read later with
+/// `local_cache`. The progress is saved after every page, thus the download can be resumed by
+/// sending the same request again. The last page is always fetched again, since it may have
+/// got new replies.
+pub async fn download_topic(request: TopicDownloadRequest) -> ServiceResult<TopicDownloadResponse> {
+    let topic_id = request.get_topic_id();
+
+    let mut progress = if request.get_force() {
+        None
+    } else {
+        CACHE
+            .get_msg_async::<TopicDownloadProgress>(&topic_download_key(topic_id))
+            .await?
+    }
+    .unwrap_or_else(|| TopicDownloadProgress {
+        topic_id: topic_id.to_owned(),
+        ..Default::default()
+    });
+    progress.set_finished(false);
+
+    let details_request = |page| TopicDetailsRequest {
+        topic_id: topic_id.to_owned(),
+        fav: request.get_fav().to_owned(),
+        page,
+        web_api_strategy: request.get_web_api_strategy(),
+        ..Default::default()
+    };
+
+    let mut page = 1;
+    loop {
+        let last_known_page = progress.get_total_pages().max(1);
+        if page > last_known_page {
+            break;
+        }
+
+        let request = details_request(page);
+        if page == last_known_page || !is_downloaded(&progress, &request).await {
+            let response = get_topic_details(request).await?;
+            // The topic may have grown since the last download.
+            progress.set_total_pages(response.get_pages().max(progress.get_total_pages()));
+            if !progress.get_downloaded_pages().contains(&page) {
+                progress.mut_downloaded_pages().push(page);
+            }
+            save_progress(&mut progress).await;
+        }
+        progress::report_partial(page as u64, progress.get_total_pages() as u64, &progress);
+
+        page += 1;
+    }
+
+    progress.mut_downloaded_pages().sort_unstable();
+    progress.set_finished(true);
+    save_progress(&mut progress).await;
+
+    Ok(TopicDownloadResponse {
+        progress: Some(progress).into(),
+        ..Default::default()
+    })
+}
+
+pub fn get_topic_download_progress(
+    request: TopicDownloadProgressRequest,
+) -> ServiceResult<TopicDownloadProgressResponse> {
+    let progress =
+        CACHE.get_msg::<TopicDownloadProgress>(&topic_download_key(request.get_topic_id()))?;
+
+    Ok(TopicDownloadProgressResponse {
+        progress: progress.into(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use protos::Service::AsyncProgress;
+
+    use super::*;
+    use crate::{
+        fake_nga::{FakeNga, FakePost},
+        progress::ProgressSink,
+    };
+
+    fn add_posts(server: &FakeNga, count: usize) -> String {
+        let mut state = server.state();
+        let topic = &mut state.topics[0];
+        let template = topic.posts[1].clone();
+        for _ in 0..count {
+            let pid = format!("{}{}", template.pid, topic.posts.len());
+            topic.posts.push(FakePost {
+                pid,
+                ..template.clone()
+            });
+        }
+        topic.tid.clone()
+    }
+
+    fn read_requests(server: &FakeNga, page: u32) -> usize {
+        let needle = format!("page={}&", page);
+        (server.state().requests.iter())
+            .filter(|r| r.starts_with("/read.php") && r.contains(&needle))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_download_topic() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let topic_id = add_posts(&server, 40); // 42 posts, 3 pages
+        let download = |force| {
+            download_topic(TopicDownloadRequest {
+                topic_id: topic_id.clone(),
+                force,
+                ..Default::default()
+            })
+        };
+
+        server
+            .scope(async {
+                let progress = download(false).await?.take_progress();
+                assert!(progress.get_finished());
+                assert_eq!(progress.get_total_pages(), 3);
+                assert_eq!(progress.get_downloaded_pages(), [1, 2, 3]);
+
+                let cached = get_topic_details(TopicDetailsRequest {
+                    topic_id: topic_id.clone(),
+                    page: 3,
+                    local_cache: true,
+                    ..Default::default()
+                })
+                .await?;
+                assert!(cached.get_is_local_cache());
+                assert_eq!(cached.get_replies().len(), 2);
+
+                let stored = get_topic_download_progress(TopicDownloadProgressRequest {
+                    topic_id: topic_id.clone(),
+                    ..Default::default()
+                })?;
+                assert_eq!(stored.get_progress(), &progress);
+
+                Ok::<_, crate::error::ServiceError>(())
+            })
+            .await?;
+
+        // Resume after the topic grows: only the last page and the new page are fetched.
+        add_posts(&server, 20);
+        let reported = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let reported = reported.clone();
+            Arc::new(move |p: AsyncProgress| reported.lock().unwrap().push(p.done)) as ProgressSink
+        };
+        server
+            .scope(progress::with_progress(sink, async {
+                let progress = download(false).await?.take_progress();
+                assert_eq!(progress.get_downloaded_pages(), [1, 2, 3, 4]);
+                Ok::<_, crate::error::ServiceError>(())
+            }))
+            .await?;
+        assert_eq!(*reported.lock().unwrap(), [1, 2, 3, 4]);
+        assert_eq!(
+            (1..=4)
+                .map(|p| read_requests(&server, p))
+                .collect::<Vec<_>>(),
+            [1, 1, 2, 1]
+        );
+
+        // Force to download all pages again.
+        server.scope(download(true)).await?;
+        assert_eq!(
+            (1..=4)
+                .map(|p| read_requests(&server, p))
+                .collect::<Vec<_>>(),
+            [2, 2, 3, 2]
+        );
+
+        Ok(())
+    }
+}
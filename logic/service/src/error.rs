@@ -1,4 +1,7 @@
-use protos::DataModel::ErrorMessage;
+use protos::{
+    DataModel::ErrorMessage,
+    Service::{ServiceErrorResponse, ServiceErrorResponse_Kind},
+};
 use reqwest::StatusCode;
 use std::any;
 use thiserror::Error;
@@ -34,14 +37,61 @@ pub enum ServiceError {
     #[error(transparent)]
     Cache(#[from] cache::CacheError),
     #[error(transparent)]
+    Config(#[from] config::ConfigError),
+    #[error(transparent)]
     TextParse(#[from] text::error::ParseError),
     #[error(transparent)]
     UrlParse(#[from] url::ParseError),
     #[error(transparent)]
     Protobuf(#[from] protos::ProtobufError),
+    #[error(transparent)]
+    ProtobufJson(#[from] protos::json::JsonError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
 
     #[error("{0}")]
     Panic(String),
+    #[error("Request cancelled")]
+    Cancelled,
+    /// The local cache has been tried as a fallback of the error, but nothing is cached.
+    #[error(transparent)]
+    LocalCacheMissed(Box<ServiceError>),
+}
+
+/// Specific kind of the known NGA errors. Codes are not reliable for this, as most of them are
+/// just `0` or `?`, so the info is also checked.
+fn nga_kind(e: &ErrorMessage) -> ServiceErrorResponse_Kind {
+    use ServiceErrorResponse_Kind as Kind;
+
+    const KEYWORDS: &[(Kind, &[&str])] = &[
+        (
+            Kind::NOT_LOGGED_IN,
+            &["未登录", "请登录", "请先登录", "需要登录", "访客不能"],
+        ),
+        (
+            Kind::PERMISSION_DENIED,
+            &["没有权限", "无权", "权限不足", "你不能"],
+        ),
+        (
+            Kind::TOPIC_DELETED,
+            &["已被删除", "帖子不存在", "主题不存在"],
+        ),
+        (Kind::RATE_LIMITED, &["太快", "频繁", "稍后再试"]),
+    ];
+
+    let info = e.get_info();
+    KEYWORDS
+        .iter()
+        .find(|(_, keywords)| keywords.iter().any(|k| info.contains(k)))
+        .map(|(kind, _)| *kind)
+        .unwrap_or(match e.get_code() {
+            "401" => Kind::NOT_LOGGED_IN,
+            "403" => Kind::PERMISSION_DENIED,
+            "429" => Kind::RATE_LIMITED,
+            _ => Kind::NGA,
+        })
 }
 
 impl ServiceError {
@@ -56,10 +106,16 @@ impl ServiceError {
             ServiceError::JsonParse(_) => "JSON Parse",
             ServiceError::XPath(_) => "XPath Resolve",
             ServiceError::Cache(_) => "Cache",
+            ServiceError::Config(_) => "Configuration",
             ServiceError::TextParse(_) => "Text Parse",
             ServiceError::UrlParse(_) => "URL Parse",
             ServiceError::Protobuf(_) => "Protocol Buffer Encoding",
+            ServiceError::ProtobufJson(_) => "Protocol Buffer JSON Mapping",
+            ServiceError::Io(_) => "IO",
+            ServiceError::Zip(_) => "Zip Archive",
             ServiceError::Panic(_) => "Backend Panic",
+            ServiceError::Cancelled => "Cancelled",
+            ServiceError::LocalCacheMissed(e) => e.to_kind(),
         }
     }
 
@@ -76,6 +132,74 @@ impl ServiceError {
         format!("{}|{}", self.to_kind(), self)
     }
 
+    /// Structured error for the front ends, whose `kind_name` and `message` are the same as those
+    /// in `to_app_string`.
+    pub fn to_response(&self) -> ServiceErrorResponse {
+        use ServiceErrorResponse_Kind as Kind;
+
+        if let ServiceError::LocalCacheMissed(e) = self {
+            let mut response = e.to_response();
+            response.local_cache_missed = true;
+            return response;
+        }
+
+        let http_status = match self {
+            ServiceError::Status(e) => e.get_code().parse().unwrap_or_default(),
+            ServiceError::Reqwest(e) => e.status().map_or(0, |s| s.as_u16() as u32),
+            _ => 0,
+        };
+
+        let kind = match self {
+            ServiceError::MngaInternal(_) => Kind::MNGA,
+            ServiceError::Status(_) => match http_status {
+                401 => Kind::NOT_LOGGED_IN,
+                403 => Kind::PERMISSION_DENIED,
+                429 => Kind::RATE_LIMITED,
+                _ => Kind::ERROR_RESPONSE,
+            },
+            ServiceError::Nga(e) => nga_kind(e),
+            ServiceError::MissingField(_) => Kind::MISSING_FIELD,
+            ServiceError::Reqwest(_) => Kind::NETWORK_CONNECTION,
+            ServiceError::XmlParse(_) => Kind::XML_PARSE,
+            ServiceError::JsonParse(_) => Kind::JSON_PARSE,
+            ServiceError::XPath(_) => Kind::XPATH_RESOLVE,
+            ServiceError::Cache(_) => Kind::CACHE,
+            ServiceError::Config(_) => Kind::CONFIGURATION,
+            ServiceError::TextParse(_) => Kind::TEXT_PARSE,
+            ServiceError::UrlParse(_) => Kind::URL_PARSE,
+            ServiceError::Protobuf(_) => Kind::PROTOBUF,
+            ServiceError::ProtobufJson(_) => Kind::PROTOBUF_JSON,
+            ServiceError::Io(_) => Kind::IO,
+            ServiceError::Zip(_) => Kind::ZIP_ARCHIVE,
+            ServiceError::Panic(_) => Kind::BACKEND_PANIC,
+            ServiceError::Cancelled => Kind::CANCELLED,
+            ServiceError::LocalCacheMissed(_) => unreachable!(),
+        };
+
+        let retryable = match kind {
+            Kind::NETWORK_CONNECTION | Kind::XML_PARSE | Kind::JSON_PARSE | Kind::RATE_LIMITED => {
+                true
+            }
+            Kind::ERROR_RESPONSE => http_status >= 500,
+            _ => false,
+        };
+
+        let nga = match self {
+            ServiceError::Status(e) | ServiceError::Nga(e) => Some(e.clone()),
+            _ => None,
+        };
+
+        ServiceErrorResponse {
+            kind,
+            kind_name: self.to_kind().to_owned(),
+            message: self.to_string(),
+            nga: nga.into(),
+            http_status,
+            retryable,
+            ..Default::default()
+        }
+    }
+
     /// Whether the error is caused by response parse error. If so, it's likely to be NGA blocking.
     pub fn is_response_parse_error(&self) -> bool {
         matches!(self, ServiceError::XmlParse(_) | ServiceError::JsonParse(_))
@@ -83,3 +207,57 @@ impl ServiceError {
 }
 
 pub type ServiceResult<T> = Result<T, ServiceError>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nga(code: &str, info: &str) -> ServiceError {
+        ServiceError::Nga(ErrorMessage {
+            code: code.to_owned(),
+            info: info.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_to_response() {
+        use ServiceErrorResponse_Kind as Kind;
+
+        let response = nga("2", "帖子不存在或已被删除").to_response();
+        assert_eq!(response.get_kind(), Kind::TOPIC_DELETED);
+        assert_eq!(response.get_kind_name(), "NGA");
+        assert_eq!(response.get_message(), "帖子不存在或已被删除 (2)");
+        assert_eq!(response.get_nga().get_code(), "2");
+        assert!(!response.get_retryable());
+
+        assert_eq!(
+            nga("?", "你不能编辑这个帖子").to_response().get_kind(),
+            Kind::PERMISSION_DENIED
+        );
+        assert_eq!(
+            nga("0", "你需要登录").to_response().get_kind(),
+            Kind::NOT_LOGGED_IN
+        );
+        assert_eq!(
+            nga("403", "帖子不存在").to_response().get_kind(),
+            Kind::TOPIC_DELETED
+        );
+        assert_eq!(nga("0", "版面不存在").to_response().get_kind(), Kind::NGA);
+
+        let response = ServiceError::from_status(StatusCode::TOO_MANY_REQUESTS).to_response();
+        assert_eq!(response.get_kind(), Kind::RATE_LIMITED);
+        assert_eq!(response.get_http_status(), 429);
+        assert!(response.get_retryable());
+
+        let response = ServiceError::from_status(StatusCode::BAD_GATEWAY).to_response();
+        assert_eq!(response.get_kind(), Kind::ERROR_RESPONSE);
+        assert!(response.get_retryable());
+
+        let error = ServiceError::LocalCacheMissed(Box::new(nga("0", "访客不能直接访问")));
+        assert_eq!(error.to_app_string(), "NGA|访客不能直接访问 (0)");
+        let response = error.to_response();
+        assert_eq!(response.get_kind(), Kind::NOT_LOGGED_IN);
+        assert!(response.get_local_cache_missed());
+    }
+}
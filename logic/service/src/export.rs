@@ -0,0 +1,377 @@
+//! Export of topics to documents, so that good threads can be archived outside of the app.
+//!
+//! All pages of the topic are collected first, either from the network or from the local cache,
+//! then the content spans of every post are rendered by the renderer of the requested format.
+//! Images are referenced by their remote URLs and are not embedded.
+
+mod epub;
+mod html;
+mod markdown;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset};
+use protos::{
+    DataModel::{Post, Span, Span_oneof_value, Topic},
+    Service::{
+        TopicDetailsRequest, TopicExportRequest, TopicExportRequest_Format, TopicExportResponse,
+    },
+};
+use url::Url;
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    topic::get_topic_details,
+    user::UserController,
+};
+
+const EXPORT_DIR: &str = "exports";
+const ATTACHMENT_BASE: &str = "https://img.nga.cn/attachments/";
+const WEB_BASE: &str = "https://bbs.nga.cn/";
+
+/// Legacy image hosts that are no longer available, see `URLs.resourceURL` of the app.
+const LEGACY_IMAGE_DOMAIN_SUFFIXES: &[&str] = &[".nga.178.com", ".ngacn.cc", ".ngabbs.com"];
+
+/// A topic with the posts of all pages, ready to be rendered.
+pub struct ExportedTopic {
+    topic: Topic,
+    forum_name: String,
+    posts: Vec<Post>,
+    pages: u32,
+}
+
+impl ExportedTopic {
+    fn title(&self) -> String {
+        let subject = self.topic.get_subject();
+        (subject.get_tags().iter())
+            .map(|t| format!("[{}]", t))
+            .chain([subject.get_content().to_owned()])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn url(&self) -> String {
+        topic_url(self.topic.get_id())
+    }
+
+    fn author_name(&self, post: &Post) -> String {
+        let id = post.get_author_id();
+        UserController::get()
+            .get_by_id(id)
+            .map(|u| {
+                let name = u.get_name();
+                match name.get_anonymous() {
+                    "" => name.get_normal().to_owned(),
+                    anonymous => anonymous.to_owned(),
+                }
+            })
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| format!("UID {}", id))
+    }
+
+    fn post_date(&self, post: &Post) -> String {
+        format_date(post.get_post_date())
+    }
+
+    fn posts_of_page(&self, page: u32) -> impl Iterator<Item = &Post> {
+        self.posts.iter().filter(move |p| p.get_at_page() == page)
+    }
+}
+
+fn format_date(timestamp: u64) -> String {
+    const HOUR: i32 = 3600;
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.with_timezone(&FixedOffset::east_opt(8 * HOUR).unwrap()))
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Resolve the URL of an image or other attachment, which is usually relative like
+/// `./mon_202107/03/xxx.jpg`.
+fn attachment_url(value: &str) -> String {
+    let value = value.trim();
+    let Some(mut url) = Url::parse(ATTACHMENT_BASE)
+        .ok()
+        .and_then(|base| base.join(value).ok())
+    else {
+        return value.to_owned();
+    };
+
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let is_legacy = host.split_once('.').is_some_and(|(first, _)| {
+        first
+            .strip_prefix("img")
+            .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+    }) && LEGACY_IMAGE_DOMAIN_SUFFIXES
+        .iter()
+        .any(|suffix| host.ends_with(suffix));
+
+    if is_legacy {
+        let host = if url.path().starts_with("/ngabbs/") {
+            "img4.nga.cn"
+        } else {
+            "img.nga.cn"
+        };
+        let _ = url.set_scheme("https");
+        let _ = url.set_host(Some(host));
+    }
+    url.to_string()
+}
+
+/// Resolve the URL of a link, which may be relative to the NGA website.
+fn link_url(value: &str) -> String {
+    let value = value.trim();
+    Url::parse(WEB_BASE)
+        .ok()
+        .and_then(|base| base.join(value).ok())
+        .map(|u| u.to_string())
+        .unwrap_or_else(|| value.to_owned())
+}
+
+fn topic_url(tid: &str) -> String {
+    format!("{}read.php?tid={}", WEB_BASE, tid)
+}
+
+fn post_url(pid: &str) -> String {
+    format!("{}read.php?pid={}", WEB_BASE, pid)
+}
+
+fn user_url(uid: &str) -> String {
+    format!("{}nuke.php?func=ucp&uid={}", WEB_BASE, uid)
+}
+
+fn user_name_url(name: &str) -> String {
+    let name = url::form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>();
+    format!("{}nuke.php?func=ucp&username={}", WEB_BASE, name)
+}
+
+/// Text of the plain spans, which is the URL of `img`, `url` and similar tags.
+fn plain_text(spans: &[Span]) -> String {
+    spans
+        .iter()
+        .filter_map(|s| match &s.value {
+            Some(Span_oneof_value::plain(p)) => Some(p.get_text()),
+            _ => None,
+        })
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// Resolve the directory where exported documents are placed.
+fn export_dir() -> ServiceResult<PathBuf> {
+    config::CONF
+        .get()
+        .map(|conf| conf.document_dir_path.join(EXPORT_DIR))
+        .ok_or_else(|| {
+            ServiceError::MngaInternal("Document directory is not configured".to_owned())
+        })
+}
+
+async fn collect_topic(request: &TopicExportRequest) -> ServiceResult<ExportedTopic> {
+    let details_request = |page| TopicDetailsRequest {
+        topic_id: request.get_topic_id().to_owned(),
+        fav: request.get_fav().to_owned(),
+        page,
+        local_cache: request.get_local_cache(),
+        web_api_strategy: request.get_web_api_strategy(),
+        ..Default::default()
+    };
+
+    let mut first = get_topic_details(details_request(1)).await?;
+    let mut exported = ExportedTopic {
+        topic: first.take_topic(),
+        forum_name: first.take_forum_name(),
+        posts: first.take_replies().into_vec(),
+        pages: first.get_pages().max(1),
+    };
+
+    let mut page = 2;
+    while page <= exported.pages {
+        let mut response = get_topic_details(details_request(page)).await?;
+        // Later pages of the local cache may be newer than the first one.
+        exported.pages = exported.pages.max(response.get_pages());
+        exported.posts.extend(response.take_replies());
+        page += 1;
+    }
+
+    Ok(exported)
+}
+
+async fn export_topic_to_dir(
+    request: TopicExportRequest,
+    dir: &Path,
+) -> ServiceResult<TopicExportResponse> {
+    use TopicExportRequest_Format::*;
+
+    let topic = collect_topic(&request).await?;
+    let (extension, content) = match request.get_format() {
+        MARKDOWN => ("md", markdown::render(&topic).into_bytes()),
+        HTML => ("html", html::render(&topic).into_bytes()),
+        EPUB => ("epub", epub::render(&topic)?),
+    };
+
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("topic-{}.{}", topic.topic.get_id(), extension));
+    fs::write(&path, content)?;
+
+    Ok(TopicExportResponse {
+        path: path.to_string_lossy().into_owned(),
+        pages: topic.pages,
+        posts: topic.posts.len() as u32,
+        ..Default::default()
+    })
+}
+
+/// Export all pages of a topic into a document under `Configuration.document_dir_path`.
+pub async fn export_topic(request: TopicExportRequest) -> ServiceResult<TopicExportResponse> {
+    let dir = export_dir()?;
+    export_topic_to_dir(request, &dir).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, io::Read};
+
+    use super::*;
+    use crate::{
+        fake_nga::{FakeNga, FakePost},
+        utils::get_unique_id,
+    };
+
+    const CONTENT: &str = "[quote][pid=1,2,1]Reply[/pid] [b]Post by [uid=42]Alice[/uid] (2021-06-28 16:29):[/b]<br/>Quoted & <escaped>[/quote]\
+        Hello [s:a2:不明觉厉]<br/>[@Bob] hi<br/>\
+        [img]./mon_202107/03/a.jpg[/img]\
+        ===Section===\
+        [table][tr][td20]A[/td][td]B|C[/td][/tr][tr][td]1[/td][td]2[/td][/tr][/table]";
+
+    fn exported_topic(content: &str) -> ExportedTopic {
+        let post = Post {
+            author_id: "42".to_owned(),
+            floor: 0,
+            at_page: 1,
+            content: Some(text::parse_content(content)).into(),
+            ..Default::default()
+        };
+        let mut topic = Topic::new();
+        topic.set_id("233".to_owned());
+        topic.mut_subject().set_content("Subject <1>".to_owned());
+
+        ExportedTopic {
+            topic,
+            forum_name: "Forum".to_owned(),
+            posts: vec![post],
+            pages: 1,
+        }
+    }
+
+    #[test]
+    fn test_attachment_url() {
+        assert_eq!(
+            attachment_url("./mon_202107/03/a.jpg"),
+            "https://img.nga.cn/attachments/mon_202107/03/a.jpg"
+        );
+        assert_eq!(
+            attachment_url("http://img8.nga.178.com/attachments/a.jpg"),
+            "https://img.nga.cn/attachments/a.jpg"
+        );
+        assert_eq!(
+            attachment_url("http://img.ngacn.cc/ngabbs/a.png"),
+            "https://img4.nga.cn/ngabbs/a.png"
+        );
+        assert_eq!(
+            attachment_url("https://example.com/a.png"),
+            "https://example.com/a.png"
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let md = markdown::render(&exported_topic(CONTENT));
+        println!("{}", md);
+
+        assert!(md.starts_with("# Subject \\<1\\>\n"));
+        assert!(md.contains(
+            "**Post by [Alice](https://bbs.nga.cn/nuke.php?func=ucp&uid=42) (2021-06-28 16:29):**"
+        ));
+        assert!(md.contains("> Quoted & \\<escaped\\>"));
+        assert!(md.contains(
+            "Hello \\[不明觉厉\\]  \n[@Bob](https://bbs.nga.cn/nuke.php?func=ucp&username=Bob) hi"
+        ));
+        assert!(md.contains("![](https://img.nga.cn/attachments/mon_202107/03/a.jpg)"));
+        assert!(md.contains("---\n\n**Section**"));
+        assert!(md.contains("| A | B\\|C |\n| --- | --- |\n| 1 | 2 |"));
+    }
+
+    #[test]
+    fn test_render_html() {
+        let html = html::render(&exported_topic(CONTENT));
+        println!("{}", html);
+
+        assert!(html.contains("<title>Subject &lt;1&gt;</title>"));
+        assert!(html.contains("<blockquote>"));
+        assert!(html.contains("Quoted &amp; &lt;escaped&gt;"));
+        assert!(html.contains(r#"<span class="sticker" title="a2:不明觉厉">[不明觉厉]</span>"#));
+        assert!(html.contains(r#"<img src="https://img.nga.cn/attachments/mon_202107/03/a.jpg""#));
+        assert!(html.contains("<table><tr>"));
+        assert!(html.contains("<td>B|C</td>"));
+        assert!(html.contains("Section"));
+    }
+
+    #[tokio::test]
+    async fn test_export_topic() -> ServiceResult<()> {
+        use TopicExportRequest_Format::*;
+
+        let server = FakeNga::start();
+        let dir = env::temp_dir().join(format!("mnga-export-{}", get_unique_id()));
+        let topic_id = {
+            let mut state = server.state();
+            let topic = &mut state.topics[0];
+            let template = topic.posts[1].clone();
+            for i in 0..25 {
+                topic.posts.push(FakePost {
+                    pid: format!("{}{}", template.pid, i),
+                    content: format!("[quote]Reply {}[/quote]", i),
+                    ..template.clone()
+                });
+            }
+            topic.tid.clone()
+        };
+        let export = |format, local_cache| {
+            export_topic_to_dir(
+                TopicExportRequest {
+                    topic_id: topic_id.clone(),
+                    format,
+                    local_cache,
+                    ..Default::default()
+                },
+                &dir,
+            )
+        };
+
+        let response = server.scope(export(EPUB, false)).await?;
+        assert_eq!(response.get_pages(), 2);
+        assert_eq!(response.get_posts(), 27);
+
+        let file = fs::File::open(response.get_path())?;
+        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let mut mimetype = String::new();
+        archive.by_index(0).unwrap().read_to_string(&mut mimetype)?;
+        assert_eq!(mimetype, "application/epub+zip");
+        let mut page = String::new();
+        (archive.by_name("OEBPS/page-2.xhtml").unwrap()).read_to_string(&mut page)?;
+        assert!(page.contains("<blockquote>Reply 24</blockquote>"));
+
+        // Export again from the pages cached above.
+        let response = export(MARKDOWN, true).await?;
+        let md = fs::read_to_string(response.get_path())?;
+        assert!(md.contains("**First!**"));
+        assert!(md.contains("> Reply 24"));
+
+        let _ = fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! EPUB 3 container with one chapter per page of the topic.
+
+use std::io::{Cursor, Write};
+
+use chrono::Utc;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use super::{ExportedTopic, html};
+use crate::error::ServiceResult;
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn chapter_name(page: u32) -> String {
+    format!("page-{}.xhtml", page)
+}
+
+fn package(topic: &ExportedTopic, chapters: &[(u32, String)]) -> String {
+    let manifest = chapters
+        .iter()
+        .map(|(page, content)| {
+            // Images are not embedded, which must be declared for the chapter.
+            let properties = if content.contains("<img ") {
+                " properties=\"remote-resources\""
+            } else {
+                ""
+            };
+            format!(
+                "    <item id=\"page-{page}\" href=\"{}\" media-type=\"application/xhtml+xml\"{properties}/>\n",
+                chapter_name(*page)
+            )
+        })
+        .collect::<String>();
+    let spine = chapters
+        .iter()
+        .map(|(page, _)| format!("    <itemref idref=\"page-{}\"/>\n", page))
+        .collect::<String>();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id" xml:lang="zh">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">{url}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:language>zh</dc:language>
+    <dc:source>{url}</dc:source>
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+        url = html_escape(&topic.url()),
+        title = html_escape(&topic.title()),
+        modified = Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+    )
+}
+
+fn nav(topic: &ExportedTopic, chapters: &[(u32, String)]) -> String {
+    let items = chapters
+        .iter()
+        .map(|(page, _)| {
+            format!(
+                "<li><a href=\"{}\">Page {}</a></li>\n",
+                chapter_name(*page),
+                page
+            )
+        })
+        .collect::<String>();
+    let body = format!("<nav epub:type=\"toc\">\n<h1>Contents</h1>\n<ol>\n{items}</ol>\n</nav>\n");
+
+    html::document(&topic.title(), &body, true).replacen(
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"",
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\"",
+        1,
+    )
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn render(topic: &ExportedTopic) -> ServiceResult<Vec<u8>> {
+    let chapters = (1..=topic.pages)
+        .filter_map(|page| {
+            let mut posts = topic.posts_of_page(page).peekable();
+            posts.peek()?;
+            let mut body = html::posts(topic, posts);
+            if page == 1 {
+                body = html::header(topic) + &body;
+            }
+            let title = format!("{} - Page {}", topic.title(), page);
+            Some((page, html::document(&title, &body, true)))
+        })
+        .collect::<Vec<_>>();
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The `mimetype` must be the first entry and not compressed.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(package(topic, &chapters).as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(nav(topic, &chapters).as_bytes())?;
+    for (page, content) in &chapters {
+        zip.start_file(format!("OEBPS/{}", chapter_name(*page)), deflated)?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
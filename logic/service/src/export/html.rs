@@ -0,0 +1,276 @@
+use std::fmt::Write;
+
+use protos::DataModel::{Post, Span, Span_Tagged, Span_oneof_value};
+use text::sticker_name;
+
+use super::{
+    ExportedTopic, attachment_url, link_url, plain_text, post_url, topic_url, user_name_url,
+    user_url,
+};
+
+const STYLE: &str = "\
+body { max-width: 48em; margin: 0 auto; padding: 1em; font-family: sans-serif; line-height: 1.6; }
+.meta, .post header { color: #888; font-size: 0.9em; }
+.post { border-top: 1px solid #ddd; padding: 0.5em 0; }
+blockquote { margin: 0.5em 0; padding: 0.2em 1em; border-left: 3px solid #ccc; background: #f7f7f7; }
+img { max-width: 100%; }
+table { border-collapse: collapse; }
+td { border: 1px solid #ccc; padding: 0.2em 0.5em; }
+.sticker { color: #888; }
+";
+
+/// Render a standalone HTML page of the whole topic.
+pub fn render(topic: &ExportedTopic) -> String {
+    let body = header(topic) + &posts(topic, topic.posts.iter());
+    document(&topic.title(), &body, false)
+}
+
+/// Wrap the body into an HTML document, or an XHTML one if `xhtml` is set, which is required
+/// by EPUB. The markup of the body is always valid XHTML.
+pub fn document(title: &str, body: &str, xhtml: bool) -> String {
+    let (prolog, html) = if xhtml {
+        (
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n",
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\" lang=\"zh\" xml:lang=\"zh\">",
+        )
+    } else {
+        ("<!DOCTYPE html>\n", "<html lang=\"zh\">")
+    };
+
+    format!(
+        "{prolog}{html}\n<head>\n<meta charset=\"utf-8\"/>\n<title>{}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        escape(title),
+    )
+}
+
+pub fn header(topic: &ExportedTopic) -> String {
+    format!(
+        "<h1>{}</h1>\n<p class=\"meta\">{} · <a href=\"{url}\">{url}</a></p>\n",
+        escape(&topic.title()),
+        escape(&topic.forum_name),
+        url = escape(&topic.url()),
+    )
+}
+
+pub fn posts<'a>(topic: &ExportedTopic, posts: impl Iterator<Item = &'a Post>) -> String {
+    let mut out = String::new();
+    for post in posts {
+        let content = if post.get_blocked() {
+            "<p><em>This post is blocked.</em></p>".to_owned()
+        } else {
+            render_spans(post.get_content().get_spans())
+        };
+        let _ = writeln!(
+            out,
+            "<article class=\"post\" id=\"floor-{floor}\">\n<header>#{floor} {} · {}</header>\n<div class=\"content\">{}</div>\n</article>",
+            escape(&topic.author_name(post)),
+            topic.post_date(post),
+            content,
+            floor = post.get_floor(),
+        );
+    }
+    out
+}
+
+fn render_spans(spans: &[Span]) -> String {
+    let mut renderer = Html::default();
+    renderer.spans(spans);
+    renderer.out
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\n' => escaped.push_str("<br/>"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Default)]
+struct Html {
+    out: String,
+    in_list: bool,
+}
+
+impl Html {
+    fn inner(&self, spans: &[Span]) -> String {
+        let mut renderer = Self {
+            in_list: self.in_list,
+            ..Default::default()
+        };
+        renderer.spans(spans);
+        renderer.out
+    }
+
+    fn spans(&mut self, spans: &[Span]) {
+        spans.iter().for_each(|s| self.span(s));
+    }
+
+    fn span(&mut self, span: &Span) {
+        match &span.value {
+            Some(Span_oneof_value::plain(plain)) => {
+                let text = plain.get_text();
+                if self.in_list {
+                    let items = text.split("[*]").map(escape).collect::<Vec<_>>();
+                    self.out.push_str(&items.join("<br/>• "));
+                } else {
+                    self.out.push_str(&escape(text));
+                }
+            }
+            Some(Span_oneof_value::break_line(_)) => self.out.push_str("<br/>"),
+            Some(Span_oneof_value::sticker(sticker)) => {
+                let _ = write!(
+                    self.out,
+                    "<span class=\"sticker\" title=\"{}\">[{}]</span>",
+                    escape(sticker.get_name()),
+                    escape(sticker_name(sticker.get_name()))
+                );
+            }
+            Some(Span_oneof_value::tagged(tagged)) => self.tagged(tagged),
+            None => {}
+        }
+    }
+
+    fn wrap(&mut self, spans: &[Span], element: &str) {
+        let inner = self.inner(spans);
+        let _ = write!(self.out, "<{element}>{}</{element}>", inner);
+    }
+
+    fn link(&mut self, inner: &str, url: &str) {
+        let url = escape(url);
+        let inner = if inner.is_empty() { &url } else { inner };
+        let _ = write!(self.out, "<a href=\"{}\">{}</a>", url, inner);
+    }
+
+    fn tagged(&mut self, tagged: &Span_Tagged) {
+        let spans = tagged.get_spans();
+        let attribute = tagged.get_attributes().first().map(String::as_str);
+
+        match tagged.get_tag() {
+            "_divider" => {
+                let title = self.inner(spans);
+                self.out.push_str("<hr/>");
+                if !title.trim().is_empty() {
+                    let _ = write!(self.out, "<h4>{}</h4>", title);
+                }
+            }
+            "quote" => self.wrap(spans, "blockquote"),
+            "img" => {
+                let url = attachment_url(&plain_text(spans));
+                if url.ends_with(".mp4") {
+                    self.link("", &url);
+                } else {
+                    let _ = write!(self.out, "<img src=\"{}\" alt=\"\"/>", escape(&url));
+                }
+            }
+            "album" => {
+                for span in spans {
+                    if let Some(Span_oneof_value::plain(p)) = &span.value
+                        && !p.get_text().trim().is_empty()
+                    {
+                        let url = attachment_url(p.get_text());
+                        let _ = write!(self.out, "<img src=\"{}\" alt=\"\"/>", escape(&url));
+                    }
+                }
+            }
+            "flash" | "attach" | "noimg" => {
+                let url = attachment_url(&plain_text(spans));
+                self.link("", &url);
+            }
+            "b" => self.wrap(spans, "strong"),
+            "i" => self.wrap(spans, "em"),
+            "u" => self.wrap(spans, "u"),
+            "del" => self.wrap(spans, "del"),
+            "code" => {
+                let inner = self.inner(spans);
+                let _ = write!(self.out, "<pre><code>{}</code></pre>", inner);
+            }
+            "url" => {
+                let inner = self.inner(spans);
+                let url = link_url(attribute.unwrap_or(&plain_text(spans)));
+                self.link(&inner, &url);
+            }
+            "uid" => {
+                let inner = self.inner(spans);
+                let uid = attribute
+                    .map(ToOwned::to_owned)
+                    .unwrap_or(plain_text(spans));
+                self.link(&inner, &user_url(&uid));
+            }
+            "pid" => {
+                let inner = self.inner(spans);
+                match attribute {
+                    Some(pid) => self.link(&inner, &post_url(pid)),
+                    None => self.out.push_str(&inner),
+                }
+            }
+            "tid" => {
+                let inner = self.inner(spans);
+                match attribute {
+                    Some(tid) if inner.is_empty() => {
+                        self.link(&format!("Topic {}", tid), &topic_url(tid))
+                    }
+                    Some(tid) => self.link(&inner, &topic_url(tid)),
+                    None => self.out.push_str(&inner),
+                }
+            }
+            "at" => {
+                let name = attribute.unwrap_or_default();
+                self.link(&format!("@{}", escape(name)), &user_name_url(name));
+            }
+            "collapse" => {
+                let title = escape(attribute.unwrap_or("Collapsed"));
+                let inner = self.inner(spans);
+                let _ = write!(
+                    self.out,
+                    "<details><summary>{}</summary>{}</details>",
+                    title, inner
+                );
+            }
+            "list" => {
+                let mut renderer = Self {
+                    in_list: true,
+                    ..Default::default()
+                };
+                renderer.spans(spans);
+                let _ = write!(self.out, "<div class=\"list\">{}</div>", renderer.out);
+            }
+            // Cells may have a width like `[td20]`.
+            tag if matches!(tag, "table" | "tr") || tag.starts_with("td") => {
+                let element = if tag.starts_with("td") { "td" } else { tag };
+                let inner = self.inner(spans);
+                let attributes = (tagged.get_complex_attributes().iter())
+                    .filter_map(|a| a.split_once('='))
+                    .filter(|(k, _)| matches!(*k, "rowspan" | "colspan"))
+                    .map(|(k, v)| format!(" {}=\"{}\"", k, escape(v.trim_matches('"'))))
+                    .collect::<String>();
+                let _ = write!(self.out, "<{element}{attributes}>{}</{element}>", inner);
+            }
+            "align" => {
+                let inner = self.inner(spans);
+                match attribute {
+                    Some(align @ ("left" | "center" | "right")) => {
+                        let _ = write!(
+                            self.out,
+                            "<div style=\"text-align: {}\">{}</div>",
+                            align, inner
+                        );
+                    }
+                    _ => self.out.push_str(&inner),
+                }
+            }
+            _ => {
+                let inner = self.inner(spans);
+                self.out.push_str(&inner);
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt::Write;
+
+use protos::DataModel::{Span, Span_Tagged, Span_oneof_value};
+use text::sticker_name;
+
+use super::{
+    ExportedTopic, attachment_url, link_url, plain_text, post_url, topic_url, user_name_url,
+    user_url,
+};
+
+pub fn render(topic: &ExportedTopic) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}\n", escape(&topic.title()));
+    let _ = writeln!(out, "{} · <{}>\n", escape(&topic.forum_name), topic.url());
+
+    for post in &topic.posts {
+        let _ = writeln!(
+            out,
+            "## #{} {} · {}\n",
+            post.get_floor(),
+            escape(&topic.author_name(post)),
+            topic.post_date(post)
+        );
+        if post.get_blocked() {
+            out.push_str("*This post is blocked.*\n\n");
+        } else {
+            let content = render_spans(post.get_content().get_spans());
+            if !content.is_empty() {
+                let _ = writeln!(out, "{}\n", content);
+            }
+        }
+    }
+
+    out
+}
+
+fn render_spans(spans: &[Span]) -> String {
+    let mut renderer = Markdown::default();
+    renderer.spans(spans);
+    renderer.out.trim().to_owned()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '~' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("  \n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Default)]
+struct Markdown {
+    out: String,
+    in_list: bool,
+}
+
+impl Markdown {
+    fn inner(&self, spans: &[Span]) -> String {
+        let mut renderer = Self {
+            in_list: self.in_list,
+            ..Default::default()
+        };
+        renderer.spans(spans);
+        renderer.out.trim().to_owned()
+    }
+
+    /// Start a new block, which is separated from the surrounding text by blank lines.
+    fn block(&mut self, text: &str) {
+        let trimmed = self.out.trim_end_matches([' ', '\n']).len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() {
+            self.out.push_str("\n\n");
+        }
+        self.out.push_str(text);
+        self.out.push_str("\n\n");
+    }
+
+    fn spans(&mut self, spans: &[Span]) {
+        spans.iter().for_each(|s| self.span(s));
+    }
+
+    fn span(&mut self, span: &Span) {
+        match &span.value {
+            Some(Span_oneof_value::plain(plain)) => {
+                let text = plain.get_text();
+                if self.in_list {
+                    let items = text.split("[*]").map(escape).collect::<Vec<_>>();
+                    self.out.push_str(&items.join("\n- "));
+                } else {
+                    self.out.push_str(&escape(text));
+                }
+            }
+            Some(Span_oneof_value::break_line(_)) => self.out.push_str("  \n"),
+            Some(Span_oneof_value::sticker(sticker)) => {
+                let name = sticker_name(sticker.get_name());
+                self.out.push_str(&escape(&format!("[{}]", name)));
+            }
+            Some(Span_oneof_value::tagged(tagged)) => self.tagged(tagged),
+            None => {}
+        }
+    }
+
+    fn wrap(&mut self, spans: &[Span], mark: &str) {
+        let inner = self.inner(spans);
+        if !inner.is_empty() {
+            let _ = write!(self.out, "{}{}{}", mark, inner, mark);
+        }
+    }
+
+    fn link(&mut self, text: &str, url: &str) {
+        let text = if text.is_empty() { url } else { text };
+        let _ = write!(self.out, "[{}]({})", text, url.replace(' ', "%20"));
+    }
+
+    fn tagged(&mut self, tagged: &Span_Tagged) {
+        let spans = tagged.get_spans();
+        let attribute = tagged.get_attributes().first().map(String::as_str);
+
+        match tagged.get_tag() {
+            "_divider" => {
+                let title = self.inner(spans);
+                if title.is_empty() {
+                    self.block("---");
+                } else {
+                    self.block(&format!("---\n\n**{}**", title));
+                }
+            }
+            "quote" => {
+                let inner = self.inner(spans);
+                let quoted = (inner.lines())
+                    .map(|l| {
+                        if l.is_empty() {
+                            ">".to_owned()
+                        } else {
+                            format!("> {}", l)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.block(&quoted);
+            }
+            "img" => {
+                let url = attachment_url(&plain_text(spans));
+                if url.ends_with(".mp4") {
+                    self.link("", &url);
+                } else {
+                    let _ = write!(self.out, "![]({})", url);
+                }
+            }
+            "album" => {
+                for span in spans {
+                    if let Some(Span_oneof_value::plain(p)) = &span.value
+                        && !p.get_text().trim().is_empty()
+                    {
+                        let _ = write!(self.out, "![]({})", attachment_url(p.get_text()));
+                    }
+                }
+            }
+            "flash" | "attach" | "noimg" => {
+                let url = attachment_url(&plain_text(spans));
+                self.link("", &url);
+            }
+            "b" => self.wrap(spans, "**"),
+            "i" => self.wrap(spans, "*"),
+            "del" => self.wrap(spans, "~~"),
+            "code" => {
+                let code = spans
+                    .iter()
+                    .map(|s| match &s.value {
+                        Some(Span_oneof_value::plain(p)) => p.get_text(),
+                        Some(Span_oneof_value::break_line(_)) => "\n",
+                        _ => "",
+                    })
+                    .collect::<String>();
+                self.block(&format!("```\n{}\n```", code.trim()));
+            }
+            "url" => {
+                let inner = self.inner(spans);
+                let url = link_url(attribute.unwrap_or(&plain_text(spans)));
+                self.link(&inner, &url);
+            }
+            "uid" => {
+                let inner = self.inner(spans);
+                let uid = attribute.unwrap_or(&inner).to_owned();
+                self.link(&inner, &user_url(&uid));
+            }
+            "pid" => {
+                let inner = self.inner(spans);
+                if let Some(pid) = attribute {
+                    self.link(&inner, &post_url(pid));
+                } else {
+                    self.out.push_str(&inner);
+                }
+            }
+            "tid" => {
+                let inner = self.inner(spans);
+                if let Some(tid) = attribute {
+                    let text = if inner.is_empty() {
+                        format!("Topic {}", tid)
+                    } else {
+                        inner
+                    };
+                    self.link(&text, &topic_url(tid));
+                } else {
+                    self.out.push_str(&inner);
+                }
+            }
+            "at" => {
+                let name = attribute.unwrap_or_default();
+                self.link(&format!("@{}", escape(name)), &user_name_url(name));
+            }
+            "collapse" => {
+                let title = escape(attribute.unwrap_or("Collapsed"));
+                let inner = self.inner(spans);
+                self.block(&format!("**{}**\n\n{}", title, inner));
+            }
+            "list" => {
+                let mut renderer = Self {
+                    in_list: true,
+                    ..Default::default()
+                };
+                renderer.spans(spans);
+                self.block(renderer.out.trim());
+            }
+            "table" => self.table(tagged),
+            _ => {
+                let inner = self.inner(spans);
+                self.out.push_str(&inner);
+            }
+        }
+    }
+
+    /// Render a table in GFM syntax, with the first row as the header.
+    fn table(&mut self, table: &Span_Tagged) {
+        let tagged = |spans: &[Span], tag: &str| -> Vec<Span_Tagged> {
+            spans
+                .iter()
+                .filter_map(|s| match &s.value {
+                    Some(Span_oneof_value::tagged(t)) if t.get_tag().starts_with(tag) => {
+                        Some(t.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // Cells may have a width like `[td20]`.
+        let rows = tagged(table.get_spans(), "tr")
+            .iter()
+            .map(|tr| {
+                tagged(tr.get_spans(), "td")
+                    .iter()
+                    .map(|td| self.inner(td.get_spans()).replace("  \n", "<br>"))
+                    .map(|cell| cell.replace('\n', " "))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|cells| !cells.is_empty())
+            .collect::<Vec<_>>();
+
+        let Some(columns) = rows.iter().map(Vec::len).max() else {
+            let inner = self.inner(table.get_spans());
+            self.out.push_str(&inner);
+            return;
+        };
+
+        let line = |cells: &[String]| {
+            let cells = (0..columns)
+                .map(|i| cells.get(i).map(String::as_str).unwrap_or_default())
+                .collect::<Vec<_>>();
+            format!("| {} |", cells.join(" | "))
+        };
+        let mut lines = vec![line(&rows[0]), line(&vec!["---".to_owned(); columns])];
+        lines.extend(rows[1..].iter().map(|r| line(r)));
+
+        self.block(&lines.join("\n"));
+    }
+}
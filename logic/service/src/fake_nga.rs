@@ -0,0 +1,1134 @@
+//! An in-process stand-in for the NGA server, used by integration tests.
+//!
+//! It serves `thread.php`, `read.php`, `nuke.php`, `post.php` and `app_api.php` with the same
+//! compact XML and `__output=8` JSON shapes as the real server, encoded in GB18030. Use
+//! [`FakeNga::scope`] to point `RequestOption.base_url_v2` at it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use itertools::Itertools;
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::{
+    request::{request_option, with_request_option},
+    utils::server_now,
+};
+
+pub const FORUM_ID: &str = "-7";
+pub const FORUM_NAME: &str = "网事杂谈";
+pub const DEFAULT_FOLDER_ID: &str = "1";
+
+const TOPICS_PER_PAGE: usize = 35;
+const POSTS_PER_PAGE: usize = 20;
+
+/// Ids are unique across all servers, so that concurrent tests never share cache entries.
+static NEXT_ID: AtomicU64 = AtomicU64::new(100_000_000);
+
+fn next_id() -> String {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed).to_string()
+}
+
+fn now() -> u64 {
+    server_now().timestamp() as u64
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeUser {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakePost {
+    pub pid: String,
+    pub author: FakeUser,
+    pub content: String,
+    pub score: i32,
+    pub post_date: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeTopic {
+    pub tid: String,
+    pub subject: String,
+    pub posts: Vec<FakePost>,
+}
+
+impl FakeTopic {
+    fn author(&self) -> &FakeUser {
+        &self.posts[0].author
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeFolder {
+    pub name: String,
+    pub topic_ids: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeMessage {
+    pub mid: String,
+    pub subject: String,
+    pub users: Vec<FakeUser>,
+    /// Author and content of each post.
+    pub posts: Vec<(FakeUser, String, u64)>,
+}
+
+/// The mutable state of the server.
+///
+/// The first user is the one logged in, unless `access_uid` says otherwise.
+#[derive(Debug)]
+pub struct State {
+    pub users: Vec<FakeUser>,
+    pub topics: Vec<FakeTopic>,
+    /// Vote value of the current user, keyed by `(tid, pid)`.
+    pub votes: BTreeMap<(String, String), i32>,
+    pub folders: BTreeMap<String, FakeFolder>,
+    pub messages: Vec<FakeMessage>,
+    /// Notifications in the raw `noti/get_all` shape.
+    pub notis: Vec<Value>,
+    /// Path and query of all received requests.
+    pub requests: Vec<String>,
+}
+
+impl State {
+    fn seeded() -> Self {
+        let me = FakeUser {
+            id: next_id(),
+            name: "MNGA".to_owned(),
+        };
+        let other = FakeUser {
+            id: next_id(),
+            name: "Reviewer".to_owned(),
+        };
+
+        let topic = FakeTopic {
+            tid: next_id(),
+            subject: "Hello from the stand-in".to_owned(),
+            posts: vec![
+                FakePost {
+                    pid: "0".to_owned(),
+                    author: me.clone(),
+                    content: "[b]First![/b]".to_owned(),
+                    score: 0,
+                    post_date: now(),
+                },
+                FakePost {
+                    pid: next_id(),
+                    author: other.clone(),
+                    content: "A reply.".to_owned(),
+                    score: 3,
+                    post_date: now(),
+                },
+            ],
+        };
+
+        let noti = json!({
+            "0": 1, // REPLY_TOPIC
+            "1": other.id,
+            "2": other.name,
+            "5": topic.subject,
+            "6": topic.tid,
+            "7": topic.posts[1].pid,
+            "8": "0",
+            "9": now(),
+            "10": 1,
+        });
+
+        let folders = BTreeMap::from([(
+            DEFAULT_FOLDER_ID.to_owned(),
+            FakeFolder {
+                name: "默认".to_owned(),
+                topic_ids: BTreeSet::new(),
+            },
+        )]);
+
+        Self {
+            users: vec![me, other],
+            topics: vec![topic],
+            votes: BTreeMap::new(),
+            folders,
+            messages: vec![],
+            notis: vec![noti],
+            requests: vec![],
+        }
+    }
+
+    fn current_user(&self, params: &Params) -> FakeUser {
+        let uid = params.get("access_uid");
+        self.users
+            .iter()
+            .find(|u| u.id == uid)
+            .unwrap_or(&self.users[0])
+            .clone()
+    }
+
+    fn topic_mut(&mut self, tid: &str) -> Option<&mut FakeTopic> {
+        self.topics.iter_mut().find(|t| t.tid == tid)
+    }
+}
+
+/// Query and form pairs of a request, merged as NGA does.
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn get(&self, key: &str) -> &str {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map_or("", |(_, v)| v.as_str())
+    }
+
+    fn get_all(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn page(&self) -> usize {
+        self.get("page").parse().unwrap_or(1).max(1)
+    }
+}
+
+enum Reply {
+    /// Children of `<root>`.
+    Xml(u16, String),
+    Json(u16, Value),
+}
+
+impl Reply {
+    fn xml(body: impl Into<String>) -> Self {
+        Reply::Xml(200, body.into())
+    }
+
+    fn xml_message(status: u16, code: u32, info: &str) -> Self {
+        Reply::Xml(
+            status,
+            format!(
+                "<__MESSAGE><item>{}</item><item>{}</item></__MESSAGE>",
+                code,
+                escape(info)
+            ),
+        )
+    }
+
+    fn json(data: Value) -> Self {
+        Reply::Json(200, json!({ "data": data, "time": now() }))
+    }
+
+    fn json_error(info: &str) -> Self {
+        Reply::Json(200, json!({ "error": { "0": info }, "time": now() }))
+    }
+
+    fn into_parts(self) -> (u16, &'static str, String) {
+        match self {
+            Reply::Xml(status, body) => (
+                status,
+                "text/xml",
+                format!(
+                    r#"<?xml version="1.0" encoding="GBK"?><root>{}</root>"#,
+                    body
+                ),
+            ),
+            Reply::Json(status, value) => (status, "application/json", value.to_string()),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn tag(name: &str, value: impl Display) -> String {
+    format!("<{0}>{1}</{0}>", name, escape(&value.to_string()))
+}
+
+fn topic_xml(topic: &FakeTopic) -> String {
+    let last = topic.posts.last().unwrap();
+    [
+        tag("tid", &topic.tid),
+        tag("fid", FORUM_ID),
+        tag("subject", &topic.subject),
+        tag("author", &topic.author().name),
+        tag("authorid", &topic.author().id),
+        tag("postdate", topic.posts[0].post_date),
+        tag("lastpost", last.post_date),
+        tag("replies", topic.posts.len() - 1),
+        tag("type", 0),
+    ]
+    .concat()
+}
+
+fn post_xml(topic: &FakeTopic, floor: usize, post: &FakePost) -> String {
+    let fields = [
+        tag("pid", &post.pid),
+        tag("tid", &topic.tid),
+        tag("fid", FORUM_ID),
+        tag("lou", floor),
+        tag("authorid", &post.author.id),
+        tag("content", &post.content),
+        tag("postdatetimestamp", post.post_date),
+        tag("score", post.score),
+    ]
+    .concat();
+    format!("<item>{}</item>", fields)
+}
+
+fn forum_xml() -> String {
+    format!(
+        "<__F>{}{}</__F>",
+        tag("fid", FORUM_ID),
+        tag("name", FORUM_NAME)
+    )
+}
+
+fn topic_list_xml<'a>(topics: impl Iterator<Item = &'a FakeTopic>, page: usize) -> String {
+    let topics = topics
+        .sorted_by_key(|t| std::cmp::Reverse(t.posts.last().unwrap().post_date))
+        .collect_vec();
+    let items = topics
+        .iter()
+        .skip((page - 1) * TOPICS_PER_PAGE)
+        .take(TOPICS_PER_PAGE)
+        .map(|t| format!("<item>{}</item>", topic_xml(t)))
+        .join("");
+
+    format!(
+        "<__T>{}</__T>{}{}{}",
+        items,
+        tag("__ROWS", topics.len()),
+        tag("__T__ROWS_PAGE", TOPICS_PER_PAGE),
+        forum_xml()
+    )
+}
+
+fn thread(state: &State, params: &Params) -> Reply {
+    let page = params.page();
+
+    if !params.get("favor").is_empty() {
+        let Some(folder) = state.folders.get(params.get("favor")) else {
+            return Reply::xml_message(200, 0, "收藏夹不存在");
+        };
+        let topics = state
+            .topics
+            .iter()
+            .filter(|t| folder.topic_ids.contains(&t.tid));
+        return Reply::xml(topic_list_xml(topics, page));
+    }
+
+    if params.get("fid") != FORUM_ID {
+        return Reply::xml_message(200, 0, "版面不存在");
+    }
+    let key = params.get("key");
+    let topics = state.topics.iter().filter(|t| t.subject.contains(key));
+    Reply::xml(topic_list_xml(topics, page))
+}
+
+fn read(state: &State, params: &Params) -> Reply {
+    let Some(topic) = state.topics.iter().find(|t| t.tid == params.get("tid")) else {
+        // NGA responds with a non-OK status along with the detailed message.
+        return Reply::xml_message(404, 2, "帖子不存在或已被删除");
+    };
+
+    let page = params.page();
+    let posts = topic
+        .posts
+        .iter()
+        .enumerate()
+        .skip((page - 1) * POSTS_PER_PAGE)
+        .take(POSTS_PER_PAGE)
+        .collect_vec();
+
+    let users = posts
+        .iter()
+        .map(|(_, p)| &p.author)
+        .unique_by(|u| &u.id)
+        .map(|u| {
+            format!(
+                "<item>{}{}</item>",
+                tag("uid", &u.id),
+                tag("username", &u.name)
+            )
+        })
+        .join("");
+    let replies = posts
+        .iter()
+        .map(|(floor, p)| post_xml(topic, *floor, p))
+        .join("");
+
+    Reply::xml(format!(
+        "<__U>{}</__U><__R>{}</__R><__T>{}</__T>{}{}{}",
+        users,
+        replies,
+        topic_xml(topic),
+        forum_xml(),
+        tag("__ROWS", topic.posts.len()),
+        tag("__R__ROWS_PAGE", POSTS_PER_PAGE),
+    ))
+}
+
+fn post(state: &mut State, params: &Params) -> Reply {
+    let me = state.current_user(params);
+    let action = params.get("action");
+    let content = params.get("post_content");
+
+    if params.get("step") != "2" {
+        // Fetch the content for editing.
+        let Some(topic) = state.topics.iter().find(|t| t.tid == params.get("tid")) else {
+            return Reply::xml_message(200, 0, "帖子不存在或已被删除");
+        };
+        let post = topic.posts.iter().find(|p| p.pid == params.get("pid"));
+        let content = match (action, post) {
+            ("quote", Some(p)) => format!("[quote]{}[/quote]", p.content),
+            ("modify", Some(p)) => p.content.clone(),
+            _ => String::new(),
+        };
+        let subject = if action == "modify" && params.get("pid") == "0" {
+            topic.subject.as_str()
+        } else {
+            ""
+        };
+        return Reply::xml(
+            [
+                tag("content", content),
+                tag("subject", subject),
+                tag("auth", "fake-auth"),
+            ]
+            .concat(),
+        );
+    }
+
+    if content.chars().count() < 2 {
+        return Reply::xml_message(200, 0, "内容过短");
+    }
+
+    let new_post = |pid: String| FakePost {
+        pid,
+        author: me.clone(),
+        content: content.to_owned(),
+        score: 0,
+        post_date: now(),
+    };
+
+    match action {
+        "new" => {
+            if params.get("fid") != FORUM_ID {
+                return Reply::xml_message(200, 0, "版面不存在");
+            }
+            let subject = params.get("post_subject");
+            if subject.is_empty() {
+                return Reply::xml_message(200, 0, "标题过短");
+            }
+            state.topics.push(FakeTopic {
+                tid: next_id(),
+                subject: subject.to_owned(),
+                posts: vec![new_post("0".to_owned())],
+            });
+        }
+        "reply" | "quote" => {
+            let Some(topic) = state.topic_mut(params.get("tid")) else {
+                return Reply::xml_message(200, 0, "帖子不存在或已被删除");
+            };
+            topic.posts.push(new_post(next_id()));
+        }
+        "modify" => {
+            let Some(post) = state
+                .topic_mut(params.get("tid"))
+                .and_then(|t| t.posts.iter_mut().find(|p| p.pid == params.get("pid")))
+            else {
+                return Reply::xml_message(200, 0, "帖子不存在或已被删除");
+            };
+            if post.author.id != me.id {
+                return Reply::xml_message(200, 0, "你不能编辑这个帖子");
+            }
+            post.content = content.to_owned();
+        }
+        _ => return Reply::xml_message(400, 0, "未知操作"),
+    }
+
+    Reply::xml_message(200, 1, "发贴完毕 ...")
+}
+
+fn nuke(state: &mut State, params: &Params) -> Reply {
+    match (params.get("__lib"), params.get("__act")) {
+        ("topic_recommend", "add") => vote(state, params),
+        ("topic_favor_v2", act) => favor(state, act, params),
+        ("message", "message") => message(state, params),
+        ("noti", "get_all") => Reply::json(json!({ "0": { "0": state.notis } })),
+        _ => Reply::json_error("未知操作"),
+    }
+}
+
+fn vote(state: &mut State, params: &Params) -> Reply {
+    let (tid, pid) = (params.get("tid"), params.get("pid"));
+    let value: i32 = params.get("value").parse().unwrap_or_default();
+
+    let key = (tid.to_owned(), pid.to_owned());
+    let current = state.votes.get(&key).copied().unwrap_or_default();
+    let Some(post) = state
+        .topic_mut(tid)
+        .and_then(|t| t.posts.iter_mut().find(|p| p.pid == pid))
+    else {
+        return Reply::json_error("帖子不存在");
+    };
+
+    // Voting the same way again cancels the vote.
+    let new = if current == value { 0 } else { value };
+    let delta = new - current;
+    post.score += delta;
+    state.votes.insert(key, new);
+
+    Reply::json(json!({ "0": "操作成功", "1": delta }))
+}
+
+fn favor(state: &mut State, act: &str, params: &Params) -> Reply {
+    let folder_id = match params.get("folder") {
+        "" => DEFAULT_FOLDER_ID,
+        id => id,
+    }
+    .to_owned();
+
+    match act {
+        "list_folder" => {
+            let folders = state
+                .folders
+                .iter()
+                .enumerate()
+                .map(|(i, (id, folder))| {
+                    let mut value = json!({
+                        "id": id,
+                        "name": folder.name,
+                        "length": folder.topic_ids.len(),
+                    });
+                    if id == DEFAULT_FOLDER_ID {
+                        value["default"] = json!(1);
+                    }
+                    (i.to_string(), value)
+                })
+                .collect::<serde_json::Map<_, _>>();
+            Reply::json(json!({ "0": folders }))
+        }
+        "new_folder" => {
+            let id = next_id();
+            state.folders.insert(
+                id.clone(),
+                FakeFolder {
+                    name: params.get("name").to_owned(),
+                    topic_ids: BTreeSet::new(),
+                },
+            );
+            Reply::json(json!({ "0": "操作成功", "1": id }))
+        }
+        "add" => {
+            let tid = params.get("tid");
+            if state.topics.iter().all(|t| t.tid != tid) {
+                return Reply::json_error("帖子不存在");
+            }
+            let Some(folder) = state.folders.get_mut(&folder_id) else {
+                return Reply::json_error("收藏夹不存在");
+            };
+            folder.topic_ids.insert(tid.to_owned());
+            Reply::json(json!({ "0": "操作成功" }))
+        }
+        "del" => {
+            let Some(folder) = state.folders.get_mut(&folder_id) else {
+                return Reply::json_error("收藏夹不存在");
+            };
+            for tid in params.get("tidarray").split(',') {
+                folder.topic_ids.remove(tid);
+            }
+            Reply::json(json!({ "0": "操作成功" }))
+        }
+        _ => Reply::json_error("未知操作"),
+    }
+}
+
+fn message(state: &mut State, params: &Params) -> Reply {
+    let me = state.current_user(params);
+    let content = params.get("content");
+
+    let message_json = |m: &FakeMessage| {
+        let (from, _, time) = &m.posts[0];
+        let all_users = m
+            .users
+            .iter()
+            .map(|u| format!("{}\t{}", u.id, u.name))
+            .join("\t");
+        json!({
+            "mid": m.mid,
+            "subject": m.subject,
+            "from": from.id,
+            "from_username": from.name,
+            "time": time,
+            "last_modify": m.posts.last().unwrap().2,
+            "posts": m.posts.len(),
+            "all_user": all_users,
+        })
+    };
+
+    match params.get("act") {
+        "list" => {
+            let messages = state
+                .messages
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(i, m)| (i.to_string(), message_json(m)))
+                .collect::<serde_json::Map<_, _>>();
+            Reply::json(json!({ "0": messages }))
+        }
+        "read" => {
+            let Some(m) = state.messages.iter().find(|m| m.mid == params.get("mid")) else {
+                return Reply::json_error("短消息不存在");
+            };
+            let posts = m
+                .posts
+                .iter()
+                .enumerate()
+                .map(|(i, (from, content, time))| {
+                    let post = json!({
+                        "id": format!("{}-{}", m.mid, i),
+                        "from": from.id,
+                        "subject": if i == 0 { m.subject.as_str() } else { "" },
+                        "content": content,
+                        "time": time,
+                    });
+                    (i.to_string(), post)
+                })
+                .collect::<serde_json::Map<_, _>>();
+            let users = m
+                .users
+                .iter()
+                .map(|u| (u.id.clone(), json!({ "uid": u.id, "username": u.name })))
+                .collect::<serde_json::Map<_, _>>();
+            Reply::json(json!({
+                "0": {
+                    "allmsgs": posts,
+                    "userInfo": users,
+                    "allUsers": message_json(m)["all_user"],
+                }
+            }))
+        }
+        "new" => {
+            let names = params.get_all("to").flat_map(str::split_whitespace);
+            let mut users = vec![me.clone()];
+            for name in names {
+                let Some(user) = state.users.iter().find(|u| u.name == name || u.id == name) else {
+                    return Reply::json_error("找不到用户");
+                };
+                users.push(user.clone());
+            }
+            if users.len() == 1 {
+                return Reply::json_error("没有收件人");
+            }
+            let mid = next_id();
+            state.messages.push(FakeMessage {
+                mid: mid.clone(),
+                subject: params.get("subject").to_owned(),
+                users,
+                posts: vec![(me, content.to_owned(), now())],
+            });
+            Reply::json(json!({ "0": "操作成功", "1": mid }))
+        }
+        "reply" => {
+            let Some(m) = state
+                .messages
+                .iter_mut()
+                .find(|m| m.mid == params.get("mid"))
+            else {
+                return Reply::json_error("短消息不存在");
+            };
+            m.posts.push((me, content.to_owned(), now()));
+            Reply::json(json!({ "0": "操作成功" }))
+        }
+        _ => Reply::json_error("未知操作"),
+    }
+}
+
+fn app_api(params: &Params) -> Reply {
+    if (params.get("__lib"), params.get("__act")) != ("home", "category") {
+        return Reply::json_error("未知操作");
+    }
+    Reply::json(json!({
+        "0": {
+            "_id": "1",
+            "name": "综合讨论",
+            "groups": { "0": { "forums": { "0": { "fid": FORUM_ID, "name": FORUM_NAME } } } },
+        }
+    }))
+}
+
+fn route(state: &mut State, path: &str, params: &Params) -> Reply {
+    match path {
+        "/thread.php" => thread(state, params),
+        "/read.php" => read(state, params),
+        "/post.php" => post(state, params),
+        "/nuke.php" => nuke(state, params),
+        "/app_api.php" => app_api(params),
+        _ => Reply::xml_message(404, 404, "Not Found"),
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = value.trim().parse().unwrap_or_default();
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let url = Url::parse(&format!("http://localhost{}", target))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let params = Params(
+        url.query_pairs()
+            .chain(url::form_urlencoded::parse(&body))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect(),
+    );
+
+    let reply = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(target.to_owned());
+        route(&mut state, url.path(), &params)
+    };
+    let (status, content_type, body) = reply.into_parts();
+    let (body, _, _) = encoding_rs::GB18030.encode(&body);
+
+    write!(
+        stream,
+        "HTTP/1.1 {} Fake\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// A running stand-in server. It's shut down on drop.
+pub struct FakeNga {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl FakeNga {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind fake NGA server");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::seeded()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        {
+            let state = state.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &state) {
+                            log::warn!("fake NGA server: {}", e);
+                        }
+                    });
+                }
+            });
+        }
+
+        Self {
+            addr,
+            state,
+            shutdown,
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Run `f` with all requests sent to this server.
+    pub async fn scope<F: futures::Future>(&self, f: F) -> F::Output {
+        let mut option = request_option();
+        option.set_base_url_v2(self.base_url());
+        with_request_option(option, f).await
+    }
+}
+
+impl Drop for FakeNga {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the listener thread.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        error::{ServiceError, ServiceResult},
+        forum::{get_forum_list, make_fid},
+        msg::{get_short_msg_details, get_short_msg_list, post_short_msg},
+        noti::fetch_notis,
+        post::{post_reply, post_vote},
+        topic::{
+            get_favorite_folder_list, get_favorite_topic_list, get_topic_details, get_topic_list,
+            topic_favor,
+        },
+    };
+    use protos::{DataModel::*, Service::*};
+
+    fn seeded_topic(server: &FakeNga) -> FakeTopic {
+        server.state().topics[0].clone()
+    }
+
+    fn assert_nga_error<T: std::fmt::Debug>(result: ServiceResult<T>, info: &str) {
+        match result {
+            Err(ServiceError::Nga(e)) => assert_eq!(e.get_info(), info),
+            Err(ServiceError::LocalCacheMissed(e)) => assert_nga_error::<T>(Err(*e), info),
+            r => panic!("expected NGA error `{}`, got {:?}", info, r),
+        }
+    }
+
+    async fn topic_details(tid: &str) -> ServiceResult<TopicDetailsResponse> {
+        get_topic_details(TopicDetailsRequest {
+            topic_id: tid.to_owned(),
+            page: 1,
+            ..Default::default()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_topic_list_and_details() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let topic = seeded_topic(&server);
+
+        server
+            .scope(async {
+                let list = get_topic_list(TopicListRequest {
+                    id: make_fid(FORUM_ID.to_owned()).into(),
+                    page: 1,
+                    ..Default::default()
+                })
+                .await?;
+                assert_eq!(list.get_pages(), 1);
+                assert_eq!(list.get_forum().get_name(), FORUM_NAME);
+                assert_eq!(list.get_topics()[0].get_id(), topic.tid);
+                assert_eq!(list.get_topics()[0].get_replies_num(), 1);
+
+                let details = topic_details(&topic.tid).await?;
+                assert_eq!(
+                    details.get_topic().get_subject().get_content(),
+                    topic.subject
+                );
+                assert_eq!(details.get_replies().len(), 2);
+                assert_eq!(details.get_replies()[1].get_score(), 3);
+                assert_eq!(details.get_forum_name(), FORUM_NAME);
+
+                let forums = get_forum_list(Default::default()).await?;
+                assert!(
+                    forums
+                        .get_categories()
+                        .iter()
+                        .flat_map(|c| c.get_forums())
+                        .any(|f| f.get_name() == FORUM_NAME)
+                );
+
+                // Notifications are cached globally, so find ours by the topic id.
+                let notis = fetch_notis(Default::default()).await?;
+                let noti = notis
+                    .get_notis()
+                    .iter()
+                    .find(|n| n.get_post_id().get_tid() == topic.tid)
+                    .unwrap();
+                assert_eq!(noti.get_field_type(), Notification_Type::REPLY_TOPIC);
+
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_post_reply_and_new_topic() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let topic = seeded_topic(&server);
+
+        server
+            .scope(async {
+                post_reply(PostReplyRequest {
+                    action: Some(PostReplyAction {
+                        operation: PostReplyAction_Operation::REPLY,
+                        post_id: Some(PostId {
+                            tid: topic.tid.clone(),
+                            pid: "0".to_owned(),
+                            ..Default::default()
+                        })
+                        .into(),
+                        ..Default::default()
+                    })
+                    .into(),
+                    content: "Reply from MNGA".to_owned(),
+                    ..Default::default()
+                })
+                .await?;
+
+                let details = topic_details(&topic.tid).await?;
+                assert_eq!(details.get_replies().len(), 3);
+                assert_eq!(
+                    details.get_replies()[2].get_content().get_raw(),
+                    "Reply from MNGA"
+                );
+
+                let mut request = PostReplyRequest {
+                    action: Some(PostReplyAction {
+                        operation: PostReplyAction_Operation::NEW,
+                        forum_id: make_fid(FORUM_ID.to_owned()).into(),
+                        ..Default::default()
+                    })
+                    .into(),
+                    content: "Content of the new topic".to_owned(),
+                    ..Default::default()
+                };
+                request.set_subject("New topic".to_owned());
+                post_reply(request.clone()).await?;
+
+                request.set_content("".to_owned());
+                assert_nga_error(post_reply(request).await, "内容过短");
+
+                Ok::<_, ServiceError>(())
+            })
+            .await?;
+
+        let state = server.state();
+        assert_eq!(state.topics.len(), 2);
+        assert_eq!(state.topics[1].subject, "New topic");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_vote() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let topic = seeded_topic(&server);
+        let post_id = PostId {
+            tid: topic.tid.clone(),
+            pid: topic.posts[1].pid.clone(),
+            ..Default::default()
+        };
+        let vote = |operation| {
+            post_vote(PostVoteRequest {
+                post_id: Some(post_id.clone()).into(),
+                operation,
+                ..Default::default()
+            })
+        };
+
+        server
+            .scope(async {
+                let response = vote(PostVoteRequest_Operation::UPVOTE).await?;
+                assert_eq!((response.delta, response.state), (1, VoteState::UP));
+
+                let response = vote(PostVoteRequest_Operation::DOWNVOTE).await?;
+                assert_eq!((response.delta, response.state), (-2, VoteState::DOWN));
+
+                let response = vote(PostVoteRequest_Operation::DOWNVOTE).await?;
+                assert_eq!((response.delta, response.state), (1, VoteState::NONE));
+
+                // The vote state is cached and shows up in the topic details.
+                vote(PostVoteRequest_Operation::UPVOTE).await?;
+                let details = topic_details(&topic.tid).await?;
+                assert_eq!(details.get_replies()[1].get_vote_state(), VoteState::UP);
+                assert_eq!(details.get_replies()[1].get_score(), 4);
+
+                let mut missing = post_id.clone();
+                missing.set_pid("0xdead".to_owned());
+                let result = post_vote(PostVoteRequest {
+                    post_id: Some(missing).into(),
+                    ..Default::default()
+                })
+                .await;
+                assert_nga_error(result, "帖子不存在");
+
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_favorites() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let topic = seeded_topic(&server);
+        let favor = |topic_id: &str, operation| {
+            topic_favor(TopicFavorRequest {
+                topic_id: topic_id.to_owned(),
+                operation,
+                folder_id: DEFAULT_FOLDER_ID.to_owned(),
+                ..Default::default()
+            })
+        };
+        let favorite_topics = || {
+            get_favorite_topic_list(FavoriteTopicListRequest {
+                page: 1,
+                folder_id: DEFAULT_FOLDER_ID.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        server
+            .scope(async {
+                let response = favor(&topic.tid, TopicFavorRequest_Operation::ADD).await?;
+                assert!(response.get_is_favored());
+                assert_eq!(response.get_folder_ids(), [DEFAULT_FOLDER_ID]);
+
+                let topics = favorite_topics().await?;
+                assert_eq!(topics.get_topics().len(), 1);
+                assert!(topics.get_topics()[0].get_is_favored());
+
+                let folders = get_favorite_folder_list(Default::default()).await?;
+                assert_eq!(folders.get_folders().len(), 1);
+                assert_eq!(folders.get_folders()[0].get_topic_count(), 1);
+                assert!(folders.get_folders()[0].get_is_default());
+
+                let response = favor(&topic.tid, TopicFavorRequest_Operation::DELETE).await?;
+                assert!(!response.get_is_favored());
+                assert!(favorite_topics().await?.get_topics().is_empty());
+
+                let result = favor("0", TopicFavorRequest_Operation::ADD).await;
+                assert_nga_error(result, "帖子不存在");
+
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_short_messages() -> ServiceResult<()> {
+        let server = FakeNga::start();
+
+        server
+            .scope(async {
+                post_short_msg(ShortMessagePostRequest {
+                    action: Some(ShortMessagePostAction {
+                        operation: ShortMessagePostAction_Operation::NEW,
+                        ..Default::default()
+                    })
+                    .into(),
+                    to: vec!["Reviewer".to_owned()].into(),
+                    subject: "Hi".to_owned(),
+                    content: "How are you?".to_owned(),
+                    ..Default::default()
+                })
+                .await?;
+
+                let list = get_short_msg_list(ShortMessageListRequest {
+                    page: 1,
+                    ..Default::default()
+                })
+                .await?;
+                let message = &list.get_messages()[0];
+                assert_eq!(message.get_subject(), "Hi");
+                assert_eq!(message.get_ids().len(), 2);
+
+                post_short_msg(ShortMessagePostRequest {
+                    action: Some(ShortMessagePostAction {
+                        operation: ShortMessagePostAction_Operation::REPLY,
+                        mid: message.get_id().to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
+                    content: "Fine, thanks.".to_owned(),
+                    ..Default::default()
+                })
+                .await?;
+
+                let details = get_short_msg_details(ShortMessageDetailsRequest {
+                    id: message.get_id().to_owned(),
+                    page: 1,
+                    ..Default::default()
+                })
+                .await?;
+                assert_eq!(details.get_posts().len(), 2);
+                assert_eq!(
+                    details.get_posts()[1].get_content().get_raw(),
+                    "Fine, thanks."
+                );
+                assert_eq!(details.get_users().len(), 2);
+
+                let result = post_short_msg(ShortMessagePostRequest {
+                    action: Some(ShortMessagePostAction {
+                        operation: ShortMessagePostAction_Operation::NEW,
+                        ..Default::default()
+                    })
+                    .into(),
+                    to: vec!["Nobody".to_owned()].into(),
+                    content: "Hello?".to_owned(),
+                    ..Default::default()
+                })
+                .await;
+                assert_nga_error(result, "找不到用户");
+
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_error_pages() -> ServiceResult<()> {
+        let server = FakeNga::start();
+
+        server
+            .scope(async {
+                // Returned with a non-OK status.
+                assert_nga_error(topic_details("0").await, "帖子不存在或已被删除");
+
+                let result = get_topic_list(TopicListRequest {
+                    id: make_fid("233".to_owned()).into(),
+                    page: 1,
+                    ..Default::default()
+                })
+                .await;
+                assert_nga_error(result, "版面不存在");
+
+                Ok(())
+            })
+            .await
+    }
+}
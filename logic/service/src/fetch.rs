@@ -2,6 +2,7 @@
 
 use std::{
     borrow::Cow,
+    hash::{DefaultHasher, Hash as _, Hasher as _},
     sync::{LazyLock, Mutex},
     time::Duration,
 };
@@ -13,17 +14,20 @@ use crate::{
         WINDOWS_PHONE_UA,
     },
     error::{ServiceError, ServiceResult},
-    request,
+    fixture::{Fixture, FixtureRequest},
+    request, throttle,
     utils::{extract_error, sanitize_json_control_chars_in_strings},
 };
 use dashmap::DashMap;
+use futures::Future;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use protos::DataModel::Device;
-use reqwest::{Client, Method, RequestBuilder, Response, Url, multipart};
+use rand::Rng;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url, multipart};
 
 fn device_ua(api: &str) -> Cow<'static, str> {
-    let option = request::REQUEST_OPTION.read().unwrap();
+    let option = request::request_option();
 
     // If not customized, always use windows phone for read.php since it seems to be more robust.
     if api == "read.php" && option.get_device() != Device::CUSTOM {
@@ -36,7 +40,7 @@ fn device_ua(api: &str) -> Cow<'static, str> {
         Device::ANDROID => ANDROID_UA,
         Device::WINDOWS_PHONE => WINDOWS_PHONE_UA,
         // Use `custom_ua` if `device` is `CUSTOM`
-        Device::CUSTOM => return option.get_custom_ua().to_owned().into(),
+        Device::CUSTOM => return option.custom_ua.into(),
     }
     .into()
 }
@@ -44,7 +48,7 @@ fn device_ua(api: &str) -> Cow<'static, str> {
 fn resolve_url(api: &str, kind: FetchKind) -> ServiceResult<Url> {
     let url = Url::parse(api) // if absolute
         .or_else(|_| -> ServiceResult<Url> {
-            let option = request::REQUEST_OPTION.read().unwrap();
+            let option = request::request_option();
             let base = match kind {
                 FetchKind::Normal => option.get_base_url_v2(),
                 FetchKind::Mock => DEFAULT_MOCK_BASE_URL,
@@ -60,10 +64,12 @@ fn resolve_url(api: &str, kind: FetchKind) -> ServiceResult<Url> {
 
 fn build_client() -> Client {
     log::info!("build reqwest client");
+    let tuning = config::tuning();
     Client::builder()
-        .https_only(true)
-        .connect_timeout(Duration::from_secs(5))
-        .read_timeout(Duration::from_secs(20))
+        // The stand-in server for tests speaks plain HTTP.
+        .https_only(!cfg!(test))
+        .connect_timeout(tuning.connect_timeout)
+        .read_timeout(tuning.read_timeout)
         .gzip(true)
         .build()
         .expect("failed to build reqwest client")
@@ -128,20 +134,128 @@ enum ProxyMode {
     Only,
 }
 
+/// APIs that may modify something, whose requests are not idempotent.
+const WRITE_APIS: &[&str] = &["post.php", "nuke.php"];
+
+/// Retry policy of transient errors, with jittered exponential backoff.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether to also retry the errors after which the request may have been processed, like
+    /// timeouts. Only set this for idempotent requests.
+    pub after_sent: bool,
+}
+
+impl Backoff {
+    /// Only retry the errors that the request is surely not processed, like connection failures
+    /// or being rate limited.
+    pub fn unsent_only() -> Self {
+        Self {
+            after_sent: false,
+            ..Self::idempotent()
+        }
+    }
+
+    pub fn idempotent() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            after_sent: true,
+        }
+    }
+
+    fn for_api(api: &str) -> Self {
+        if WRITE_APIS.contains(&api) {
+            Self::unsent_only()
+        } else {
+            Self::idempotent()
+        }
+    }
+
+    fn is_transient_status(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+        )
+    }
+
+    fn is_transient(&self, error: &ServiceError) -> bool {
+        use protos::Service::ServiceErrorResponse_Kind as Kind;
+
+        match error {
+            ServiceError::Reqwest(e) if e.is_connect() => true,
+            ServiceError::Reqwest(e) => {
+                self.after_sent && (e.is_timeout() || e.is_request() || e.is_body())
+            }
+            ServiceError::Status(_) | ServiceError::Nga(_) => {
+                let response = error.to_response();
+                match (response.get_kind(), response.get_http_status()) {
+                    (Kind::RATE_LIMITED, _) | (_, 503) => true,
+                    (_, 502) => self.after_sent,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Delay before the retry after `retries` ones, which is in `[exp / 2, exp]` where `exp` is
+    /// the exponential delay.
+    fn delay(&self, retries: u32) -> Duration {
+        let exp = (self.base_delay)
+            .saturating_mul(1 << retries.min(16))
+            .min(self.max_delay);
+        exp.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+
+    async fn retry<T, F, Fut>(&self, f: F) -> ServiceResult<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ServiceResult<T>>,
+    {
+        let mut retries = 0;
+        loop {
+            // Do not hold the result across the sleep, which may not be `Send`.
+            let error = match f().await {
+                Err(e) if retries < self.max_retries && self.is_transient(&e) => e,
+                result => return result,
+            };
+            let delay = self.delay(retries);
+            retries += 1;
+            log::warn!(
+                "transient error, retry #{} after {:?}: {}",
+                retries,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
 /// Determine the retry behavior of the request.
 #[derive(Clone, Debug)]
 pub struct RetryMode {
     key: Option<String>,
     use_alternative_query_pairs: bool,
     use_proxy: ProxyMode,
+    backoff: Backoff,
 }
 
 impl RetryMode {
+    /// Never try alternative query pairs or the proxy. Transient errors are still retried if the
+    /// request is surely not processed.
     pub fn never() -> Self {
         Self {
             key: None,
             use_alternative_query_pairs: false,
             use_proxy: ProxyMode::Never,
+            backoff: Backoff::unsent_only(),
         }
     }
 
@@ -150,6 +264,7 @@ impl RetryMode {
             key: Some(key.into()),
             use_alternative_query_pairs: true,
             use_proxy: ProxyMode::Never,
+            backoff: Backoff::idempotent(),
         }
     }
 
@@ -158,6 +273,7 @@ impl RetryMode {
             key: Some(key.into()),
             use_alternative_query_pairs: true,
             use_proxy: ProxyMode::Use,
+            backoff: Backoff::idempotent(),
         }
     }
 
@@ -166,9 +282,14 @@ impl RetryMode {
             key: Some(key.into()),
             use_alternative_query_pairs: true,
             use_proxy: ProxyMode::Only,
+            backoff: Backoff::idempotent(),
         }
     }
 
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self { backoff, ..self }
+    }
+
     fn fetch_kinds(&self) -> &'static [FetchKind] {
         match self.use_proxy {
             ProxyMode::Never => &[FetchKind::Normal][..],
@@ -233,7 +354,20 @@ where
     println!("{} request to url: {}", method, request.url());
     log::info!("{} request to url: {}", method, request.url());
 
-    let response = client.execute(request).await?;
+    let fixture = Fixture::current();
+    if !fixture.as_ref().is_some_and(|f| f.is_replay()) {
+        throttle::acquire(request.url().host_str().unwrap_or_default()).await;
+    }
+
+    let response = match fixture {
+        Some(fixture) if fixture.is_replay() => fixture.replay(&request)?,
+        Some(fixture) => {
+            let fixture_request = FixtureRequest::from_request(&request);
+            let response = client.execute(request).await?;
+            fixture.record(&fixture_request, response).await?
+        }
+        None => client.execute(request).await?,
+    };
 
     if response.status().is_success() {
         Ok(response)
@@ -266,11 +400,14 @@ pub fn was_proxied(key: &str) -> bool {
     }
 }
 
+/// Identical requests in flight with the same `coalesce_key` share the response, see
+/// `throttle::coalesce`.
 async fn do_fetch_text<RF, AF>(
     api: &str,
     query: Vec<(&str, &str)>,
     add_form: AF,
     retry: RetryMode,
+    coalesce_key: Option<String>,
 ) -> ServiceResult<RF>
 where
     RF: ResponseFormat,
@@ -296,24 +433,42 @@ where
         let mut query = query.clone();
         query.push(query_pair);
 
-        let error = {
-            let result = async {
-                let response = do_fetch(api, kind, query, Method::POST, false, &add_form).await?;
+        let key = (coalesce_key.as_ref())
+            .map(|k| format!("{}|{:?}|{}={}", k, kind, query_pair.0, query_pair.1));
+        let add_form = &add_form;
+        let fetch = move || {
+            let query = query.clone();
+            async move {
+                let response = do_fetch(api, kind, query, Method::POST, false, add_form).await?;
                 let status = response.status();
                 let response = response.text_with_charset("gb18030").await?;
+                Ok((status, response))
+            }
+        };
+
+        let attempt = || {
+            let key = key.clone();
+            let fetch = &fetch;
+            async move {
+                let (status, response) = throttle::coalesce(key, fetch).await?;
 
                 #[cfg(test)]
                 let _ = RESPONSE_CB.try_with(|c| c.borrow_mut()(&response));
                 #[cfg(test)]
                 println!("http response (kind={kind:?}, query_pair={query_pair:?}):\n{response}");
 
-                if response.is_empty() && !status.is_success() {
+                if (response.is_empty() && !status.is_success())
+                    || Backoff::is_transient_status(status)
+                {
                     // Parse must fail. Here we use the error message from the status code.
                     return Err(ServiceError::from_status(status));
                 }
                 RF::parse_response(response)
             }
-            .await;
+        };
+
+        let error = {
+            let result = retry.backoff.retry(attempt).await;
 
             match result {
                 Ok(r) => {
@@ -350,10 +505,6 @@ where
             // For other errors, we don't need to retry.
             return Err(error);
         }
-
-        // Sleep for a random duration.
-        // let duration = Duration::from_millis(rand::rng().random_range(200..=400));
-        // tokio::time::sleep(duration).await;
     }
 
     log::error!("all query pairs failed, giving up");
@@ -376,8 +527,21 @@ where
         form.push(("access_uid", auth_info.get_uid()));
         form
     };
+    let coalesce_key = coalesce_key_of(api, &query, &form);
 
-    do_fetch_text(api, query, |b| b.form(&form), retry).await
+    do_fetch_text(api, query, |b| b.form(&form), retry, coalesce_key).await
+}
+
+/// Key to coalesce the identical requests in flight, or `None` for the write APIs, since each
+/// request of them should take effect on its own.
+fn coalesce_key_of(api: &str, query: &[(&str, &str)], form: &[(&str, &str)]) -> Option<String> {
+    if WRITE_APIS.contains(&api) {
+        return None;
+    }
+    // Hashed so that the auth info in the form does not show up in the logs.
+    let mut hasher = DefaultHasher::new();
+    (query, form).hash(&mut hasher);
+    Some(format!("{}#{:016x}", api, hasher.finish()))
 }
 
 mod xml {
@@ -424,7 +588,8 @@ mod xml {
         query: Vec<(&str, &str)>,
         form: Vec<(&str, &str)>,
     ) -> ServiceResult<sxd_document::Package> {
-        fetch_text_with_auth(api, query, form, RetryMode::never()).await
+        let retry = RetryMode::never().with_backoff(Backoff::for_api(api));
+        fetch_text_with_auth(api, query, form, retry).await
     }
 
     pub async fn fetch_package_with_retry(
@@ -449,7 +614,15 @@ mod xml {
                 .text("access_uid", auth_info.uid.clone())
         };
 
-        do_fetch_text(api, query, |b| b.multipart(make_form()), RetryMode::never()).await
+        // Uploads are never coalesced.
+        do_fetch_text(
+            api,
+            query,
+            |b| b.multipart(make_form()),
+            RetryMode::never(),
+            None,
+        )
+        .await
     }
 }
 
@@ -518,7 +691,8 @@ mod json {
         query: Vec<(&str, &str)>,
         form: Vec<(&str, &str)>,
     ) -> ServiceResult<serde_json::Value> {
-        fetch_text_with_auth(api, query, form, RetryMode::never()).await
+        let retry = RetryMode::never().with_backoff(Backoff::for_api(api));
+        fetch_text_with_auth(api, query, form, retry).await
     }
 
     #[cfg(test)]
@@ -584,7 +758,8 @@ mod web {
         query: Vec<(&str, &str)>,
         form: Vec<(&str, &str)>,
     ) -> ServiceResult<String> {
-        fetch_text_with_auth(api, query, form, RetryMode::never())
+        let retry = RetryMode::never().with_backoff(Backoff::for_api(api));
+        fetch_text_with_auth(api, query, form, retry)
             .await
             .map(|h: WebHtml| h.0)
     }
@@ -614,3 +789,113 @@ pub use self::json::*;
 pub use self::mock::*;
 pub use self::web::*;
 pub use self::xml::*;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protos::DataModel::ErrorMessage;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff::idempotent();
+        for (retries, exp) in [(0, 500), (1, 1000), (2, 2000), (5, 8000), (100, 8000)] {
+            let delay = backoff.delay(retries);
+            let exp = Duration::from_millis(exp);
+            assert!(
+                delay >= exp / 2 && delay <= exp,
+                "{:?} for {}",
+                delay,
+                retries
+            );
+        }
+    }
+
+    #[test]
+    fn test_coalesce_key() {
+        let query = [("__lib", "topic"), ("fid", "-7")];
+        let form = [("access_uid", "1")];
+        let key = coalesce_key_of("nuke.php", &query, &form);
+        assert!(key.is_none());
+        let key = coalesce_key_of("post.php", &query, &form);
+        assert!(key.is_none());
+
+        let key = coalesce_key_of("thread.php", &query, &form).unwrap();
+        assert!(key.starts_with("thread.php#"));
+        assert!(!key.contains("access_uid"));
+        assert_eq!(
+            coalesce_key_of("thread.php", &query, &form).as_ref(),
+            Some(&key)
+        );
+        assert_ne!(
+            coalesce_key_of("thread.php", &query, &[("access_uid", "2")]).as_ref(),
+            Some(&key)
+        );
+    }
+
+    #[test]
+    fn test_backoff_is_transient() {
+        let nga = |info: &str| {
+            ServiceError::Nga(ErrorMessage {
+                code: "0".to_owned(),
+                info: info.to_owned(),
+                ..Default::default()
+            })
+        };
+        let status = |s: StatusCode| ServiceError::from_status(s);
+
+        for backoff in [Backoff::idempotent(), Backoff::unsent_only()] {
+            assert!(backoff.is_transient(&nga("发帖太快")));
+            assert!(backoff.is_transient(&status(StatusCode::TOO_MANY_REQUESTS)));
+            assert!(backoff.is_transient(&status(StatusCode::SERVICE_UNAVAILABLE)));
+            assert!(!backoff.is_transient(&nga("帖子不存在或已被删除")));
+            assert!(!backoff.is_transient(&status(StatusCode::NOT_FOUND)));
+            assert!(!backoff.is_transient(&ServiceError::MngaInternal("".to_owned())));
+        }
+        assert!(Backoff::idempotent().is_transient(&status(StatusCode::BAD_GATEWAY)));
+        assert!(!Backoff::unsent_only().is_transient(&status(StatusCode::BAD_GATEWAY)));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_retry() {
+        let backoff = Backoff {
+            base_delay: Duration::from_millis(1),
+            ..Backoff::idempotent()
+        };
+        let count = AtomicU32::new(0);
+        let flaky = |failures: u32| {
+            let count = &count;
+            move || async move {
+                if count.fetch_add(1, Ordering::SeqCst) < failures {
+                    Err(ServiceError::from_status(StatusCode::SERVICE_UNAVAILABLE))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        assert!(backoff.retry(flaky(2)).await.is_ok());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 3);
+
+        // Give up after the budget is used up.
+        assert!(backoff.retry(flaky(10)).await.is_err());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 4);
+
+        let no_retry = Backoff {
+            max_retries: 0,
+            ..Backoff::idempotent()
+        };
+        assert!(no_retry.retry(flaky(1)).await.is_err());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+
+        // Other errors are never retried.
+        let result: ServiceResult<()> = backoff
+            .retry(|| async {
+                count.fetch_add(1, Ordering::SeqCst);
+                Err(ServiceError::MngaInternal("".to_owned()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,352 @@
+//! Record/replay layer under `fetch`, which makes it possible to run tests against captured
+//! NGA responses without network access or credentials.
+//!
+//! Fixtures are keyed by the normalized request: method, path, query pairs and form pairs.
+//! The base URL, the order of the pairs and the auth info are ignored. Each fixture consists of
+//! a `<key>.json` describing the request and response status, and a `<key>.body` holding the raw
+//! response body, which is usually GB18030-encoded.
+//!
+//! Tests run with `with_fixtures` replay the fixtures under `service/fixtures`, or record them
+//! again if `MNGA_FIXTURE_MODE=record`. Identical requests in such a test are told apart by their
+//! order, so that the changes made by the test are replayed as well.
+
+use std::{env, fs, path::PathBuf};
+
+use itertools::Itertools;
+use protos::DataModel::RequestOption_FixtureMode as FixtureMode;
+use reqwest::{Request, Response, StatusCode, header::CONTENT_TYPE};
+use serde_json::{Value, json};
+
+use crate::{
+    error::{ServiceError, ServiceResult},
+    request,
+};
+
+const MODE_ENV: &str = "MNGA_FIXTURE_MODE";
+const DIR_ENV: &str = "MNGA_FIXTURE_DIR";
+const DEFAULT_DIR: &str = "fixtures";
+
+/// Form fields that do not take part in the fixture key.
+const IGNORED_FORM_KEYS: &[&str] = &["access_token", "access_uid"];
+
+/// Read the fixture mode from `MNGA_FIXTURE_MODE`, which is either `record` or `replay`.
+pub fn mode_from_env() -> Option<FixtureMode> {
+    match env::var(MODE_ENV).ok()?.to_ascii_lowercase().as_str() {
+        "record" => Some(FixtureMode::RECORD),
+        "replay" => Some(FixtureMode::REPLAY),
+        _ => None,
+    }
+}
+
+/// Normalized representation of a request, used to look up fixtures.
+#[derive(Debug, Clone)]
+pub struct FixtureRequest {
+    method: String,
+    url: String,
+    path: String,
+    query: Vec<(String, String)>,
+    form: Vec<(String, String)>,
+}
+
+impl FixtureRequest {
+    pub fn from_request(request: &Request) -> Self {
+        let url = request.url();
+
+        let query = url.query_pairs().into_owned().sorted().collect();
+        // Multipart bodies are streamed and cannot be inspected, so they're simply ignored.
+        let form = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| {
+                url::form_urlencoded::parse(b)
+                    .into_owned()
+                    .filter(|(k, _)| !IGNORED_FORM_KEYS.contains(&k.as_str()))
+                    .sorted()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut bare_url = url.clone();
+        bare_url.set_query(None);
+
+        Self {
+            method: request.method().to_string(),
+            url: bare_url.to_string(),
+            path: url.path().trim_start_matches('/').to_owned(),
+            query,
+            form,
+        }
+    }
+
+    pub fn key(&self) -> String {
+        fn encode(pairs: &[(String, String)]) -> String {
+            url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(pairs)
+                .finish()
+        }
+
+        let canonical = format!(
+            "{} {}?{}\n{}",
+            self.method,
+            self.path,
+            encode(&self.query),
+            encode(&self.form)
+        );
+        let name = self.path.replace(
+            |c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'),
+            "_",
+        );
+
+        format!("{}-{:016x}", name, fnv1a(canonical.as_bytes()))
+    }
+}
+
+/// A stable hash, so that fixture names do not change across Rust versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub struct Fixture {
+    mode: FixtureMode,
+    dir: PathBuf,
+}
+
+impl Fixture {
+    /// Resolve the fixture settings from the request option, falling back to the environment
+    /// variables `MNGA_FIXTURE_MODE` and `MNGA_FIXTURE_DIR`.
+    pub fn current() -> Option<Self> {
+        let option = request::request_option();
+        let (mode, dir) = if option.get_fixture_mode() != FixtureMode::DISABLED {
+            (option.get_fixture_mode(), option.fixture_dir)
+        } else {
+            (mode_from_env()?, env::var(DIR_ENV).unwrap_or_default())
+        };
+        let dir = if dir.is_empty() {
+            PathBuf::from(DEFAULT_DIR)
+        } else {
+            PathBuf::from(dir)
+        };
+
+        Some(Self { mode, dir })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == FixtureMode::REPLAY
+    }
+
+    fn paths(&self, key: &str) -> (PathBuf, PathBuf) {
+        let path = |ext: &str| self.dir.join(format!("{}.{}", key, ext));
+        (path("json"), path("body"))
+    }
+
+    /// Key of the fixture, suffixed with the number of identical requests before in the test.
+    fn occurrence_key(request: &FixtureRequest) -> String {
+        let key = request.key();
+        #[cfg(test)]
+        if let Ok(n) = OCCURRENCES.try_with(|o| {
+            let mut occurrences = o.borrow_mut();
+            let n = occurrences.entry(key.clone()).or_default();
+            *n += 1;
+            *n - 1
+        }) && n > 0
+        {
+            return format!("{}.{}", key, n);
+        }
+        key
+    }
+
+    /// Serve the response of `request` from the recorded fixture.
+    pub fn replay(&self, request: &Request) -> ServiceResult<Response> {
+        let key = Self::occurrence_key(&FixtureRequest::from_request(request));
+        let (meta_path, body_path) = self.paths(&key);
+
+        if !meta_path.exists() {
+            return Err(ServiceError::MngaInternal(format!(
+                "No fixture `{}` recorded for `{}` in {}",
+                key,
+                request.url(),
+                self.dir.display()
+            )));
+        }
+        let meta: Value = serde_json::from_slice(&fs::read(meta_path)?)?;
+        let body = fs::read(body_path)?;
+
+        let status = meta["status"]
+            .as_u64()
+            .and_then(|s| StatusCode::from_u16(s as u16).ok())
+            .unwrap_or(StatusCode::OK);
+        let mut builder = http::Response::builder().status(status);
+        if let Some(content_type) = meta["content_type"].as_str() {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        let response = builder
+            .body(body)
+            .map_err(|e| ServiceError::MngaInternal(e.to_string()))?;
+
+        log::info!("replay fixture: key={}", key);
+        Ok(response.into())
+    }
+
+    /// Save the response of `request` as a fixture, and return an equivalent response.
+    pub async fn record(
+        &self,
+        request: &FixtureRequest,
+        response: Response,
+    ) -> ServiceResult<Response> {
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToOwned::to_owned);
+        let body = response.bytes().await?.to_vec();
+
+        self.save(request, status, content_type.as_deref(), &body)?;
+
+        let mut builder = http::Response::builder().status(status);
+        if let Some(content_type) = content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+        let response = builder
+            .body(body)
+            .map_err(|e| ServiceError::MngaInternal(e.to_string()))?;
+        Ok(response.into())
+    }
+
+    fn save(
+        &self,
+        request: &FixtureRequest,
+        status: StatusCode,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> ServiceResult<()> {
+        let key = Self::occurrence_key(request);
+        let (meta_path, body_path) = self.paths(&key);
+
+        let meta = json!({
+            "method": request.method,
+            "url": request.url,
+            "query": request.query,
+            "form": request.form,
+            "status": status.as_u16(),
+            "content_type": content_type,
+        });
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(meta_path, serde_json::to_vec_pretty(&meta)?)?;
+        fs::write(body_path, body)?;
+
+        log::info!("record fixture: key={}", key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    static OCCURRENCES: std::cell::RefCell<std::collections::HashMap<String, usize>>;
+}
+
+/// Run the test `f` against the fixtures under `service/fixtures`, which are recorded instead if
+/// `MNGA_FIXTURE_MODE=record`.
+#[cfg(test)]
+pub async fn with_fixtures<F: futures::Future>(f: F) -> <F as futures::Future>::Output {
+    let mut option = request::request_option();
+    option.set_fixture_mode(match mode_from_env() {
+        Some(FixtureMode::RECORD) => FixtureMode::RECORD,
+        _ => FixtureMode::REPLAY,
+    });
+    option.set_fixture_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures").to_owned());
+
+    let f = request::with_request_option(option, f);
+    OCCURRENCES.scope(Default::default(), f).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{msg::get_short_msg_list, request::with_request_option, utils::get_unique_id};
+    use protos::Service::ShortMessageListRequest;
+    use reqwest::Client;
+
+    fn build_request(query: &[(&str, &str)], form: &[(&str, &str)]) -> Request {
+        Client::new()
+            .post("https://bbs.nga.cn/nuke.php")
+            .query(query)
+            .form(form)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_key_normalization() {
+        let key = |r: &Request| FixtureRequest::from_request(r).key();
+
+        let a = build_request(
+            &[("__lib", "noti"), ("__act", "get_all")],
+            &[("access_uid", "1"), ("access_token", "a")],
+        );
+        let b = build_request(
+            &[("__act", "get_all"), ("__lib", "noti")],
+            &[("access_token", "b"), ("access_uid", "2")],
+        );
+        let c = build_request(
+            &[("__lib", "noti"), ("__act", "get_all"), ("page", "2")],
+            &[],
+        );
+
+        assert_eq!(key(&a), key(&b));
+        assert_ne!(key(&a), key(&c));
+        assert!(key(&a).starts_with("nuke.php-"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_short_msg_list() -> ServiceResult<()> {
+        let dir = env::temp_dir().join(format!("mnga-fixtures-{}", get_unique_id()));
+        let fixture = Fixture {
+            mode: FixtureMode::REPLAY,
+            dir: dir.clone(),
+        };
+
+        let request = build_request(
+            &[
+                ("__lib", "message"),
+                ("__act", "message"),
+                ("act", "list"),
+                ("page", "1"),
+                ("__inchst", "UTF8"),
+                ("__output", "8"),
+            ],
+            &[],
+        );
+        let body = r#"{"data":{"0":{"0":{"mid":"233","subject":"Hello","from":"1","from_username":"MNGA","time":1,"posts":3}}},"time":1}"#;
+        fixture.save(
+            &FixtureRequest::from_request(&request),
+            StatusCode::OK,
+            None,
+            body.as_bytes(),
+        )?;
+
+        let mut option = request::request_option();
+        option.set_fixture_mode(FixtureMode::REPLAY);
+        option.set_fixture_dir(dir.to_string_lossy().into_owned());
+
+        let response = with_request_option(
+            option,
+            get_short_msg_list(ShortMessageListRequest {
+                page: 1,
+                ..Default::default()
+            }),
+        )
+        .await?;
+
+        let messages = response.get_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].get_subject(), "Hello");
+        assert_eq!(messages[0].get_post_num(), 3);
+
+        let _ = fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
@@ -258,120 +258,132 @@ pub async fn modify_favorite_forum(
 
 #[cfg(test)]
 mod test {
-    use crate::fetch::with_fetch_check;
+    use crate::{fetch::with_fetch_check, fixture::with_fixtures};
 
     use super::*;
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_set_filter() -> ServiceResult<()> {
-        let _response = with_fetch_check(
-            |r| assert!(r.contains("操作成功")),
-            set_subforum_filter(SubforumFilterRequest {
-                forum_id: "310".to_owned(),
-                subforum_filter_id: "19115466".to_owned(),
-                operation: SubforumFilterRequest_Operation::SHOW,
-                ..Default::default()
-            }),
-        )
-        .await?;
+        with_fixtures(async {
+            let _response = with_fetch_check(
+                |r| assert!(r.contains("操作成功")),
+                set_subforum_filter(SubforumFilterRequest {
+                    forum_id: "310".to_owned(),
+                    subforum_filter_id: "19115466".to_owned(),
+                    operation: SubforumFilterRequest_Operation::SHOW,
+                    ..Default::default()
+                }),
+            )
+            .await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_get_forum_list() -> ServiceResult<()> {
-        let response = get_forum_list(ForumListRequest::new()).await?;
+        with_fixtures(async {
+            let response = get_forum_list(ForumListRequest::new()).await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        let forum_exists = response
-            .get_categories()
-            .iter()
-            .flat_map(|c| c.get_forums())
-            .any(|f| f.name == "晴风村");
-        assert!(forum_exists);
+            let forum_exists = response
+                .get_categories()
+                .iter()
+                .flat_map(|c| c.get_forums())
+                .any(|f| f.name == "晴风村");
+            assert!(forum_exists);
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_search_forum_chinese() -> ServiceResult<()> {
-        let response = search_forum(ForumSearchRequest {
-            key: "原神".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = search_forum(ForumSearchRequest {
+                key: "原神".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        let forum_exists = response.get_forums().iter().any(|f| f.name == "原神");
-        assert!(forum_exists);
+            let forum_exists = response.get_forums().iter().any(|f| f.name == "原神");
+            assert!(forum_exists);
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_search_forum_not_exist() -> ServiceResult<()> {
-        let response = search_forum(ForumSearchRequest {
-            key: "元神".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = search_forum(ForumSearchRequest {
+                key: "元神".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        assert!(response.get_forums().is_empty());
+            assert!(response.get_forums().is_empty());
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_get_favorite_forum_list() -> ServiceResult<()> {
-        let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
+        with_fixtures(async {
+            let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_favorite_forum() -> ServiceResult<()> {
-        let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
-        let favor1 = response.get_forums();
+        with_fixtures(async {
+            let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
+            let favor1 = response.get_forums();
 
-        for id in [make_fid("708".to_owned()), make_stid("16667422".to_owned())] {
-            let _response = modify_favorite_forum(FavoriteForumModifyRequest {
-                id: id.clone().into(),
-                operation: FavoriteForumModifyRequest_Operation::ADD,
-                ..Default::default()
-            })
-            .await?;
+            for id in [make_fid("708".to_owned()), make_stid("16667422".to_owned())] {
+                let _response = modify_favorite_forum(FavoriteForumModifyRequest {
+                    id: id.clone().into(),
+                    operation: FavoriteForumModifyRequest_Operation::ADD,
+                    ..Default::default()
+                })
+                .await?;
 
-            let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
-            let favor2 = response.get_forums();
+                let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
+                let favor2 = response.get_forums();
 
-            assert_eq!(favor1.len() + 1, favor2.len());
-            assert!(favor2.iter().any(|f| f.id == id.clone().into()));
+                assert_eq!(favor1.len() + 1, favor2.len());
+                assert!(favor2.iter().any(|f| f.id == id.clone().into()));
 
-            let _response = modify_favorite_forum(FavoriteForumModifyRequest {
-                id: id.into(),
-                operation: FavoriteForumModifyRequest_Operation::DEL,
-                ..Default::default()
-            })
-            .await?;
+                let _response = modify_favorite_forum(FavoriteForumModifyRequest {
+                    id: id.into(),
+                    operation: FavoriteForumModifyRequest_Operation::DEL,
+                    ..Default::default()
+                })
+                .await?;
 
-            let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
-            let favor3 = response.get_forums();
+                let response = get_favorite_forum_list(FavoriteForumListRequest::new()).await?;
+                let favor3 = response.get_forums();
 
-            assert_eq!(favor1, favor3);
-        }
+                assert_eq!(favor1, favor3);
+            }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 }
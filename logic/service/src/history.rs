@@ -1,9 +1,9 @@
 use crate::error::ServiceResult;
 use cache::CACHE;
 use chrono::Utc;
+use futures::StreamExt;
 use protos::{
     DataModel::{Topic, TopicSnapshot},
-    Message,
     Service::{TopicHistoryRequest, TopicHistoryResponse, UpdateTopicProgressRequest},
 };
 use std::cmp::Reverse;
@@ -13,14 +13,14 @@ fn topic_snapshot_key(id: &str) -> String {
     format!("{}/{}", TOPIC_SNAPSHOT_PREFIX, id)
 }
 
-pub fn insert_topic_history(topic: Topic) {
+pub async fn insert_topic_history(topic: Topic) {
     let key = topic_snapshot_key(topic.get_id());
     let snapshot = TopicSnapshot {
         topic_snapshot: Some(topic).into(),
         timestamp: Utc::now().timestamp_millis() as u64,
         ..Default::default()
     };
-    let _ = CACHE.insert_msg(&key, &snapshot);
+    let _ = CACHE.insert_msg_async(&key, &snapshot).await;
 }
 
 pub fn find_topic_history(topic_id: &str) -> Option<TopicSnapshot> {
@@ -49,13 +49,10 @@ pub async fn get_topic_history(
     request: TopicHistoryRequest,
 ) -> ServiceResult<TopicHistoryResponse> {
     let snapshots = {
-        let mut ss = tokio::task::block_in_place(|| {
-            CACHE
-                .scan_prefix(TOPIC_SNAPSHOT_PREFIX)
-                .filter_map(|p| p.ok())
-                .filter_map(|(_k, v)| TopicSnapshot::parse_from_bytes(&v).ok())
-                .collect::<Vec<_>>()
-        });
+        let mut ss = CACHE
+            .scan_msg_async::<TopicSnapshot>(TOPIC_SNAPSHOT_PREFIX)
+            .collect::<Vec<_>>()
+            .await;
 
         ss.sort_by_key(|s| Reverse(s.timestamp)); // todo: use heap
         let _ = ss.split_off((request.limit as usize).min(ss.len()));
@@ -1,18 +1,28 @@
 mod attachment;
 mod auth;
+mod backup;
+mod block;
 mod cache;
 mod clock_in;
 mod constants;
 mod dispatch;
+mod download;
 pub mod error;
+mod export;
+#[cfg(test)]
+mod fake_nga;
 mod fetch;
+mod fixture;
 mod forum;
 mod history;
 mod macros;
 mod msg;
 mod noti;
 mod post;
+pub mod progress;
 mod request;
+mod search;
+mod throttle;
 mod topic;
 mod user;
 mod utils;
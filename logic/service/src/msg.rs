@@ -201,92 +201,101 @@ mod test {
     use protos::DataModel::{ShortMessagePostAction, ShortMessagePostAction_Operation};
 
     use super::*;
+    use crate::fixture::with_fixtures;
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_get_short_msg_list() -> ServiceResult<()> {
-        let response = get_short_msg_list(ShortMessageListRequest {
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = get_short_msg_list(ShortMessageListRequest {
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        assert!(!response.get_messages().is_empty());
+            assert!(!response.get_messages().is_empty());
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_get_short_msg_details() -> ServiceResult<()> {
-        let list = get_short_msg_list(ShortMessageListRequest {
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
-        let mid = list
-            .get_messages()
-            .first()
-            .map(|msg| msg.get_id().to_owned())
-            .unwrap();
-
-        let response = get_short_msg_details(ShortMessageDetailsRequest {
-            id: mid,
-            page: 1,
-            ..Default::default()
+        with_fixtures(async {
+            let list = get_short_msg_list(ShortMessageListRequest {
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
+            let mid = list
+                .get_messages()
+                .first()
+                .map(|msg| msg.get_id().to_owned())
+                .unwrap();
+
+            let response = get_short_msg_details(ShortMessageDetailsRequest {
+                id: mid,
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
+
+            println!("response: {:?}", response);
+
+            assert!(!response.get_posts().is_empty());
+
+            Ok(())
         })
-        .await?;
-
-        println!("response: {:?}", response);
-
-        assert!(!response.get_posts().is_empty());
-
-        Ok(())
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_new_short_msg() -> ServiceResult<()> {
-        let action = ShortMessagePostAction {
-            operation: ShortMessagePostAction_Operation::NEW,
-            ..Default::default()
-        };
-
-        let response = post_short_msg(ShortMessagePostRequest {
-            action: Some(action).into(),
-            content: "Test Content".to_owned(),
-            subject: "Test Short Message from Logic Test".to_owned(),
-            to: vec!["y-ricky".to_owned()].into(),
-            ..Default::default()
+        with_fixtures(async {
+            let action = ShortMessagePostAction {
+                operation: ShortMessagePostAction_Operation::NEW,
+                ..Default::default()
+            };
+
+            let response = post_short_msg(ShortMessagePostRequest {
+                action: Some(action).into(),
+                content: "Test Content".to_owned(),
+                subject: "Test Short Message from Logic Test".to_owned(),
+                to: vec!["y-ricky".to_owned()].into(),
+                ..Default::default()
+            })
+            .await?;
+
+            println!("response: {:?}", response);
+
+            Ok(())
         })
-        .await?;
-
-        println!("response: {:?}", response);
-
-        Ok(())
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_reply_short_msg() -> ServiceResult<()> {
-        let action = ShortMessagePostAction {
-            operation: ShortMessagePostAction_Operation::REPLY,
-            mid: "3549006".to_owned(),
-            ..Default::default()
-        };
-
-        let response = post_short_msg(ShortMessagePostRequest {
-            action: Some(action).into(),
-            content: "Test Reply Content".to_owned(),
-            subject: "Test Reply Short Message from Logic Test".to_owned(),
-            ..Default::default()
+        with_fixtures(async {
+            let action = ShortMessagePostAction {
+                operation: ShortMessagePostAction_Operation::REPLY,
+                mid: "3549006".to_owned(),
+                ..Default::default()
+            };
+
+            let response = post_short_msg(ShortMessagePostRequest {
+                action: Some(action).into(),
+                content: "Test Reply Content".to_owned(),
+                subject: "Test Reply Short Message from Logic Test".to_owned(),
+                ..Default::default()
+            })
+            .await?;
+
+            println!("response: {:?}", response);
+
+            Ok(())
         })
-        .await?;
-
-        println!("response: {:?}", response);
-
-        Ok(())
+        .await
     }
 }
@@ -1,5 +1,7 @@
 use std::{cmp::Reverse, collections::HashMap};
 
+use futures::StreamExt;
+
 use protos::{
     DataModel::{Notification, Notification_Type, PostId, User},
     ProtobufEnum,
@@ -10,11 +12,14 @@ use protos::{
 };
 use serde_json::Value;
 
-use crate::{error::ServiceResult, fetch::fetch_json_value, user::extract_user_name};
+use crate::{
+    auth::user_prefix, block, error::ServiceResult, fetch::fetch_json_value,
+    user::extract_user_name,
+};
 
 pub static NOTI_PREFIX: &str = "/noti_v2";
 fn noti_key(id: &str) -> String {
-    format!("{}/{}", NOTI_PREFIX, id)
+    format!("{}/{}", user_prefix(NOTI_PREFIX), id)
 }
 
 fn extract_noti(value: &Value) -> Option<Notification> {
@@ -110,24 +115,26 @@ pub async fn fetch_notis(
         notis
     };
 
-    notis.into_iter().for_each(|noti| {
+    for noti in notis {
         let key = noti_key(noti.get_id());
         let not_exist = cache::CACHE
-            .get_msg::<Notification>(&key)
+            .get_msg_async::<Notification>(&key)
+            .await
             .ok()
             .flatten()
             .is_none();
         if not_exist {
-            let _ = cache::CACHE.insert_msg(&key, &noti);
+            let _ = cache::CACHE.insert_msg_async(&key, &noti).await;
         }
-    });
+    }
 
     let notis = {
         let mut notis = cache::CACHE
-            .scan_msg::<Notification>(NOTI_PREFIX)
-            .collect::<Vec<_>>();
+            .scan_msg_async::<Notification>(&format!("{}/", user_prefix(NOTI_PREFIX)))
+            .collect::<Vec<_>>()
+            .await;
         notis.sort_by_key(|n| Reverse(n.timestamp));
-        notis.into()
+        block::filter_notis(notis).into()
     };
 
     Ok(FetchNotificationResponse {
@@ -157,33 +164,39 @@ pub fn mark_noti_read(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{cache::test::ENCRYPTION_TEST_LOCK, fixture::with_fixtures};
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_notis() -> ServiceResult<()> {
-        let response = fetch_notis(FetchNotificationRequest::new()).await?;
-        println!("response: {:?}", response);
-
-        let all_unread = response.get_notis().iter().all(|noti| !noti.read);
-        assert!(all_unread);
-
-        if let Some(noti) = response.get_notis().first() {
-            let id = noti.get_id();
-            mark_noti_read(MarkNotificationReadRequest {
-                ids: vec![id.to_owned()].into(),
-                ..Default::default()
-            })?;
-
-            let new_response = fetch_notis(FetchNotificationRequest::new()).await?;
-            let marked = new_response
-                .get_notis()
-                .iter()
-                .find(|noti| noti.id == id)
-                .unwrap()
-                .read;
-            assert!(marked);
-        }
+        // Notifications are encrypted and decrypted by the other tests.
+        let _guard = ENCRYPTION_TEST_LOCK.lock().await;
+        with_fixtures(async {
+            let response = fetch_notis(FetchNotificationRequest::new()).await?;
+            println!("response: {:?}", response);
+
+            let all_unread = response.get_notis().iter().all(|noti| !noti.read);
+            assert!(all_unread);
+
+            if let Some(noti) = response.get_notis().first() {
+                let id = noti.get_id();
+                mark_noti_read(MarkNotificationReadRequest {
+                    ids: vec![id.to_owned()].into(),
+                    read: true,
+                    ..Default::default()
+                })?;
+
+                let new_response = fetch_notis(FetchNotificationRequest::new()).await?;
+                let marked = new_response
+                    .get_notis()
+                    .iter()
+                    .find(|noti| noti.id == id)
+                    .unwrap()
+                    .read;
+                assert!(marked);
+            }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 }
@@ -1,5 +1,6 @@
 use crate::{
     attachment::extract_attachment,
+    auth::user_prefix,
     error::ServiceResult,
     fetch::fetch_json_value,
     fetch::fetch_package_multipart,
@@ -16,8 +17,14 @@ use protos::{DataModel::*, Service::*, ToValue};
 use reqwest::multipart;
 use sxd_xpath::nodeset::Node;
 
+pub static VOTE_RESPONSE_PREFIX: &str = "/vote_response";
 fn vote_response_key(id: &PostId) -> String {
-    format!("/vote_response/topic/{}/post/{}", id.tid, id.pid)
+    format!(
+        "{}/topic/{}/post/{}",
+        user_prefix(VOTE_RESPONSE_PREFIX),
+        id.tid,
+        id.pid
+    )
 }
 
 pub fn extract_post(node: Node, at_page: u32, context: &str) -> Option<Post> {
@@ -178,7 +185,9 @@ pub async fn post_vote(request: PostVoteRequest) -> ServiceResult<PostVoteRespon
         state,
         ..Default::default()
     };
-    let _ = CACHE.insert_msg(&vote_response_key(request.get_post_id()), &response);
+    let _ = CACHE
+        .insert_msg_async(&vote_response_key(request.get_post_id()), &response)
+        .await;
 
     Ok(response)
 }
@@ -408,140 +417,149 @@ pub async fn get_user_post_list(
 mod test {
     use crate::{
         fetch::with_fetch_check,
+        fixture::with_fixtures,
         forum::{make_fid, make_stid},
-        utils::get_unique_id,
     };
 
     use super::*;
     use protos::DataModel::PostId;
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_vote() -> ServiceResult<()> {
-        use PostVoteRequest_Operation::*;
-        let vote = |op| {
-            with_fetch_check(
-                |c| println!("{}", c),
-                post_vote(PostVoteRequest {
-                    post_id: Some(PostId {
-                        tid: "27477718".to_owned(),
-                        pid: "0".to_owned(),
+        with_fixtures(async {
+            use PostVoteRequest_Operation::*;
+            let vote = |op| {
+                with_fetch_check(
+                    |c| println!("{}", c),
+                    post_vote(PostVoteRequest {
+                        post_id: Some(PostId {
+                            tid: "27477718".to_owned(),
+                            pid: "0".to_owned(),
+                            ..Default::default()
+                        })
+                        .into(),
+                        operation: op,
                         ..Default::default()
-                    })
-                    .into(),
-                    operation: op,
-                    ..Default::default()
-                }),
-            )
-        };
+                    }),
+                )
+            };
 
-        while vote(UPVOTE).await.unwrap().delta != -1 {}
+            while vote(UPVOTE).await.unwrap().delta != -1 {}
 
-        assert_eq!(vote(UPVOTE).await.unwrap().delta, 1);
-        assert_eq!(vote(UPVOTE).await.unwrap().delta, -1);
-        assert_eq!(vote(UPVOTE).await.unwrap().delta, 1);
-        assert_eq!(vote(DOWNVOTE).await.unwrap().delta, -2);
-        assert_eq!(vote(DOWNVOTE).await.unwrap().delta, 1);
+            assert_eq!(vote(UPVOTE).await.unwrap().delta, 1);
+            assert_eq!(vote(UPVOTE).await.unwrap().delta, -1);
+            assert_eq!(vote(UPVOTE).await.unwrap().delta, 1);
+            assert_eq!(vote(DOWNVOTE).await.unwrap().delta, -2);
+            assert_eq!(vote(DOWNVOTE).await.unwrap().delta, 1);
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_reply() -> ServiceResult<()> {
-        let _response = post_reply(PostReplyRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::REPLY,
-                post_id: Some(PostId {
-                    tid: "27455825".to_owned(),
-                    pid: "0".to_owned(),
+        with_fixtures(async {
+            let _response = post_reply(PostReplyRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::REPLY,
+                    post_id: Some(PostId {
+                        tid: "27455825".to_owned(),
+                        pid: "0".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
+                content: "测试回复 from logic test".to_owned(),
                 ..Default::default()
             })
-            .into(),
-            content: "测试回复 from logic test".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_reply_emoji() -> ServiceResult<()> {
-        let marker = get_unique_id();
-        let content = format!("测试回帖表情验证 {marker} 😄❤️");
+        with_fixtures(async {
+            let content = "测试回帖表情验证 😄❤️".to_owned();
 
-        let _response = post_reply(PostReplyRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::REPLY,
-                post_id: Some(PostId {
-                    tid: "45150945".to_owned(),
-                    pid: "0".to_owned(),
+            let _response = post_reply(PostReplyRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::REPLY,
+                    post_id: Some(PostId {
+                        tid: "45150945".to_owned(),
+                        pid: "0".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
+                content,
                 ..Default::default()
             })
-            .into(),
-            content: content.clone(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_reply_fetch_content() -> ServiceResult<()> {
-        let response = post_reply_fetch_content(PostReplyFetchContentRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::QUOTE,
-                post_id: Some(PostId {
-                    tid: "45150945".to_owned(),
-                    pid: "0".to_owned(),
+        with_fixtures(async {
+            let response = post_reply_fetch_content(PostReplyFetchContentRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::QUOTE,
+                    post_id: Some(PostId {
+                        tid: "45150945".to_owned(),
+                        pid: "0".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
                 ..Default::default()
             })
-            .into(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        assert!(response.get_content().contains("[quote][tid=45150945]"));
+            assert!(response.get_content().contains("[quote][tid=45150945]"));
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_reply_fetch_content_reply_to_post() -> ServiceResult<()> {
-        let response = post_reply_fetch_content(PostReplyFetchContentRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::REPLY,
-                post_id: Some(PostId {
-                    tid: "45150945".to_owned(),
-                    pid: "857399126".to_owned(),
+        with_fixtures(async {
+            let response = post_reply_fetch_content(PostReplyFetchContentRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::REPLY,
+                    post_id: Some(PostId {
+                        tid: "45150945".to_owned(),
+                        pid: "857399126".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
                 ..Default::default()
             })
-            .into(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        assert!(response.get_content().contains("Reply to"));
-        assert!(!response.get_content().contains("[quote]"));
+            assert!(response.get_content().contains("Reply to"));
+            assert!(!response.get_content().contains("[quote]"));
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
     #[test]
@@ -550,134 +568,138 @@ mod test {
         assert_eq!(decode_editor_text(encoded.to_owned()), "emoji test 😅🥊");
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_reply_fetch_content_modify_unescape_online() -> ServiceResult<()> {
-        let expected_content = "测试表情 😅😅🥊👨🏽‍❤️‍👨🏿👩🏽‍❤️‍💋‍👨🏽".to_owned();
+        with_fixtures(async {
+            let expected_content = "测试表情 😅😅🥊👨🏽‍❤️‍👨🏿👩🏽‍❤️‍💋‍👨🏽".to_owned();
 
-        let response = post_reply_fetch_content(PostReplyFetchContentRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::MODIFY,
-                post_id: Some(PostId {
-                    tid: "45150945".to_owned(),
-                    pid: "857399126".to_owned(),
+            let response = post_reply_fetch_content(PostReplyFetchContentRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::MODIFY,
+                    post_id: Some(PostId {
+                        tid: "45150945".to_owned(),
+                        pid: "857399126".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
                 ..Default::default()
             })
-            .into(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        let content = response.get_content();
-        assert_eq!(content.trim_end(), expected_content);
+            let content = response.get_content();
+            assert_eq!(content.trim_end(), expected_content);
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_new_topic() -> ServiceResult<()> {
-        let _response = post_reply(PostReplyRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::NEW,
-                forum_id: make_stid("12689291".to_owned()).into(),
+        with_fixtures(async {
+            let _response = post_reply(PostReplyRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::NEW,
+                    forum_id: make_stid("12689291".to_owned()).into(),
+                    ..Default::default()
+                })
+                .into(),
+                _subject: PostReplyRequest_oneof__subject::subject(
+                    "测试发帖 from logic test".to_owned(),
+                )
+                .into(),
+                content: "测试内容 from logic test".to_owned(),
                 ..Default::default()
             })
-            .into(),
-            _subject: PostReplyRequest_oneof__subject::subject(
-                "测试发帖 from logic test".to_owned(),
-            )
-            .into(),
-            content: "测试内容 from logic test".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_upload_attachment() -> ServiceResult<()> {
-        let mut action = PostReplyAction {
-            operation: PostReplyAction_Operation::REPLY,
-            post_id: Some(PostId {
-                pid: "0".to_owned(),
-                tid: "28426407".to_owned(),
+        with_fixtures(async {
+            let mut action = PostReplyAction {
+                operation: PostReplyAction_Operation::REPLY,
+                post_id: Some(PostId {
+                    pid: "0".to_owned(),
+                    tid: "28426407".to_owned(),
+                    ..Default::default()
+                })
+                .into(),
+                forum_id: make_fid("275".to_owned()).into(),
                 ..Default::default()
-            })
-            .into(),
-            forum_id: make_fid("275".to_owned()).into(),
-            ..Default::default()
-        };
-
-        let fetch_req = PostReplyFetchContentRequest {
-            action: Some(action.clone()).into(),
-            ..Default::default()
-        };
+            };
 
-        let mut fetch_res = post_reply_fetch_content(fetch_req).await?;
-        action.set_verbatim(fetch_res.take_verbatim());
+            let fetch_req = PostReplyFetchContentRequest {
+                action: Some(action.clone()).into(),
+                ..Default::default()
+            };
 
-        let file =
-            reqwest::get("https://img.nga.cn/attachments/mon_201904/12/-7Q5-gr04K2iT3cSw0-k0.jpg")
-                .await?
-                .bytes()
-                .await?;
+            let mut fetch_res = post_reply_fetch_content(fetch_req).await?;
+            action.set_verbatim(fetch_res.take_verbatim());
 
-        let upload_req = UploadAttachmentRequest {
-            action: Some(action).into(),
-            file: file.to_vec(),
-            ..Default::default()
-        };
+            let upload_req = UploadAttachmentRequest {
+                action: Some(action).into(),
+                file: include_bytes!("../fixtures/upload.jpeg").to_vec(),
+                ..Default::default()
+            };
 
-        let upload_res = upload_attachment(upload_req).await?;
-        let attachment = upload_res.get_attachment();
+            let upload_res = upload_attachment(upload_req).await?;
+            let attachment = upload_res.get_attachment();
 
-        println!("{:?}", attachment);
-        assert!(!attachment.get_url().is_empty());
+            println!("{:?}", attachment);
+            assert!(!attachment.get_url().is_empty());
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_user_post_list() -> ServiceResult<()> {
-        let response = get_user_post_list(UserPostListRequest {
-            author_id: "41417929".to_owned(),
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = get_user_post_list(UserPostListRequest {
+                author_id: "41417929".to_owned(),
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        assert!(!response.get_tps().is_empty());
+            assert!(!response.get_tps().is_empty());
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_post_report() -> ServiceResult<()> {
-        let _response = post_reply(PostReplyRequest {
-            action: Some(PostReplyAction {
-                operation: PostReplyAction_Operation::REPORT,
-                post_id: Some(PostId {
-                    tid: "28706792".to_owned(),
-                    pid: "0".to_owned(),
+        with_fixtures(async {
+            let _response = post_reply(PostReplyRequest {
+                action: Some(PostReplyAction {
+                    operation: PostReplyAction_Operation::REPORT,
+                    post_id: Some(PostId {
+                        tid: "28706792".to_owned(),
+                        pid: "0".to_owned(),
+                        ..Default::default()
+                    })
+                    .into(),
                     ..Default::default()
                 })
                 .into(),
+                content: "测试举报".to_owned(),
                 ..Default::default()
             })
-            .into(),
-            content: "测试举报".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+            .await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 }
@@ -0,0 +1,79 @@
+//! Progress of long async requests, which is reported to the caller before the final response.
+
+use std::{future::Future, sync::Arc};
+
+use protos::{Message, Service::AsyncProgress};
+
+pub type ProgressSink = Arc<dyn Fn(AsyncProgress) + Send + Sync>;
+
+tokio::task_local! {
+    static SINK: ProgressSink;
+}
+
+/// Run the request future with its progress reported to `sink`.
+pub async fn with_progress<F: Future>(sink: ProgressSink, future: F) -> F::Output {
+    SINK.scope(sink, future).await
+}
+
+fn report(progress: AsyncProgress) {
+    // Nothing to do if the caller is not interested in the progress.
+    let _ = SINK.try_with(|sink| sink(progress));
+}
+
+/// Report that `done` of `total` steps are finished.
+pub fn report_progress(done: u64, total: u64) {
+    report(AsyncProgress {
+        done,
+        total,
+        ..Default::default()
+    });
+}
+
+/// Report that `done` of `total` steps are finished, with an intermediate message of the
+/// response.
+pub fn report_partial<M: Message>(done: u64, total: u64, partial: &M) {
+    report(AsyncProgress {
+        done,
+        total,
+        partial: partial.write_to_bytes().unwrap_or_default(),
+        ..Default::default()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use protos::DataModel::TopicDownloadProgress;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_progress() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let reported = reported.clone();
+            Arc::new(move |p: AsyncProgress| reported.lock().unwrap().push(p)) as ProgressSink
+        };
+        let partial = TopicDownloadProgress {
+            topic_id: "233".to_owned(),
+            ..Default::default()
+        };
+
+        report_progress(0, 2); // not in scope
+        with_progress(sink, async {
+            report_progress(1, 2);
+            report_partial(2, 2, &partial);
+        })
+        .await;
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0].get_done(), 1);
+        assert!(reported[0].get_partial().is_empty());
+        assert_eq!(
+            TopicDownloadProgress::parse_from_bytes(reported[1].get_partial()).unwrap(),
+            partial
+        );
+    }
+}
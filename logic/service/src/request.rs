@@ -32,6 +32,29 @@ pub fn set_request_option(mut option: RequestOption) {
     *REQUEST_OPTION.write().unwrap() = option;
 }
 
+/// Get a snapshot of the current request option.
+pub fn request_option() -> RequestOption {
+    #[cfg(test)]
+    if let Ok(option) = REQUEST_OPTION_OVERRIDE.try_with(Clone::clone) {
+        return option;
+    }
+    REQUEST_OPTION.read().unwrap().clone()
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    static REQUEST_OPTION_OVERRIDE: RequestOption;
+}
+
+/// Run `f` with the given request option, without affecting other tests.
+#[cfg(test)]
+pub async fn with_request_option<F: futures::Future>(
+    option: RequestOption,
+    f: F,
+) -> <F as futures::Future>::Output {
+    REQUEST_OPTION_OVERRIDE.scope(option, f).await
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,456 @@
+//! Local full-text search over the cached topics and posts, which works fully offline.
+//!
+//! Documents are the topics with their subject, tags and author name, and the posts of cached
+//! pages with their plain text and author name. Text is split into lowercase words for
+//! alphanumerics and into bigrams for CJK characters, since there's no space between Chinese
+//! words. The last character of each CJK run is also a term, so that every character starts
+//! some term, and a query term is always looked up as a prefix.
+//!
+//! The inverted index is kept in a dedicated tree of the cache:
+//! - `t/{term}\0{doc}` maps to the term frequency in the document;
+//! - `d/{doc}` maps to the terms of the document, to drop stale postings on reindexing.
+
+use std::collections::{BTreeMap, HashMap};
+
+use cache::{Batch, CACHE, CacheResult, Tree};
+use lazy_static::lazy_static;
+use protos::{
+    DataModel::{Post, Span, Span_oneof_value, Topic, TopicSnapshot},
+    Service::{
+        LocalSearchHit, LocalSearchRequest, LocalSearchResponse, TopicDetailsRequest,
+        TopicDetailsResponse,
+    },
+};
+
+use crate::{
+    block,
+    error::{ServiceError, ServiceResult},
+    history::{TOPIC_SNAPSHOT_PREFIX, find_topic_history},
+    topic::{TOPIC_DETAILS_PREFIX, topic_details_response_key},
+    user::UserController,
+};
+
+static INDEX_TREE: &str = "local_search_index";
+static TERM_PREFIX: &str = "t/";
+static DOC_PREFIX: &str = "d/";
+
+/// Words longer than this are likely to be links or garbage, which are not indexed.
+const MAX_WORD_LEN: usize = 32;
+/// Hits of the topic itself are ranked higher than the ones of its posts.
+const TOPIC_BOOST: f32 = 2.0;
+
+lazy_static! {
+    static ref INDEX: Tree = CACHE
+        .open_tree(INDEX_TREE)
+        .expect("cannot open local search index");
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}' // CJK Extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK Unified Ideographs
+        | '\u{f900}'..='\u{faff}' // CJK Compatibility Ideographs
+        | '\u{ac00}'..='\u{d7af}' // Hangul Syllables
+    )
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    fn flush_word(word: &mut String, terms: &mut Vec<String>) {
+        if !word.is_empty() && word.chars().count() <= MAX_WORD_LEN {
+            terms.push(word.clone());
+        }
+        word.clear();
+    }
+    fn flush_run(run: &mut Vec<char>, terms: &mut Vec<String>) {
+        terms.extend(run.windows(2).map(|w| w.iter().collect::<String>()));
+        terms.extend(run.last().map(|c| c.to_string()));
+        run.clear();
+    }
+
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut run = Vec::new();
+
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut terms);
+            run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_run(&mut run, &mut terms);
+            word.push(c);
+        } else {
+            flush_word(&mut word, &mut terms);
+            flush_run(&mut run, &mut terms);
+        }
+    }
+    flush_word(&mut word, &mut terms);
+    flush_run(&mut run, &mut terms);
+
+    terms
+}
+
+/// Collect the readable text of the spans, skipping the URLs of media.
+fn spans_text(spans: &[Span], out: &mut String) {
+    for span in spans {
+        match &span.value {
+            Some(Span_oneof_value::plain(p)) => out.push_str(p.get_text()),
+            Some(Span_oneof_value::break_line(_)) => out.push('\n'),
+            Some(Span_oneof_value::tagged(t)) => match t.get_tag() {
+                "img" | "album" | "flash" | "attach" | "noimg" => {}
+                _ => {
+                    spans_text(t.get_spans(), out);
+                    out.push(' ');
+                }
+            },
+            Some(Span_oneof_value::sticker(_)) | None => {}
+        }
+    }
+}
+
+fn author_name(author_id: &str) -> String {
+    UserController::get()
+        .get_by_id(author_id)
+        .map(|u| {
+            let name = u.get_name();
+            format!("{} {}", name.get_normal(), name.get_anonymous())
+        })
+        .unwrap_or_default()
+}
+
+fn topic_doc(topic_id: &str) -> String {
+    format!("topic/{}", topic_id)
+}
+
+fn post_doc(post: &Post) -> String {
+    let id = post.get_id();
+    format!(
+        "post/{}/{}/{}",
+        id.get_tid(),
+        post.get_at_page(),
+        id.get_pid()
+    )
+}
+
+fn posting_key(term: &str, doc: &str) -> String {
+    format!("{}{}\0{}", TERM_PREFIX, term, doc)
+}
+
+fn index_document(doc: &str, texts: &[&str]) -> CacheResult<()> {
+    let mut freqs = BTreeMap::<String, u32>::new();
+    for term in texts.iter().flat_map(|t| tokenize(t)) {
+        *freqs.entry(term).or_default() += 1;
+    }
+
+    let doc_key = format!("{}{}", DOC_PREFIX, doc);
+    let mut batch = Batch::default();
+    if let Some(old) = INDEX.get(&doc_key)? {
+        String::from_utf8_lossy(&old)
+            .split('\n')
+            .filter(|term| !term.is_empty() && !freqs.contains_key(*term))
+            .for_each(|term| batch.remove(posting_key(term, doc).as_bytes()));
+    }
+    for (term, freq) in &freqs {
+        batch.insert(posting_key(term, doc).as_bytes(), freq.to_be_bytes());
+    }
+    let terms = freqs.into_keys().collect::<Vec<_>>().join("\n");
+    batch.insert(doc_key.as_bytes(), terms.as_bytes());
+
+    INDEX.apply_batch(batch)?;
+    Ok(())
+}
+
+fn index_topic(topic: &Topic) -> CacheResult<()> {
+    let subject = topic.get_subject();
+    let name = topic.get_author_name();
+    let mut texts = vec![
+        subject.get_content(),
+        name.get_normal(),
+        name.get_anonymous(),
+    ];
+    texts.extend(subject.get_tags().iter().map(String::as_str));
+    index_document(&topic_doc(topic.get_id()), &texts)
+}
+
+fn index_post(post: &Post) -> CacheResult<()> {
+    let mut text = String::new();
+    spans_text(post.get_content().get_spans(), &mut text);
+    let author = author_name(post.get_author_id());
+    index_document(&post_doc(post), &[&text, &author])
+}
+
+fn do_index_topic_details(response: &TopicDetailsResponse) -> CacheResult<()> {
+    index_topic(response.get_topic())?;
+    response.get_replies().iter().try_for_each(index_post)
+}
+
+/// Index the topic and posts of a cached page, should be called once the page is saved.
+pub fn index_topic_details(response: &TopicDetailsResponse) {
+    if let Err(e) = do_index_topic_details(response) {
+        log::error!("failed to index topic details: {}", e);
+    }
+}
+
+pub fn clear_index() -> CacheResult<()> {
+    INDEX.clear()?;
+    Ok(())
+}
+
+/// Rebuild the whole index from the topic history and the cached pages.
+fn rebuild_index() -> CacheResult<()> {
+    clear_index()?;
+    for snapshot in CACHE.scan_msg::<TopicSnapshot>(TOPIC_SNAPSHOT_PREFIX) {
+        index_topic(snapshot.get_topic_snapshot())?;
+    }
+    for response in CACHE.scan_msg::<TopicDetailsResponse>(TOPIC_DETAILS_PREFIX) {
+        do_index_topic_details(&response)?;
+    }
+    Ok(())
+}
+
+/// Find the documents containing all terms of the query, ranked by TF-IDF.
+fn search_documents(query: &str) -> CacheResult<Vec<(String, f32)>> {
+    let mut terms = tokenize(query);
+    terms.sort();
+    terms.dedup();
+    if terms.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let total_docs = INDEX.scan_prefix(DOC_PREFIX).count().max(1) as f32;
+    let mut scores: Option<HashMap<String, f32>> = None;
+
+    for term in terms {
+        let mut freqs = HashMap::<String, u32>::new();
+        for r in INDEX.scan_prefix(format!("{}{}", TERM_PREFIX, term)) {
+            let (key, value) = r?;
+            let key = String::from_utf8_lossy(&key);
+            let Some((_, doc)) = key.split_once('\0') else {
+                continue;
+            };
+            let freq = value
+                .as_slice()
+                .try_into()
+                .map(u32::from_be_bytes)
+                .unwrap_or(1);
+            *freqs.entry(doc.to_owned()).or_default() += freq;
+        }
+
+        let idf = (1.0 + total_docs / freqs.len().max(1) as f32).ln();
+        let term_scores = freqs
+            .into_iter()
+            .map(|(doc, freq)| (doc, (1.0 + (freq as f32).ln()) * idf));
+
+        scores = Some(match scores {
+            None => term_scores.collect(),
+            Some(mut scores) => {
+                let term_scores = term_scores.collect::<HashMap<_, _>>();
+                scores.retain(|doc, _| term_scores.contains_key(doc));
+                scores
+                    .iter_mut()
+                    .for_each(|(doc, score)| *score += term_scores[doc]);
+                scores
+            }
+        });
+    }
+
+    let mut docs = scores
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(doc, score)| {
+            let boost = if doc.starts_with("topic/") {
+                TOPIC_BOOST
+            } else {
+                1.0
+            };
+            (doc, score * boost)
+        })
+        .collect::<Vec<_>>();
+    docs.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(docs)
+}
+
+fn cached_page(topic_id: &str, page: u32) -> Option<TopicDetailsResponse> {
+    let key = topic_details_response_key(&TopicDetailsRequest {
+        topic_id: topic_id.to_owned(),
+        page,
+        ..Default::default()
+    })?;
+    CACHE.get_msg::<TopicDetailsResponse>(&key).ok().flatten()
+}
+
+/// Load the hit of the document, or `None` if it's no longer cached.
+fn load_hit(doc: &str) -> Option<LocalSearchHit> {
+    let mut parts = doc.split('/');
+    let (topic_id, post) = match (parts.next()?, parts.next()?) {
+        ("topic", topic_id) => (topic_id, None),
+        ("post", topic_id) => {
+            let page = parts.next()?.parse().ok()?;
+            let pid = parts.next()?;
+            let post = cached_page(topic_id, page)?
+                .take_replies()
+                .into_iter()
+                .find(|p| p.get_id().get_pid() == pid)?;
+            (topic_id, Some(post))
+        }
+        _ => return None,
+    };
+
+    let topic = find_topic_history(topic_id).or_else(|| {
+        let topic = cached_page(topic_id, 1)?.take_topic();
+        Some(TopicSnapshot {
+            topic_snapshot: Some(topic).into(),
+            ..Default::default()
+        })
+    })?;
+
+    Some(LocalSearchHit {
+        topic: Some(topic).into(),
+        post: post.into(),
+        ..Default::default()
+    })
+}
+
+fn do_search_local(request: &LocalSearchRequest) -> ServiceResult<LocalSearchResponse> {
+    if request.get_rebuild_index() || INDEX.is_empty() {
+        rebuild_index()?;
+    }
+
+    let limit = match request.get_limit() {
+        0 => usize::MAX,
+        limit => limit as usize,
+    };
+
+    let mut hits = Vec::new();
+    for (doc, score) in search_documents(request.get_query())? {
+        if hits.len() >= limit {
+            break;
+        }
+        let Some(mut hit) = load_hit(&doc) else {
+            continue;
+        };
+        let topic = hit.get_topic().get_topic_snapshot().clone();
+        if block::filter_topics(vec![topic]).is_empty() {
+            continue;
+        }
+        if hit.has_post() {
+            block::mark_posts([hit.mut_post()]);
+            if hit.get_post().get_blocked() {
+                continue;
+            }
+        }
+        hit.set_score(score);
+        hits.push(hit);
+    }
+
+    Ok(LocalSearchResponse {
+        hits: hits.into(),
+        ..Default::default()
+    })
+}
+
+/// Search the local cache, which scans the index on the blocking thread pool.
+pub async fn search_local(request: LocalSearchRequest) -> ServiceResult<LocalSearchResponse> {
+    tokio::task::spawn_blocking(move || do_search_local(&request))
+        .await
+        .map_err(|e| ServiceError::Panic(e.to_string()))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fake_nga::FakeNga, topic::get_topic_details, utils::get_unique_id};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Hello, MNGA 2!"),
+            ["hello", "mnga", "2"].map(ToOwned::to_owned)
+        );
+        assert_eq!(
+            tokenize("全文搜索abc测"),
+            ["全文", "文搜", "搜索", "索", "abc", "测"].map(ToOwned::to_owned)
+        );
+    }
+
+    #[test]
+    fn test_spans_text() {
+        let content = text::parse_content(
+            "[quote]引用的内容[/quote]正文[s:a2:不明觉厉][img]./mon_202107/03/a.jpg[/img]",
+        );
+        let mut text = String::new();
+        spans_text(content.get_spans(), &mut text);
+        let terms = tokenize(&text);
+        for term in ["引用", "内容", "正文"] {
+            assert!(
+                terms.iter().any(|t| t == term),
+                "{} not in {:?}",
+                term,
+                terms
+            );
+        }
+        for term in ["image", "不明", "jpg"] {
+            assert!(!terms.iter().any(|t| t == term), "{} in {:?}", term, terms);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_local() -> ServiceResult<()> {
+        let server = FakeNga::start();
+        let marker = format!("marker{}", &get_unique_id().replace('-', "")[..16]);
+        let topic_id = {
+            let mut state = server.state();
+            let topic = &mut state.topics[0];
+            topic.subject = format!("Subject {}", marker);
+            topic.posts[1].content = format!("离线全文搜索 [b]{}[/b]", marker);
+            topic.tid.clone()
+        };
+        let fetch = || {
+            server.scope(get_topic_details(TopicDetailsRequest {
+                topic_id: topic_id.clone(),
+                page: 1,
+                ..Default::default()
+            }))
+        };
+        let search = |query: String| {
+            search_local(LocalSearchRequest {
+                query,
+                ..Default::default()
+            })
+        };
+
+        let reply_pid = fetch().await?.get_replies()[1]
+            .get_id()
+            .get_pid()
+            .to_owned();
+
+        // The topic is ranked higher than the post.
+        let hits = search(marker.clone()).await?.take_hits();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].get_topic().get_topic_snapshot().get_id(), topic_id);
+        assert!(!hits[0].has_post());
+        assert_eq!(hits[1].get_post().get_id().get_pid(), reply_pid);
+
+        // All terms must match, and single characters match as prefixes.
+        let hits = search(format!("搜 {}", marker.to_uppercase()))
+            .await?
+            .take_hits();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].has_post());
+        assert!(
+            search(format!("在线 {}", marker))
+                .await?
+                .get_hits()
+                .is_empty()
+        );
+
+        // Stale terms are dropped on reindexing.
+        server.state().topics[0].posts[1].content = "Edited".to_owned();
+        fetch().await?;
+        let hits = search(marker.clone()).await?.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert!(!hits[0].has_post());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+//! Throttling of the requests, since NGA blocks aggressive clients.
+
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use futures::{Future, channel::oneshot};
+use reqwest::StatusCode;
+
+use crate::{error::ServiceResult, request};
+
+const DEFAULT_RATE_LIMIT_PER_SEC: f32 = 5.0;
+const DEFAULT_RATE_LIMIT_BURST: u32 = 10;
+
+/// Token bucket of a host. Tokens may go negative, so that the waiting requests are queued up.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+
+    /// Take a token, and return how long to wait before it's available.
+    fn acquire(&mut self, rate: f64, burst: f64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+static BUCKETS: LazyLock<DashMap<String, TokenBucket>> = LazyLock::new(DashMap::new);
+
+/// Wait until a request to `host` is allowed by the rate limit in the request option.
+pub async fn acquire(host: &str) {
+    let option = request::request_option();
+    if option.get_disable_rate_limit() {
+        return;
+    }
+    let rate = match option.get_rate_limit_per_sec() {
+        r if r > 0.0 => r,
+        _ => DEFAULT_RATE_LIMIT_PER_SEC,
+    } as f64;
+    let burst = match option.get_rate_limit_burst() {
+        0 => DEFAULT_RATE_LIMIT_BURST,
+        b => b,
+    } as f64;
+
+    let now = Instant::now();
+    let wait = BUCKETS
+        .entry(host.to_owned())
+        .or_insert_with(|| TokenBucket::new(burst, now))
+        .acquire(rate, burst, now);
+
+    if !wait.is_zero() {
+        log::info!("rate limited, wait {:?} for {}", wait, host);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+type Waiters = Vec<oneshot::Sender<(StatusCode, String)>>;
+
+static IN_FLIGHT: LazyLock<DashMap<String, Waiters>> = LazyLock::new(DashMap::new);
+
+/// Unregister the in-flight request if it's dropped before finishing, so that the waiters will
+/// fetch by themselves.
+struct InFlightGuard<'a>(&'a str);
+
+impl InFlightGuard<'_> {
+    fn finish(self) -> Waiters {
+        let waiters = IN_FLIGHT.remove(self.0).map(|(_, w)| w).unwrap_or_default();
+        std::mem::forget(self);
+        waiters
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        IN_FLIGHT.remove(self.0);
+    }
+}
+
+/// Share the response of `fetch` among the identical requests in flight with the same `key`, which
+/// should cover everything that affects the response, including the auth info.
+///
+/// Only successful responses are shared since errors are not cloneable. If the first request
+/// fails, the others will fetch by themselves.
+pub async fn coalesce<F, Fut>(key: Option<String>, fetch: F) -> ServiceResult<(StatusCode, String)>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ServiceResult<(StatusCode, String)>>,
+{
+    let key = match key {
+        Some(key) if !request::request_option().get_disable_coalescing() => key,
+        _ => return fetch().await,
+    };
+
+    let rx = match IN_FLIGHT.entry(key.clone()) {
+        Entry::Occupied(mut entry) => {
+            let (tx, rx) = oneshot::channel();
+            entry.get_mut().push(tx);
+            Some(rx)
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(vec![]);
+            None
+        }
+    };
+
+    if let Some(rx) = rx {
+        log::info!("coalesced with the request in flight: {}", key);
+        return match rx.await {
+            Ok(response) => Ok(response),
+            Err(_) => fetch().await,
+        };
+    }
+
+    let guard = InFlightGuard(&key);
+    let result = fetch().await;
+    let waiters = guard.finish();
+    if let Ok(response) = &result {
+        for waiter in waiters {
+            let _ = waiter.send(response.clone());
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ServiceError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+
+        assert_eq!(bucket.acquire(4.0, 2.0, start), Duration::ZERO);
+        assert_eq!(bucket.acquire(4.0, 2.0, start), Duration::ZERO);
+        // Queued up after the bucket is drained.
+        assert_eq!(bucket.acquire(4.0, 2.0, start), Duration::from_millis(250));
+        assert_eq!(bucket.acquire(4.0, 2.0, start), Duration::from_millis(500));
+
+        // Refilled, but never more than the burst.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.acquire(4.0, 2.0, later), Duration::ZERO);
+        assert_eq!(bucket.acquire(4.0, 2.0, later), Duration::ZERO);
+        assert_eq!(bucket.acquire(4.0, 2.0, later), Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let count = AtomicUsize::new(0);
+        let fetch = |ok: bool| {
+            let count = &count;
+            move || async move {
+                count.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if ok {
+                    Ok((StatusCode::OK, "response".to_owned()))
+                } else {
+                    Err(ServiceError::MngaInternal("failed".to_owned()))
+                }
+            }
+        };
+
+        let key = || Some("test_coalesce".to_owned());
+        let (a, b) = tokio::join!(coalesce(key(), fetch(true)), coalesce(key(), fetch(true)));
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+
+        // Not coalesced without a key.
+        let (a, b) = tokio::join!(coalesce(None, fetch(true)), coalesce(None, fetch(true)));
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 2);
+
+        // The follower fetches by itself if the first request fails.
+        let (a, b) = tokio::join!(coalesce(key(), fetch(false)), coalesce(key(), fetch(true)));
+        assert!(a.is_err() && b.is_ok());
+        assert_eq!(count.swap(0, Ordering::SeqCst), 2);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{
+    auth::user_prefix,
+    block,
     constants::FORUM_ICON_PATH,
     error::{ServiceError, ServiceResult},
     fetch::{
@@ -8,6 +10,7 @@ use crate::{
     forum::{extract_forum, make_fid, make_minimal_forum, make_stid},
     history::{find_topic_history, insert_topic_history},
     post::extract_post,
+    progress, search,
     user::{extract_local_user_and_cache, extract_user_name},
     utils::{
         extract_kv, extract_kv_pairs, extract_node, extract_node_rel, extract_nodes, extract_pages,
@@ -16,23 +19,27 @@ use crate::{
 };
 use cache::{CACHE, CacheResult};
 use chrono::Duration;
-use futures::TryFutureExt;
+use futures::{FutureExt, TryFutureExt};
 use protos::{DataModel::*, MockRequest, Service::*, ToValue};
 use serde_json::Value;
-use std::cmp::Reverse;
+use std::{
+    cmp::Reverse,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use sxd_document::Package;
 use sxd_xpath::nodeset::Node;
 
 #[cfg(test)]
 mod parity_tests;
 mod web_to_xml;
 
-pub static FAVOR_RESPONSE_PREFIX: &str = "/favor_response/topic";
+pub static FAVOR_RESPONSE_PREFIX: &str = "/favor_response";
 fn favor_response_key(topic_id: &str) -> String {
-    format!("{}/{}", FAVOR_RESPONSE_PREFIX, topic_id)
+    format!("{}/topic/{}", user_prefix(FAVOR_RESPONSE_PREFIX), topic_id)
 }
 
 pub static TOPIC_DETAILS_PREFIX: &str = "/topic_details_response/topic";
-fn topic_details_response_key(request: &TopicDetailsRequest) -> Option<String> {
+pub fn topic_details_response_key(request: &TopicDetailsRequest) -> Option<String> {
     if request.get_post_id().is_empty()
         && request.get_author_id().is_empty()
         && !request.get_anonymous_author_only()
@@ -299,14 +306,17 @@ fn mutate_favor_response(folder_id: &str, op: FavorOp, r: &mut TopicFavorRespons
 
 /// Add or remove the folder from the cached response for specific topic.
 /// Create an entry if not exists.
-fn update_topic_cached_favor_response(
+async fn update_topic_cached_favor_response(
     topic_id: &str,
     folder_id: &str,
     op: FavorOp,
 ) -> CacheResult<TopicFavorResponse> {
-    CACHE.mutate_msg_or_default(&favor_response_key(topic_id), |r| {
-        mutate_favor_response(folder_id, op, r)
-    })
+    let folder_id = folder_id.to_owned();
+    CACHE
+        .mutate_msg_or_default_async(&favor_response_key(topic_id), move |r| {
+            mutate_favor_response(&folder_id, op, r)
+        })
+        .await
 }
 
 pub async fn get_favorite_topic_list(
@@ -315,28 +325,30 @@ pub async fn get_favorite_topic_list(
     let folder_id = request.get_folder_id();
     let page = request.page.to_string();
 
-    let package = fetch_package(
-        "thread.php",
-        vec![("favor", folder_id), ("page", page.as_str())],
-        vec![],
-    )
-    .await?;
+    let (topics, pages) = {
+        let package = fetch_package(
+            "thread.php",
+            vec![("favor", folder_id), ("page", page.as_str())],
+            vec![],
+        )
+        .await?;
 
-    let topics = extract_nodes(&package, "/root/__T/item", |ns| {
-        ns.into_iter()
-            .filter_map(|node| {
-                let topic = extract_topic(node);
-                // Update cache when browsing the favorite topic list.
-                if let Some(ref topic) = topic {
-                    let _ =
-                        update_topic_cached_favor_response(topic.get_id(), folder_id, FavorOp::Add);
-                }
-                topic
-            })
-            .collect()
-    })?;
+        let topics = extract_nodes(&package, "/root/__T/item", |ns| {
+            ns.into_iter().filter_map(extract_topic).collect::<Vec<_>>()
+        })?;
+        let pages = extract_pages(
+            &package,
+            "/root/__ROWS",
+            "/root/__T__ROWS_PAGE",
+            config::tuning().fallback_topic_rows_per_page,
+        )?;
+        (topics, pages)
+    };
 
-    let pages = extract_pages(&package, "/root/__ROWS", "/root/__T__ROWS_PAGE", 35)?;
+    // Update cache when browsing the favorite topic list.
+    for topic in &topics {
+        let _ = update_topic_cached_favor_response(topic.get_id(), folder_id, FavorOp::Add).await;
+    }
 
     Ok(FavoriteTopicListResponse {
         topics: topics.into(),
@@ -397,9 +409,14 @@ pub async fn modify_favorite_folder(
 
     // Update cache if folder is deleted.
     if let delete(_) = change {
-        CACHE.scan_mutate_msg(FAVOR_RESPONSE_PREFIX, |r| {
-            mutate_favor_response(folder_id, FavorOp::Remove, r)
-        })?;
+        // Folders are owned by the current user.
+        let prefix = format!("{}/", user_prefix(FAVOR_RESPONSE_PREFIX));
+        let folder_id = folder_id.to_owned();
+        CACHE
+            .scan_mutate_msg_async(&prefix, move |r| {
+                mutate_favor_response(&folder_id, FavorOp::Remove, r)
+            })
+            .await?;
     }
 
     Ok(FavoriteFolderModifyResponse::new())
@@ -454,8 +471,14 @@ pub async fn get_topic_list(request: TopicListRequest) -> ServiceResult<TopicLis
     let topics = extract_nodes(&package, "/root/__T/item", |ns| {
         ns.into_iter().filter_map(extract_topic).collect()
     })?;
+    let topics = block::filter_topics(topics);
 
-    let pages = extract_pages(&package, "/root/__ROWS", "/root/__T__ROWS_PAGE", 35)?;
+    let pages = extract_pages(
+        &package,
+        "/root/__ROWS",
+        "/root/__T__ROWS_PAGE",
+        config::tuning().fallback_topic_rows_per_page,
+    )?;
 
     let subforums = {
         let mut subforums = extract_nodes(&package, "/root/__F/sub_forums/*", |ns| {
@@ -511,6 +534,8 @@ pub async fn get_hot_topic_list(
     .timestamp() as u64;
     let limit = request.get_limit().max(30) as usize;
 
+    let total = fetch_page_limit as u64;
+    let done = AtomicU64::new(0);
     let futures = (1..=fetch_page_limit)
         .map(|page| {
             let request = TopicListRequest {
@@ -518,7 +543,20 @@ pub async fn get_hot_topic_list(
                 page,
                 ..Default::default()
             };
-            get_topic_list(request).map_ok(|r| (r.topics.into_vec(), r.forum))
+            get_topic_list(request)
+                .map_ok(|r| (r.topics.into_vec(), r.forum))
+                .inspect(|r| {
+                    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    let topics = (r.iter().flat_map(|p| &p.0))
+                        .filter(|t| t.get_post_date() > start_timestamp)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    let partial = HotTopicListResponse {
+                        topics: topics.into(),
+                        ..Default::default()
+                    };
+                    progress::report_partial(done, total, &partial);
+                })
         })
         .collect::<Vec<_>>();
     let responses = futures::future::join_all(futures).await;
@@ -565,8 +603,14 @@ pub async fn search_topic(request: TopicSearchRequest) -> ServiceResult<TopicSea
     let topics = extract_nodes(&package, "/root/__T/item", |ns| {
         ns.into_iter().filter_map(extract_topic).collect()
     })?;
+    let topics = block::filter_topics(topics);
 
-    let pages = extract_pages(&package, "/root/__ROWS", "/root/__T__ROWS_PAGE", 35)?;
+    let pages = extract_pages(
+        &package,
+        "/root/__ROWS",
+        "/root/__T__ROWS_PAGE",
+        config::tuning().fallback_topic_rows_per_page,
+    )?;
 
     Ok(TopicSearchResponse {
         topics: topics.into(),
@@ -575,36 +619,94 @@ pub async fn search_topic(request: TopicSearchRequest) -> ServiceResult<TopicSea
     })
 }
 
+fn extract_topic_details(
+    package: &Package,
+    request: &TopicDetailsRequest,
+    api_used: String,
+) -> ServiceResult<TopicDetailsResponse> {
+    let user_context = get_unique_id();
+    let _users = extract_nodes(package, "/root/__U/item", |ns| {
+        ns.into_iter()
+            .filter_map(|n| extract_local_user_and_cache(n, Some(&user_context)))
+            .collect()
+    })?;
+
+    let replies = extract_nodes(package, "/root/__R/item", |ns| {
+        ns.into_iter()
+            .filter_map(|n| extract_post(n, request.get_page(), &user_context))
+            .collect()
+    })?;
+
+    let mut topic = extract_node(package, "/root/__T", extract_topic)?
+        .flatten()
+        .ok_or_else(|| ServiceError::MissingField("topic".to_owned()))?;
+    topic.set_fav(request.get_fav().to_owned());
+
+    let forum_name = extract_string(package, "/root/__F/name")
+        .or_else(|_| extract_string(package, "/root/__F"))
+        .unwrap_or_default();
+
+    let pages = extract_pages(
+        package,
+        "/root/__ROWS",
+        "/root/__R__ROWS_PAGE",
+        config::tuning().fallback_post_rows_per_page,
+    )?;
+
+    Ok(TopicDetailsResponse {
+        topic: Some(topic).into(),
+        replies: replies.into(),
+        forum_name,
+        pages,
+        api_used,
+        ..Default::default()
+    })
+}
+
+/// Save the topic into history and the page into the cache, then index it for local search.
+async fn save_history(key: Option<&str>, response: &TopicDetailsResponse) {
+    insert_topic_history(response.get_topic().to_owned()).await;
+    if let Some(key) = key {
+        let _ = CACHE.insert_msg_async(key, response).await;
+        let response = response.clone();
+        let _ = CACHE
+            .run_blocking(move |_| {
+                search::index_topic_details(&response);
+                Ok(())
+            })
+            .await;
+    }
+}
+
 pub async fn get_topic_details(
     request: TopicDetailsRequest,
 ) -> ServiceResult<TopicDetailsResponse> {
     let key = topic_details_response_key(&request);
 
-    let get_local_cache = || {
-        key.as_ref()
-            .and_then(|key| CACHE.get_msg::<TopicDetailsResponse>(key).ok())
+    let get_local_cache = || async {
+        let cached = match key.as_ref() {
+            Some(key) => CACHE.get_msg_async::<TopicDetailsResponse>(key).await,
+            None => Ok(None),
+        };
+        cached
+            .ok()
             .flatten()
             .ok_or_else(|| ServiceError::MngaInternal("No local cache found".to_owned()))
             .map(|mut r| {
                 r.is_local_cache = true;
+                block::mark_posts(r.mut_replies());
                 r
             })
     };
 
     if request.get_local_cache() {
-        return get_local_cache();
+        return get_local_cache().await;
     }
 
-    let save_history = |response: &TopicDetailsResponse| {
-        insert_topic_history(response.get_topic().to_owned()); // save history
-        if let Some(key) = key.as_ref() {
-            let _ = CACHE.insert_msg(key, response);
-        }
-    };
-
     if request.is_mock() {
-        let response = fetch_mock(&request).await?;
-        save_history(&response);
+        let mut response: TopicDetailsResponse = fetch_mock(&request).await?;
+        save_history(key.as_deref(), &response).await;
+        block::mark_posts(response.mut_replies());
         return Ok(response);
     }
 
@@ -678,51 +780,23 @@ pub async fn get_topic_details(
         (response, api_used)
     };
 
-    if let Err(e @ ServiceError::Nga(_)) = package_result {
-        match get_local_cache() {
-            Ok(mut response) => {
-                response.set_local_reason(e.to_string());
-                return Ok(response);
-            }
-            Err(_) => return Err(e),
+    let response =
+        package_result.and_then(|package| extract_topic_details(&package, &request, api_used));
+    let mut response = match response {
+        Err(e @ ServiceError::Nga(_)) => {
+            return match get_local_cache().await {
+                Ok(mut response) => {
+                    response.set_local_reason(e.to_string());
+                    Ok(response)
+                }
+                Err(_) => Err(ServiceError::LocalCacheMissed(Box::new(e))),
+            };
         }
-    }
-    let package = package_result?;
-
-    let user_context = get_unique_id();
-    let _users = extract_nodes(&package, "/root/__U/item", |ns| {
-        ns.into_iter()
-            .filter_map(|n| extract_local_user_and_cache(n, Some(&user_context)))
-            .collect()
-    })?;
-
-    let replies = extract_nodes(&package, "/root/__R/item", |ns| {
-        ns.into_iter()
-            .filter_map(|n| extract_post(n, request.get_page(), &user_context))
-            .collect()
-    })?;
-
-    let mut topic = extract_node(&package, "/root/__T", extract_topic)?
-        .flatten()
-        .ok_or_else(|| ServiceError::MissingField("topic".to_owned()))?;
-    topic.set_fav(request.get_fav().to_owned());
-
-    let forum_name = extract_string(&package, "/root/__F/name")
-        .or_else(|_| extract_string(&package, "/root/__F"))
-        .unwrap_or_default();
-
-    let pages = extract_pages(&package, "/root/__ROWS", "/root/__R__ROWS_PAGE", 20)?;
-
-    let response = TopicDetailsResponse {
-        topic: Some(topic).into(),
-        replies: replies.into(),
-        forum_name,
-        pages,
-        api_used: api_used.to_owned(),
-        ..Default::default()
+        response => response?,
     };
 
-    save_history(&response);
+    save_history(key.as_deref(), &response).await;
+    block::mark_posts(response.mut_replies());
     Ok(response)
 }
 
@@ -740,7 +814,8 @@ pub async fn topic_favor(request: TopicFavorRequest) -> ServiceResult<TopicFavor
     )
     .await?;
 
-    let response = update_topic_cached_favor_response(request.get_topic_id(), folder_id, op)?;
+    let response =
+        update_topic_cached_favor_response(request.get_topic_id(), folder_id, op).await?;
 
     Ok(response)
 }
@@ -762,7 +837,12 @@ pub async fn get_user_topic_list(
         ns.into_iter().filter_map(extract_topic).collect()
     })?;
 
-    let pages = extract_pages(&package, "/root/__ROWS", "/root/__T__ROWS_PAGE", 35)?;
+    let pages = extract_pages(
+        &package,
+        "/root/__ROWS",
+        "/root/__T__ROWS_PAGE",
+        config::tuning().fallback_topic_rows_per_page,
+    )?;
 
     Ok(UserTopicListResponse {
         topics: topics.into(),
@@ -774,85 +854,94 @@ pub async fn get_user_topic_list(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{constants::REVIEW_UID, fetch::with_fetch_check, user::UserController};
+    use crate::{
+        constants::REVIEW_UID, fetch::with_fetch_check, fixture::with_fixtures,
+        user::UserController,
+    };
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_topic_list() -> ServiceResult<()> {
-        let id = make_fid("650".to_owned());
-        let response = get_topic_list(TopicListRequest {
-            id: id.into(),
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let id = make_fid("650".to_owned());
+            let response = get_topic_list(TopicListRequest {
+                id: id.into(),
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
-        println!("forum: {:#?}", response.get_forum());
+            println!("response: {:?}", response);
+            println!("forum: {:#?}", response.get_forum());
 
-        assert!(!response.get_topics().is_empty());
-        assert_eq!(response.get_forum().get_name(), "原神");
+            assert!(!response.get_topics().is_empty());
+            assert_eq!(response.get_forum().get_name(), "原神");
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_topic_list_with_shortcuts() -> ServiceResult<()> {
-        let id = make_fid("-447601".to_owned());
-        let response = get_topic_list(TopicListRequest {
-            id: id.into(),
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let id = make_fid("-447601".to_owned());
+            let response = get_topic_list(TopicListRequest {
+                id: id.into(),
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        for t in response.get_topics() {
-            if t.has_shortcut_forum() {
-                println!("shortcut: {:#?}", t);
-            }
-            if !t.get_subject().get_font_modifiers().is_empty() {
-                println!("subject font: {:#?}", t);
+            for t in response.get_topics() {
+                if t.has_shortcut_forum() {
+                    println!("shortcut: {:#?}", t);
+                }
+                if !t.get_subject().get_font_modifiers().is_empty() {
+                    println!("subject font: {:#?}", t);
+                }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_topic_details_new() -> ServiceResult<()> {
-        let response = get_topic_details(TopicDetailsRequest {
-            topic_id: "45094055".to_owned(),
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
-
-        assert!(!response.get_topic().get_id().is_empty());
-        assert!(!response.get_replies().is_empty());
+        with_fixtures(async {
+            let response = get_topic_details(TopicDetailsRequest {
+                topic_id: "45094055".to_owned(),
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        assert!(
-            response
-                .get_replies()
-                .first()
-                .unwrap()
-                .get_content()
-                .get_raw()
-                .contains("测量")
-        );
-        assert!(
-            response
-                .get_topic()
-                .get_subject()
-                .get_content()
-                .contains("拿捏")
-        );
+            assert!(!response.get_topic().get_id().is_empty());
+            assert!(!response.get_replies().is_empty());
+
+            assert!(
+                response
+                    .get_replies()
+                    .first()
+                    .unwrap()
+                    .get_content()
+                    .get_raw()
+                    .contains("测量")
+            );
+            assert!(
+                response
+                    .get_topic()
+                    .get_subject()
+                    .get_content()
+                    .contains("拿捏")
+            );
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
+    #[ignore = "manual: keeps the topics posted in the last day, which a recorded response can't follow"]
     #[tokio::test]
     async fn test_hot_topic_list() -> ServiceResult<()> {
         let id = make_fid("650".to_owned());
@@ -870,181 +959,197 @@ mod test {
         Ok(())
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_topic_favor() -> ServiceResult<()> {
-        use TopicFavorRequest_Operation::*;
-
-        let folder = get_favorite_folder_list(FavoriteFolderListRequest::new())
-            .await?
-            .folders
-            .first()
-            .unwrap()
-            .clone();
-
-        let post = |op| {
-            with_fetch_check(
-                |c| assert!(c.contains("操作成功")),
-                topic_favor(TopicFavorRequest {
-                    topic_id: "27455825".to_owned(),
-                    operation: op,
-                    folder_id: folder.id.clone(),
-                    ..Default::default()
-                }),
-            )
-        };
+        with_fixtures(async {
+            use TopicFavorRequest_Operation::*;
+
+            let folder = get_favorite_folder_list(FavoriteFolderListRequest::new())
+                .await?
+                .folders
+                .first()
+                .unwrap()
+                .clone();
+
+            let post = |op| {
+                with_fetch_check(
+                    |c| assert!(c.contains("操作成功")),
+                    topic_favor(TopicFavorRequest {
+                        topic_id: "27455825".to_owned(),
+                        operation: op,
+                        folder_id: folder.id.clone(),
+                        ..Default::default()
+                    }),
+                )
+            };
 
-        post(ADD).await?;
-        post(DELETE).await?;
+            post(ADD).await?;
+            post(DELETE).await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_favor_folder_list() -> ServiceResult<()> {
-        let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
+        with_fixtures(async {
+            let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        let folders = response.get_folders();
-        assert!(!folders.is_empty());
-        let _default_folder = folders.iter().find(|f| f.is_default).unwrap();
+            let folders = response.get_folders();
+            assert!(!folders.is_empty());
+            let _default_folder = folders.iter().find(|f| f.is_default).unwrap();
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_create_delete_favor_folder() -> ServiceResult<()> {
-        let response = create_favorite_folder(FavoriteFolderCreateRequest {
-            name: "test".to_owned(),
-            set_default: false,
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = create_favorite_folder(FavoriteFolderCreateRequest {
+                name: "test".to_owned(),
+                set_default: false,
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        let new_folder_id = response.get_folder_id();
+            let new_folder_id = response.get_folder_id();
 
-        let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
-        let folders = response.get_folders();
-        let folder = folders.iter().find(|f| f.id == new_folder_id).unwrap();
-        assert_eq!(folder.name, "test");
-        assert!(!folder.is_default);
+            let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
+            let folders = response.get_folders();
+            let folder = folders.iter().find(|f| f.id == new_folder_id).unwrap();
+            assert_eq!(folder.name, "test");
+            assert!(!folder.is_default);
 
-        let _response = modify_favorite_folder(FavoriteFolderModifyRequest {
-            folder_id: new_folder_id.to_owned(),
-            change: Some(FavoriteFolderModifyRequest_oneof_change::delete(true)),
-            ..Default::default()
-        })
-        .await?;
+            let _response = modify_favorite_folder(FavoriteFolderModifyRequest {
+                folder_id: new_folder_id.to_owned(),
+                change: Some(FavoriteFolderModifyRequest_oneof_change::delete(true)),
+                ..Default::default()
+            })
+            .await?;
 
-        let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
-        let folders = response.get_folders();
-        assert!(folders.iter().all(|f| f.id != new_folder_id));
+            let response = get_favorite_folder_list(FavoriteFolderListRequest::new()).await?;
+            let folders = response.get_folders();
+            assert!(folders.iter().all(|f| f.id != new_folder_id));
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_specific_post() -> ServiceResult<()> {
-        let response = get_topic_details(TopicDetailsRequest {
-            post_id: "531589220".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = get_topic_details(TopicDetailsRequest {
+                post_id: "531589220".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        assert_eq!(response.get_replies().len(), 1);
-        assert!(
-            response
-                .get_replies()
-                .first()
-                .unwrap()
-                .get_content()
-                .get_raw()
-                .contains("BOLD")
-        );
+            assert_eq!(response.get_replies().len(), 1);
+            assert!(
+                response
+                    .get_replies()
+                    .first()
+                    .unwrap()
+                    .get_content()
+                    .get_raw()
+                    .contains("BOLD")
+            );
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_author_only() -> ServiceResult<()> {
-        // https://ngabbs.com/read.php?tid=28454798&authorid=62765271
-        let author_id = "62765271";
+        with_fixtures(async {
+            // https://ngabbs.com/read.php?tid=28454798&authorid=62765271
+            let author_id = "62765271";
 
-        let response = get_topic_details(TopicDetailsRequest {
-            topic_id: "28454798".to_owned(),
-            author_id: author_id.to_owned(),
-            page: 1,
-            ..Default::default()
-        })
-        .await?;
+            let response = get_topic_details(TopicDetailsRequest {
+                topic_id: "28454798".to_owned(),
+                author_id: author_id.to_owned(),
+                page: 1,
+                ..Default::default()
+            })
+            .await?;
 
-        assert!(
-            response
-                .get_replies()
-                .iter()
-                .all(|p| p.author_id == author_id)
-        );
+            assert!(
+                response
+                    .get_replies()
+                    .iter()
+                    .all(|p| p.author_id == author_id)
+            );
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_search_topic() -> ServiceResult<()> {
-        let id = make_fid("650".to_owned());
-        let response = search_topic(TopicSearchRequest {
-            id: id.into(),
-            page: 1,
-            search_content: true,
-            key: "钟离".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let id = make_fid("650".to_owned());
+            let response = search_topic(TopicSearchRequest {
+                id: id.into(),
+                page: 1,
+                search_content: true,
+                key: "钟离".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        assert!(!response.get_topics().is_empty());
+            assert!(!response.get_topics().is_empty());
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_get_user_no_topic_not_err() -> ServiceResult<()> {
-        let request = UserTopicListRequest {
-            author_id: REVIEW_UID.to_owned(),
-            page: 1,
-            ..Default::default()
-        };
-        let _ = get_user_topic_list(request).await?;
+        with_fixtures(async {
+            let request = UserTopicListRequest {
+                author_id: REVIEW_UID.to_owned(),
+                page: 1,
+                ..Default::default()
+            };
+            let _ = get_user_topic_list(request).await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_forum_name() -> ServiceResult<()> {
-        let cases = [("29094948", "手机研究所"), ("29100260", "原神")];
+        with_fixtures(async {
+            let cases = [("29094948", "手机研究所"), ("29100260", "原神")];
 
-        for (id, name) in cases {
-            let response = get_topic_details(TopicDetailsRequest {
-                topic_id: id.to_owned(),
-                page: 1,
-                ..Default::default()
-            })
-            .await?;
+            for (id, name) in cases {
+                let response = get_topic_details(TopicDetailsRequest {
+                    topic_id: id.to_owned(),
+                    page: 1,
+                    ..Default::default()
+                })
+                .await?;
 
-            assert_eq!(response.forum_name, name);
-        }
+                assert_eq!(response.forum_name, name);
+            }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
     #[test]
@@ -1091,34 +1196,36 @@ mod test {
         Ok(())
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_anonymous_names() -> ServiceResult<()> {
-        for page in [1, 2] {
-            let response = get_topic_details(TopicDetailsRequest {
-                topic_id: "17169610".to_owned(),
-                page,
-                ..Default::default()
-            })
-            .await?;
-
-            let anony_ids = response
-                .replies
-                .into_iter()
-                .map(|p| p.author_id)
-                .filter(|id| id.contains(','))
-                .collect::<Vec<_>>();
-
-            assert!(!anony_ids.is_empty());
-
-            for id in anony_ids {
-                let user = UserController::get().get_by_id(&id).unwrap();
-                let anony_name = user.get_name().get_anonymous();
-                dbg!(&id, anony_name);
-                assert_eq!(anony_name.chars().count(), 6);
+        with_fixtures(async {
+            for page in [1, 2] {
+                let response = get_topic_details(TopicDetailsRequest {
+                    topic_id: "17169610".to_owned(),
+                    page,
+                    ..Default::default()
+                })
+                .await?;
+
+                let anony_ids = response
+                    .replies
+                    .into_iter()
+                    .map(|p| p.author_id)
+                    .filter(|id| id.contains(','))
+                    .collect::<Vec<_>>();
+
+                assert!(!anony_ids.is_empty());
+
+                for id in anony_ids {
+                    let user = UserController::get().get_by_id(&id).unwrap();
+                    let anony_name = user.get_name().get_anonymous();
+                    dbg!(&id, anony_name);
+                    assert_eq!(anony_name.chars().count(), 6);
+                }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 }
@@ -5,7 +5,7 @@ use protos::{
     Service::{TopicDetailsRequest, TopicDetailsRequest_WebApiStrategy},
 };
 
-use crate::topic::get_topic_details;
+use crate::{fixture::with_fixtures, topic::get_topic_details};
 
 async fn do_test(request: TopicDetailsRequest) {
     let xml = {
@@ -76,19 +76,18 @@ async fn do_test(request: TopicDetailsRequest) {
 }
 
 // PASSED
-#[ignore = "manual: requires network or mutable external state"]
 #[tokio::test]
 async fn test_first_page() {
-    do_test(TopicDetailsRequest {
+    with_fixtures(do_test(TopicDetailsRequest {
         topic_id: "45510130".to_owned(),
         page: 1,
         ..Default::default()
-    })
+    }))
     .await;
 }
 
 // FIXME: missing: post_date
-#[ignore = "manual: requires network or mutable external state"]
+#[ignore = "manual: known mismatch between the XML and the web page"]
 #[tokio::test]
 async fn test_subsequent_page() {
     do_test(TopicDetailsRequest {
@@ -100,7 +99,7 @@ async fn test_subsequent_page() {
 }
 
 // FIXME: incorrect: post_date, pages
-#[ignore = "manual: requires network or mutable external state"]
+#[ignore = "manual: known mismatch between the XML and the web page"]
 #[tokio::test]
 async fn test_specific_post() {
     do_test(TopicDetailsRequest {
@@ -111,7 +110,7 @@ async fn test_specific_post() {
 }
 
 // FIXME: incorrect: pages
-#[ignore = "manual: requires network or mutable external state"]
+#[ignore = "manual: known mismatch between the XML and the web page"]
 #[tokio::test]
 async fn test_author_only() {
     do_test(TopicDetailsRequest {
@@ -123,24 +122,22 @@ async fn test_author_only() {
 }
 
 // PASSED
-#[ignore = "manual: requires network or mutable external state"]
 #[tokio::test]
 async fn test_anonymous() {
-    do_test(TopicDetailsRequest {
+    with_fixtures(do_test(TopicDetailsRequest {
         topic_id: "17169610".to_owned(),
         page: 1,
         ..Default::default()
-    })
+    }))
     .await;
 }
 
 // PASSED
-#[ignore = "manual: requires network or mutable external state"]
 #[tokio::test]
 async fn test_error() {
-    do_test(TopicDetailsRequest {
+    with_fixtures(do_test(TopicDetailsRequest {
         topic_id: "1".to_owned(),
         ..Default::default()
-    })
+    }))
     .await;
 }
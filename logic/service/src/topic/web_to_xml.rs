@@ -170,7 +170,7 @@ pub fn build_topic_package(raw_html: &str, req_page: u32) -> ServiceResult<Packa
     let rows_per_page = if topic_defaults.rows_per_page != 0 {
         topic_defaults.rows_per_page
     } else {
-        page_meta.rows_per_page(20)
+        page_meta.rows_per_page(config::tuning().fallback_post_rows_per_page)
     };
     let subject = extract_topic_subject(&document, &posts);
     let forum_name = extract_forum_name(&document);
@@ -278,63 +278,72 @@ pub async fn update_signature(
 
 #[cfg(test)]
 mod test {
-    use crate::error::ServiceError;
+    use crate::{
+        cache::test::ENCRYPTION_TEST_LOCK, constants::REVIEW_UID, error::ServiceError,
+        fixture::with_fixtures,
+    };
 
     use super::*;
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_remote_user() -> ServiceResult<()> {
-        let response = get_remote_user(RemoteUserRequest {
-            user_id: "41417929".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = get_remote_user(RemoteUserRequest {
+                user_id: "41417929".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        assert!(response.has_user());
-        assert_eq!(response.get_user().get_name().get_normal(), "BugenZhao");
+            assert!(response.has_user());
+            assert_eq!(response.get_user().get_name().get_normal(), "BugenZhao");
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_remote_user_name() -> ServiceResult<()> {
-        let response = get_remote_user(RemoteUserRequest {
-            user_name: "MNGA-Review".to_owned(),
-            ..Default::default()
-        })
-        .await?;
+        with_fixtures(async {
+            let response = get_remote_user(RemoteUserRequest {
+                user_name: "MNGA-Review".to_owned(),
+                ..Default::default()
+            })
+            .await?;
 
-        println!("response: {:?}", response);
+            println!("response: {:?}", response);
 
-        assert!(response.has_user());
-        assert_eq!(response.get_user().get_id(), "63598535");
+            assert!(response.has_user());
+            assert_eq!(response.get_user().get_id(), "63598535");
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_remote_user_not_found_error() -> ServiceResult<()> {
-        let err = get_remote_user(RemoteUserRequest {
-            user_id: "999999999999999999".to_owned(),
-            ..Default::default()
-        })
-        .await
-        .unwrap_err();
+        with_fixtures(async {
+            let err = get_remote_user(RemoteUserRequest {
+                user_id: "999999999999999999".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
 
-        match err {
-            ServiceError::Nga(e) => {
-                assert_eq!(e.code, "?");
-                assert_eq!(e.info, "找不到用户");
+            match err {
+                ServiceError::Nga(e) => {
+                    assert_eq!(e.code, "?");
+                    assert_eq!(e.info, "找不到用户");
+                }
+                other => panic!("unexpected error: {other:?}"),
             }
-            other => panic!("unexpected error: {other:?}"),
-        }
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
     #[test]
@@ -363,9 +372,21 @@ mod test {
         assert_eq!(user.get_name().get_anonymous(), "壬宫窦丁钱甄");
     }
 
-    #[ignore = "manual: requires network or mutable external state"]
     #[tokio::test]
     async fn test_update_signature() -> ServiceResult<()> {
+        // Other tests keep their user-namespaced entries under the current uid.
+        let _guard = ENCRYPTION_TEST_LOCK.lock().await;
+        let auth_info = auth::AUTH_INFO.read().unwrap().clone();
+        auth::AUTH_INFO
+            .write()
+            .unwrap()
+            .set_uid(REVIEW_UID.to_owned());
+        let result = with_fixtures(update_and_revert_signature()).await;
+        *auth::AUTH_INFO.write().unwrap() = auth_info;
+        result
+    }
+
+    async fn update_and_revert_signature() -> ServiceResult<()> {
         async fn get_signature() -> ServiceResult<String> {
             Ok(get_remote_user(RemoteUserRequest {
                 user_id: auth::current_uid(),
@@ -0,0 +1,114 @@
+use protos::DataModel::{Span, Span_Tagged, Span_oneof_value};
+
+/// Tag of the cells of a table with given width, like `[td20]...[/td]`.
+fn close_tag(tag: &str) -> &str {
+    if tag.starts_with("td") { "td" } else { tag }
+}
+
+fn write_tagged(tagged: &Span_Tagged, out: &mut String) {
+    let tag = tagged.get_tag();
+    let spans = tagged.get_spans();
+
+    match tag {
+        "_divider" if spans.is_empty() => out.push_str("======"),
+        "_divider" => {
+            out.push_str("===");
+            write_spans(spans, out);
+            out.push_str("===");
+        }
+        "at" => {
+            let user = tagged.get_attributes().first().map(String::as_str);
+            out.push_str("[@");
+            out.push_str(user.unwrap_or_default());
+            out.push(']');
+        }
+        // Other synthetic tags are unknown to NGA, only keep their content.
+        _ if tag.starts_with('_') => write_spans(spans, out),
+        _ => {
+            out.push('[');
+            out.push_str(tag);
+            if !tagged.get_attributes().is_empty() {
+                out.push('=');
+                out.push_str(&tagged.get_attributes().join(","));
+            }
+            for attribute in tagged.get_complex_attributes() {
+                out.push(' ');
+                out.push_str(attribute);
+            }
+            out.push(']');
+            write_spans(spans, out);
+            out.push_str("[/");
+            out.push_str(close_tag(tag));
+            out.push(']');
+        }
+    }
+}
+
+fn write_spans(spans: &[Span], out: &mut String) {
+    for span in spans {
+        match &span.value {
+            Some(Span_oneof_value::plain(plain)) => out.push_str(plain.get_text()),
+            Some(Span_oneof_value::break_line(_)) => out.push('\n'),
+            Some(Span_oneof_value::sticker(sticker)) => {
+                out.push_str("[s:");
+                out.push_str(sticker.get_name());
+                out.push(']');
+            }
+            Some(Span_oneof_value::tagged(tagged)) => write_tagged(tagged, out),
+            None => {}
+        }
+    }
+}
+
+/// Serialize the spans back to BBCode, which is the inverse of `parse_content`. Tags are always
+/// closed, and plain text is written as is. The result should still go through
+/// `escape_for_submit` before posting.
+pub fn to_bbcode(spans: &[Span]) -> String {
+    let mut out = String::new();
+    write_spans(spans, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_content;
+
+    fn assert_round_trip(text: &str) {
+        let content = parse_content(text);
+        assert!(content.error.is_empty(), "{}", content.error);
+        let spans = content.spans.into_vec();
+        let bbcode = to_bbcode(&spans);
+        println!("{}", bbcode);
+        assert_eq!(parse_content(&bbcode).spans.into_vec(), spans);
+    }
+
+    #[test]
+    fn test_to_bbcode() {
+        let text = "[quote][pid=527975334,27383949,1]Reply[/pid] [b]Post by [uid=2176512]雲天青[/uid] (2021-06-28 16:29):[/b]<br/><br/>Hello[/quote]\
+                    [s:a2:不明觉厉]<br/>[@BugenZhao]===Section===<br/>======";
+        let content = parse_content(text);
+        assert!(content.error.is_empty());
+        let spans = content.spans.into_vec();
+        assert_eq!(
+            to_bbcode(&spans),
+            "[quote][pid=527975334,27383949,1]Reply[/pid] [b]Post by [uid=2176512]雲天青[/uid] (2021-06-28 16:29):[/b]\n\nHello[/quote]\
+             [s:a2:不明觉厉]\n[@BugenZhao]===Section===\n======"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for text in [
+            "[quote][tid=27457209]Topic[/tid] [b]Post by [uid=63178347]肥宅肥皂[/uid]:[/b]<br/>[img]./mon_202107/03/-7Q2o-eeg[/quote]",
+            "[url=https://bbs.nga.cn/thread.php?fid=-8725919][b]小窗视界[/b][/url]",
+            "[table][tr][td20][b]版务公告[/b][/td][td rowspan=2 colspan=3][size=0]原神Logo[/size][/td][/tr][/table]",
+            "[@  BugenZhao ]<br/>[@ 41417929 ]",
+            "===[size=150%][color=blue]前言[/color][/size]===<br/>======",
+            "[collapse=Spoiler][del]Hidden[/del][/collapse][s:ac:羡慕]",
+            "[randomblock][style height 100% width 100%][stripbr][align=right]233[/align][/style][/randomblock]",
+        ] {
+            assert_round_trip(text);
+        }
+    }
+}
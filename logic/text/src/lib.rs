@@ -4,11 +4,15 @@ use subject::do_parse_subject;
 
 use crate::error::ParseError;
 
+mod bbcode;
 mod content;
 pub mod error;
 mod escape;
+mod plain;
 mod subject;
+pub use bbcode::to_bbcode;
 pub use escape::{escape_for_submit, unescape};
+pub use plain::{sticker_name, summarize, to_plain_text};
 
 pub fn parse_content(text: &str) -> PostContent {
     let text = unescape(text).replace('\n', "<br/>");
@@ -18,7 +22,7 @@ pub fn parse_content(text: &str) -> PostContent {
         Err(ParseError::Content(error)) => {
             let fallback_spans = vec![Span {
                 value: Some(Span_oneof_value::plain(Span_Plain {
-                    text: text.replace("<br/>", "\n"), // todo: extract plain text
+                    text: plain::strip_markup(&text),
                     ..Default::default()
                 })),
                 ..Default::default()
@@ -0,0 +1,245 @@
+use protos::DataModel::{Span, Span_Tagged, Span_oneof_value};
+
+const ELLIPSIS: char = '…';
+
+/// Human-readable name of a sticker, e.g. `不明觉厉` for `a2:不明觉厉`.
+pub fn sticker_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+struct PlainText {
+    out: String,
+    /// Whether to drop the quotes instead of collapsing them into single lines.
+    skip_quotes: bool,
+}
+
+impl PlainText {
+    fn inner(&self, spans: &[Span]) -> String {
+        let mut renderer = Self {
+            out: String::new(),
+            skip_quotes: self.skip_quotes,
+        };
+        renderer.spans(spans);
+        renderer.out
+    }
+
+    fn new_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    fn spans(&mut self, spans: &[Span]) {
+        for span in spans {
+            match &span.value {
+                Some(Span_oneof_value::plain(plain)) => self.out.push_str(plain.get_text()),
+                Some(Span_oneof_value::break_line(_)) => self.out.push('\n'),
+                Some(Span_oneof_value::sticker(sticker)) => {
+                    self.out.push('[');
+                    self.out.push_str(sticker_name(sticker.get_name()));
+                    self.out.push(']');
+                }
+                Some(Span_oneof_value::tagged(tagged)) => self.tagged(tagged),
+                None => {}
+            }
+        }
+    }
+
+    fn tagged(&mut self, tagged: &Span_Tagged) {
+        let spans = tagged.get_spans();
+        let attribute = tagged.get_attributes().first().map(String::as_str);
+
+        match tagged.get_tag() {
+            "quote" if self.skip_quotes => {}
+            "quote" => {
+                let inner = collapse_whitespace(&self.inner(spans));
+                self.new_line();
+                self.out.push_str("> ");
+                self.out.push_str(&inner);
+                self.out.push('\n');
+            }
+            "_divider" => {
+                let title = self.inner(spans);
+                self.new_line();
+                self.out.push_str(title.trim());
+                self.out.push('\n');
+            }
+            "img" | "noimg" | "album" => self.out.push_str("[Image]"),
+            "flash" => self.out.push_str("[Media]"),
+            "attach" => self.out.push_str("[Attachment]"),
+            "at" => {
+                self.out.push('@');
+                self.out.push_str(attribute.unwrap_or_default());
+            }
+            "url" => {
+                let inner = self.inner(spans);
+                if inner.trim().is_empty() {
+                    self.out.push_str(attribute.unwrap_or_default());
+                } else {
+                    self.out.push_str(&inner);
+                }
+            }
+            "tr" => {
+                self.new_line();
+                self.spans(spans);
+                self.new_line();
+            }
+            tag if tag.starts_with("td") => {
+                let inner = collapse_whitespace(&self.inner(spans));
+                self.out.push_str(&inner);
+                self.out.push(' ');
+            }
+            _ => self.spans(spans),
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Trim each line and merge consecutive empty lines.
+fn tidy_lines(text: &str) -> String {
+    let mut lines = Vec::<&str>::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Render the spans as plain text. Markup is dropped, stickers are resolved to their names,
+/// media are replaced with placeholders, and each quote is collapsed into a single line.
+pub fn to_plain_text(spans: &[Span]) -> String {
+    let mut renderer = PlainText {
+        out: String::new(),
+        skip_quotes: false,
+    };
+    renderer.spans(spans);
+    tidy_lines(&renderer.out)
+}
+
+/// Summarize the spans into a single line of at most `max_chars` characters. Quotes are
+/// dropped, since they're usually not what the post says.
+pub fn summarize(spans: &[Span], max_chars: usize) -> String {
+    let mut renderer = PlainText {
+        out: String::new(),
+        skip_quotes: true,
+    };
+    renderer.spans(spans);
+    truncate(&collapse_whitespace(&renderer.out), max_chars)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    } else if max_chars == 0 {
+        return String::new();
+    }
+    let mut truncated = text
+        .chars()
+        .take(max_chars.saturating_sub(1))
+        .collect::<String>();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+/// Best-effort plain text of BBCode that fails to parse: tags are dropped, while stickers and
+/// mentions are kept as in `to_plain_text`.
+pub fn strip_markup(text: &str) -> String {
+    fn is_tag(inner: &str) -> bool {
+        let name = inner.strip_prefix('/').unwrap_or(inner);
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars
+                .take_while(|c| !matches!(c, '=' | ' '))
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    let text = text.replace("<br/>", "\n");
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+
+    while let Some(start) = rest.find('[') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(']') else {
+            break;
+        };
+        let inner = &rest[1..end];
+        if let Some(name) = inner.strip_prefix("s:") {
+            out.push('[');
+            out.push_str(sticker_name(name));
+            out.push(']');
+        } else if let Some(user) = inner.strip_prefix('@') {
+            out.push('@');
+            out.push_str(user.trim());
+        } else if inner == "*" {
+            out.push_str("\n- ");
+        } else if !is_tag(inner) {
+            out.push_str(&rest[..=end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+
+    tidy_lines(&out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_content;
+
+    const TEXT: &str = "[quote][pid=527975334,27383949,1]Reply[/pid] [b]Post by [uid=2176512]雲天青[/uid] (2021-06-28 16:29):[/b]<br/><br/>假如那帖子是真实的[/quote]<br/><br/>\
+        公务员只要[b]脑子聪明[/b]会做题[s:a2:不明觉厉]<br/>[@BugenZhao] [img]./mon_202107/03/a.jpg[/img]<br/>\
+        ===总结===<br/>[url=https://bbs.nga.cn]NGA[/url] [url]https://example.com[/url]";
+
+    #[test]
+    fn test_to_plain_text() {
+        let content = parse_content(TEXT);
+        assert!(content.error.is_empty());
+        assert_eq!(
+            to_plain_text(&content.spans),
+            "> Reply Post by 雲天青 (2021-06-28 16:29): 假如那帖子是真实的\n\
+             \n\
+             公务员只要脑子聪明会做题[不明觉厉]\n\
+             @BugenZhao [Image]\n\
+             总结\n\
+             \n\
+             NGA https://example.com"
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let content = parse_content(TEXT);
+        assert_eq!(
+            summarize(&content.spans, 100),
+            "公务员只要脑子聪明会做题[不明觉厉] @BugenZhao [Image] 总结 NGA https://example.com"
+        );
+        assert_eq!(summarize(&content.spans, 8), "公务员只要脑子…");
+        assert_eq!(summarize(&content.spans, 0), "");
+
+        let content = parse_content("图[noimg]./mon_202107/03/a.jpg[/noimg]");
+        assert_eq!(summarize(&content.spans, 100), "图[Image]");
+    }
+
+    #[test]
+    fn test_strip_markup() {
+        let text =
+            "[quote][b]Post by[/b]<br/>引用[/quote][人]很多[s:ac:羡慕][list][*]一[*]二[/list]";
+        assert_eq!(
+            strip_markup(text),
+            "Post by\n引用[人]很多[羡慕]\n- 一\n- 二"
+        );
+    }
+}